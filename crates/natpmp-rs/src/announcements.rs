@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use socket2::Socket;
+use tokio::net::UdpSocket;
+
+use crate::binding::Binding;
+use crate::capture::{self, Direction};
+use crate::errors::{NATPMPError, RequestContext};
+use crate::protocol::Opcode;
+use crate::requests::external_address_request::ExternalAddressRequest;
+use crate::responses::{
+    ExternalAddressResponse, Response as _, epoch_regressed, parse_raw_response,
+};
+
+/// Gateways announce address changes to the all-hosts multicast group.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.2.1>
+pub const ANNOUNCEMENT_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+pub const ANNOUNCEMENT_PORT: u16 = 5350;
+
+/// An external address announcement, as sent by a gateway on start-up and whenever its external address changes.
+#[derive(Debug)]
+pub struct Announcement {
+    source: Ipv4Addr,
+    response: ExternalAddressResponse,
+}
+
+impl Announcement {
+    /// The gateway that sent the announcement
+    #[must_use]
+    pub fn source(&self) -> Ipv4Addr {
+        self.source
+    }
+
+    #[must_use]
+    pub fn response(&self) -> &ExternalAddressResponse {
+        &self.response
+    }
+}
+
+/// Listens for gateway announcements on `224.0.0.1:5350`.
+pub struct AnnouncementListener {
+    socket: UdpSocket,
+}
+
+impl AnnouncementListener {
+    /// Joins the announcement multicast group on `interface`, use `Ipv4Addr::UNSPECIFIED` to let the kernel pick one.
+    ///
+    /// The address is shared, so that multiple listeners on the same host all receive the announcements.
+    ///
+    /// # Errors
+    /// When the socket cannot be created, bound, or cannot join the multicast group
+    pub fn bind(interface: Ipv4Addr) -> Result<AnnouncementListener, NATPMPError> {
+        let socket = UdpSocket::from_std(listener_socket(interface, None)?)?;
+
+        Ok(AnnouncementListener { socket })
+    }
+
+    /// Like `bind()`, but listens where `binding` sends requests from: on its interface, in its network namespace,
+    /// and on the interface of its source address when `interface` is `Ipv4Addr::UNSPECIFIED`.
+    ///
+    /// # Errors
+    /// See `bind()`
+    pub async fn bind_with_binding(
+        interface: Ipv4Addr,
+        binding: &Binding,
+    ) -> Result<AnnouncementListener, NATPMPError> {
+        let interface = match (interface, binding.source()) {
+            (Ipv4Addr::UNSPECIFIED, Some(source)) => source,
+            (interface, _) => interface,
+        };
+        let device = binding.interface().map(str::to_owned);
+
+        let socket = binding
+            .in_network_namespace(move || listener_socket(interface, device.as_deref()))
+            .await?;
+
+        Ok(AnnouncementListener {
+            socket: UdpSocket::from_std(socket)?,
+        })
+    }
+
+    /// Also joins the announcement multicast group on `interface`, to hear the gateways of several networks over
+    /// one socket, and thus every announcement once.
+    ///
+    /// # Errors
+    /// When the socket cannot join the multicast group
+    pub fn join(&self, interface: Ipv4Addr) -> Result<(), NATPMPError> {
+        self.socket
+            .join_multicast_v4(ANNOUNCEMENT_MULTICAST_ADDRESS, interface)?;
+
+        Ok(())
+    }
+
+    /// Waits for the next announcement.
+    ///
+    /// Note that the source isn't checked, it is up to the caller to ignore announcements that don't come from their gateway.
+    ///
+    /// # Errors
+    /// When receiving fails, or when the packet isn't a valid announcement
+    pub async fn recv(&self) -> Result<Announcement, NATPMPError> {
+        let mut buffer = ExternalAddressResponse::get_buffer();
+
+        let (size, from) = self.socket.recv_from(&mut buffer).await?;
+
+        capture::record(
+            Direction::Received,
+            self.socket.local_addr().ok(),
+            from,
+            &buffer[..size],
+            None,
+        );
+
+        let std::net::SocketAddr::V4(from) = from else {
+            unreachable!("We're bound to an IPv4 address");
+        };
+
+        // announcements aren't requested, so there is no attempt
+        let response = parse_raw_response(
+            &ExternalAddressRequest::new(),
+            RequestContext::new(*from.ip(), Opcode::ExternalAddress, 0),
+            &buffer.freeze(),
+        )?;
+
+        Ok(Announcement {
+            source: *from.ip(),
+            response,
+        })
+    }
+}
+
+/// Creates a non-blocking socket that is bound to the announcement address and joined the multicast group on
+/// `interface`, and with `device`, only hears the announcements that arrive on that device.
+fn listener_socket(
+    interface: Ipv4Addr,
+    device: Option<&str>,
+) -> Result<std::net::UdpSocket, std::io::Error> {
+    let socket = Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+
+    if let Some(device) = device {
+        socket.bind_device(Some(device.as_bytes()))?;
+    }
+
+    // bind specifically to the multicast address, and not to `0.0.0.0`, so that we don't receive unicast traffic
+    // Source: https://www.rfc-editor.org/rfc/rfc6886#page-6:~:text=Clients%20should%20therefore%0A%20%20%20bind%20specifically%20to%20224.0.0.1%3A5350
+    socket.bind(&SocketAddrV4::new(ANNOUNCEMENT_MULTICAST_ADDRESS, ANNOUNCEMENT_PORT).into())?;
+    socket.join_multicast_v4(&ANNOUNCEMENT_MULTICAST_ADDRESS, &interface)?;
+
+    Ok(std::net::UdpSocket::from(socket))
+}
+
+/// Remembers the last external address and epoch every gateway told us, through an announcement or a response,
+/// to tell what changed with the next one.
+#[derive(Debug, Default)]
+pub struct ExternalAddressHistory {
+    /// The address, epoch, and when we received them
+    last: BTreeMap<Ipv4Addr, (Ipv4Addr, Duration, Instant)>,
+}
+
+impl ExternalAddressHistory {
+    #[must_use]
+    pub fn new() -> ExternalAddressHistory {
+        ExternalAddressHistory::default()
+    }
+
+    /// Remembers what `gateway` told us in `response`, and returns how it differs from what it told us before.
+    pub fn record(
+        &mut self,
+        gateway: Ipv4Addr,
+        response: &ExternalAddressResponse,
+    ) -> ExternalAddressChange {
+        let address = response.ipv4_address();
+        let epoch = response.gateway_epoch();
+        let received_at = response.received_at();
+
+        let previous = self.last.insert(gateway, (address, epoch, received_at));
+
+        ExternalAddressChange {
+            address,
+            epoch,
+            previous_address: previous.map(|(previous_address, _, _)| previous_address),
+            previous_epoch: previous.map(|(_, previous_epoch, _)| previous_epoch),
+            elapsed: previous.map_or(Duration::ZERO, |(_, _, previous_at)| {
+                received_at.saturating_duration_since(previous_at)
+            }),
+        }
+    }
+}
+
+/// An external address and epoch of a gateway, and the ones it told us before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternalAddressChange {
+    address: Ipv4Addr,
+    epoch: Duration,
+    previous_address: Option<Ipv4Addr>,
+    previous_epoch: Option<Duration>,
+    /// The time between the previous epoch and this one on our clock
+    elapsed: Duration,
+}
+
+impl ExternalAddressChange {
+    #[must_use]
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// The time since the gateway started, or lost its mappings
+    #[must_use]
+    pub fn epoch(&self) -> Duration {
+        self.epoch
+    }
+
+    /// `None` when the gateway didn't tell us before
+    #[must_use]
+    pub fn previous_address(&self) -> Option<Ipv4Addr> {
+        self.previous_address
+    }
+
+    #[must_use]
+    pub fn previous_epoch(&self) -> Option<Duration> {
+        self.previous_epoch
+    }
+
+    #[must_use]
+    pub fn address_changed(&self) -> bool {
+        self.previous_address
+            .is_some_and(|previous_address| previous_address != self.address)
+    }
+
+    /// Whether the epoch went backwards, or fell behind our clock, i.e. the gateway restarted and might have lost
+    /// our mappings, see `responses::epoch_regressed()`
+    #[must_use]
+    pub fn epoch_regressed(&self) -> bool {
+        self.previous_epoch
+            .is_some_and(|previous_epoch| epoch_regressed(previous_epoch, self.epoch, self.elapsed))
+    }
+}
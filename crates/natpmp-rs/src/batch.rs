@@ -0,0 +1,689 @@
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::{NonZeroU16, NonZeroUsize};
+
+use bytes::Buf as _;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use tracing::{Level, event};
+
+use crate::binding::Binding;
+use crate::capture::{self, Direction};
+use crate::errors::{NATPMPError, RequestContext};
+use crate::protocol::MappingProtocol;
+use crate::requests::PortRequest;
+use crate::requests::mapping_request::MappingRequest;
+use crate::requests::unmap_request::UnmapPortRequest;
+use crate::responses::{MappingResponse, Response as _, parse_raw_response};
+use crate::retry::{Attempts, RetryPolicy, Rfc6886};
+use crate::{NATPMP_PORT, build_socket, resolve_gateway, send_request, telemetry};
+
+/// One mapping of a batch.
+#[derive(Debug, Clone, Copy)]
+pub struct MappingEntry {
+    protocol: MappingProtocol,
+    internal_port: NonZeroU16,
+    external_port: Option<NonZeroU16>,
+}
+
+impl MappingEntry {
+    /// # Arguments
+    /// * `protocol` - `Protocol::TCP` or `Protocol::UDP`
+    /// * `internal_port` - the private port of the mapping requested
+    /// * `external_port` - the public port we'd like, the gateway picks one when `None`
+    #[must_use]
+    pub fn new(
+        protocol: MappingProtocol,
+        internal_port: NonZeroU16,
+        external_port: Option<NonZeroU16>,
+    ) -> MappingEntry {
+        MappingEntry {
+            protocol,
+            internal_port,
+            external_port,
+        }
+    }
+
+    #[must_use]
+    pub fn protocol(&self) -> MappingProtocol {
+        self.protocol
+    }
+
+    #[must_use]
+    pub fn internal_port(&self) -> NonZeroU16 {
+        self.internal_port
+    }
+
+    #[must_use]
+    pub fn external_port(&self) -> Option<NonZeroU16> {
+        self.external_port
+    }
+}
+
+/// How many requests of a batch are in flight at once, unless told otherwise.
+pub const DEFAULT_CONCURRENCY: NonZeroUsize = NonZeroUsize::new(16).unwrap();
+
+/// Maps all `entries` at once, or none of them.
+///
+/// The requests are sent concurrently over a single socket. When any of them fails, the mappings that
+/// did succeed or timed out are deleted again, and the first error is returned.
+///
+/// Returns the responses in the order of `entries`. Mapping a protocol and internal port more than once is a
+/// `NATPMPError::DuplicateEntry`, before anything is sent.
+///
+/// # Arguments
+/// * `entries` - the mappings to create
+/// * `lifetime` - the duration of the mappings in seconds, defaults to 7200, per specification.
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
+/// * `retry` - the number of times to retry the requests if unsuccessful, defaults to 9 as per specification.
+///
+/// # Errors
+///
+/// Described by the Error component of the Result
+pub async fn map_ports(
+    entries: &[MappingEntry],
+    lifetime: Option<u32>,
+    gateway_ip: Option<Ipv4Addr>,
+    retry: Option<u32>,
+) -> Result<Vec<MappingResponse>, NATPMPError> {
+    let concurrency = NonZeroUsize::new(entries.len()).unwrap_or(NonZeroUsize::MIN);
+
+    map_ports_bounded(entries, lifetime, gateway_ip, retry, concurrency).await
+}
+
+/// Like `map_ports()`, but with at most `concurrency` requests in flight at once.
+///
+/// Every time a request is answered, the next one is sent, until a mapping failed, after which only the requests
+/// already in flight are waited for.
+///
+/// # Errors
+///
+/// Described by the Error component of the Result
+pub async fn map_ports_bounded(
+    entries: &[MappingEntry],
+    lifetime: Option<u32>,
+    gateway_ip: Option<Ipv4Addr>,
+    retry: Option<u32>,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<MappingResponse>, NATPMPError> {
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+    map_entries(
+        entries,
+        lifetime.unwrap_or(7200),
+        gateway_ip,
+        &Rfc6886::from_retry(retry),
+        &Binding::new(),
+        concurrency,
+    )
+    .await
+}
+
+pub(crate) async fn map_entries(
+    entries: &[MappingEntry],
+    lifetime: u32,
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<MappingResponse>, NATPMPError> {
+    // replies are matched to requests by protocol and internal port, the reply to one would be taken for the other's
+    if let Some(duplicate) = entries.iter().enumerate().find_map(|(position, entry)| {
+        entries[..position]
+            .iter()
+            .any(|earlier| {
+                earlier.protocol == entry.protocol && earlier.internal_port == entry.internal_port
+            })
+            .then_some(entry)
+    }) {
+        return Err(NATPMPError::DuplicateEntry {
+            protocol: duplicate.protocol,
+            internal_port: duplicate.internal_port,
+        });
+    }
+
+    let requests = entries
+        .iter()
+        .map(|entry| {
+            MappingRequest::new(
+                entry.protocol,
+                entry.internal_port,
+                entry.external_port.map_or(0, Into::into),
+                lifetime,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let results = match send_windowed(
+        gateway_ip,
+        &requests,
+        retry_policy,
+        binding,
+        concurrency,
+        Window::StopOnFailure,
+    )
+    .await
+    {
+        Ok(results) => results,
+        Err(Interrupted { error, progress }) => {
+            // like below, but only the requests that were sent, the others might be another process's mappings of
+            // the same internal ports
+            let created = entries
+                .iter()
+                .zip(progress.sent)
+                .zip(progress.results)
+                .filter(|&((_, sent), ref result)| {
+                    sent && matches!(
+                        result,
+                        None | Some(Ok(_) | Err(NATPMPError::TimedOut { .. }))
+                    )
+                })
+                .map(|((entry, _), _)| *entry)
+                .collect::<Vec<_>>();
+
+            roll_back(gateway_ip, &created, retry_policy, binding, concurrency).await;
+
+            return Err(error);
+        },
+    };
+
+    let mut mapped = Vec::with_capacity(entries.len());
+    // what to delete when a mapping fails, including the ones that timed out, as the gateway might have created
+    // them without us hearing back
+    let mut created = Vec::with_capacity(entries.len());
+    let mut first_error = None;
+
+    // the entries that weren't sent because another one failed first have no result
+    for (entry, result) in entries
+        .iter()
+        .zip(results)
+        .filter_map(|(entry, result)| Some((entry, result?)))
+    {
+        match result {
+            Ok(response) => {
+                created.push(*entry);
+                mapped.push(response);
+            },
+            Err(error) => {
+                if matches!(error, NATPMPError::TimedOut { .. }) {
+                    created.push(*entry);
+                }
+
+                first_error.get_or_insert(error);
+            },
+        }
+    }
+
+    let Some(error) = first_error else {
+        return Ok(mapped);
+    };
+
+    roll_back(gateway_ip, &created, retry_policy, binding, concurrency).await;
+
+    Err(error)
+}
+
+/// Renews `mappings`, requesting the external ports they were granted, with at most `concurrency`
+/// requests in flight at once.
+///
+/// Returns a result per mapping, in the order of `mappings`.
+///
+/// # Arguments
+/// * `mappings` - the mappings to renew
+/// * `lifetime` - the duration of the mappings in seconds, defaults to 7200, per specification.
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
+/// * `retry` - the number of times to retry the requests if unsuccessful, defaults to 9 as per specification.
+/// * `concurrency` - the number of requests in flight at once
+///
+/// # Errors
+///
+/// When the requests could not be sent at all
+pub async fn renew_ports(
+    mappings: &[MappingResponse],
+    lifetime: Option<u32>,
+    gateway_ip: Option<Ipv4Addr>,
+    retry: Option<u32>,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+    renew_mappings(
+        mappings,
+        lifetime.unwrap_or(7200),
+        gateway_ip,
+        &Rfc6886::from_retry(retry),
+        &Binding::new(),
+        concurrency,
+    )
+    .await
+}
+
+pub(crate) async fn renew_mappings(
+    mappings: &[MappingResponse],
+    lifetime: u32,
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+    let requests = mappings
+        .iter()
+        .map(|mapping| {
+            MappingRequest::new(
+                mapping.protocol(),
+                mapping.internal_port(),
+                mapping.external_port().map_or(0, NonZeroU16::get),
+                lifetime,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    send_all(gateway_ip, &requests, retry_policy, binding, concurrency).await
+}
+
+/// Deletes `mappings`, with at most `concurrency` requests in flight at once.
+///
+/// Returns a result per mapping, in the order of `mappings`.
+///
+/// # Arguments
+/// * `mappings` - the mappings to delete
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
+/// * `retry` - the number of times to retry the requests if unsuccessful, defaults to 9 as per specification.
+/// * `concurrency` - the number of requests in flight at once
+///
+/// # Errors
+///
+/// When the requests could not be sent at all
+pub async fn unmap_ports(
+    mappings: &[MappingResponse],
+    gateway_ip: Option<Ipv4Addr>,
+    retry: Option<u32>,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+    unmap_mappings(
+        mappings,
+        gateway_ip,
+        &Rfc6886::from_retry(retry),
+        &Binding::new(),
+        concurrency,
+    )
+    .await
+}
+
+pub(crate) async fn unmap_mappings(
+    mappings: &[MappingResponse],
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+    let requests = mappings
+        .iter()
+        .map(|mapping| UnmapPortRequest::new(mapping.protocol(), mapping.internal_port()))
+        .collect::<Vec<_>>();
+
+    send_all(gateway_ip, &requests, retry_policy, binding, concurrency).await
+}
+
+/// Deletes `created`, failures are logged as the mapping that failed is the error we report. Deleting a mapping
+/// that doesn't exist is harmless.
+async fn roll_back(
+    gateway_ip: Ipv4Addr,
+    created: &[MappingEntry],
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+) {
+    let requests = created
+        .iter()
+        .map(|entry| UnmapPortRequest::new(entry.protocol, entry.internal_port))
+        .collect::<Vec<_>>();
+
+    match send_all(gateway_ip, &requests, retry_policy, binding, concurrency).await {
+        Ok(results) => {
+            for (entry, result) in created.iter().zip(results) {
+                if let Err(error) = result {
+                    event!(Level::ERROR, ?error, protocol = %entry.protocol, internal_port = entry.internal_port, "Failed to roll back mapping");
+                }
+            }
+        },
+        Err(error) => {
+            event!(Level::ERROR, ?error, "Failed to roll back mappings");
+        },
+    }
+}
+
+async fn send_all<R: PortRequest + zerocopy::Immutable + zerocopy::IntoBytes>(
+    gateway_ip: Ipv4Addr,
+    requests: &[R],
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+    let results = send_windowed(
+        gateway_ip,
+        requests,
+        retry_policy,
+        binding,
+        concurrency,
+        Window::SendAll,
+    )
+    .await
+    .map_err(|interrupted| interrupted.error)?;
+
+    // every request was sent, so every request has a result
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Whether to keep sending the remaining requests of a batch once one of them failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Window {
+    SendAll,
+    StopOnFailure,
+}
+
+/// How far a batch got.
+struct Progress {
+    /// Per request, its result, if any yet
+    results: Vec<Option<Result<MappingResponse, NATPMPError>>>,
+    /// Per request, whether it was sent
+    sent: Vec<bool>,
+}
+
+/// A batch that stopped before all its requests were answered or given up on, e.g. because the socket failed.
+struct Interrupted {
+    error: NATPMPError,
+    progress: Progress,
+}
+
+/// A request of a batch waiting for its response.
+struct InFlight<'policy> {
+    /// The position of the request in the batch
+    index: usize,
+    attempts: Attempts<'policy>,
+    /// The gateway, opcode and current attempt
+    context: RequestContext,
+    deadline: Instant,
+    sent_at: Instant,
+}
+
+/// Sends `requests` over one socket, keeping up to `concurrency` of them in flight: every time one is answered or
+/// given up on, the next one is sent. Each request is retransmitted following `retry_policy`, like a single request.
+///
+/// Returns a result per request, in the order of `requests`, or `None` for the requests that weren't sent because
+/// `window` is `Window::StopOnFailure` and another one failed first. When the batch is interrupted, it tells which
+/// requests were sent.
+async fn send_windowed<R: PortRequest + zerocopy::Immutable + zerocopy::IntoBytes>(
+    gateway_ip: Ipv4Addr,
+    requests: &[R],
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+    window: Window,
+) -> Result<Vec<Option<Result<MappingResponse, NATPMPError>>>, Interrupted> {
+    let mut progress = Progress {
+        results: requests.iter().map(|_| None).collect(),
+        sent: vec![false; requests.len()],
+    };
+
+    match send_window(
+        gateway_ip,
+        requests,
+        retry_policy,
+        binding,
+        concurrency,
+        window,
+        &mut progress,
+    )
+    .await
+    {
+        Ok(()) => Ok(progress.results),
+        Err(error) => Err(Interrupted { error, progress }),
+    }
+}
+
+/// See `send_windowed()`, keeps track of how far it got in `progress`.
+async fn send_window<R: PortRequest + zerocopy::Immutable + zerocopy::IntoBytes>(
+    gateway_ip: Ipv4Addr,
+    requests: &[R],
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    concurrency: NonZeroUsize,
+    window: Window,
+    progress: &mut Progress,
+) -> Result<(), NATPMPError> {
+    let socket = build_socket(binding).await?;
+    let local = capture::local_address(
+        &socket,
+        SocketAddr::from((gateway_ip, NATPMP_PORT)),
+        binding,
+    )
+    .await;
+
+    let mut in_flight = Vec::<InFlight<'_>>::with_capacity(concurrency.get());
+    let mut next = 0;
+    let mut failed = false;
+
+    loop {
+        while in_flight.len() < concurrency.get()
+            && next < requests.len()
+            && !(failed && window == Window::StopOnFailure)
+        {
+            let request = &requests[next];
+            let mut attempts = Attempts::new(retry_policy);
+
+            if let Some((tries, deadline)) = attempts.next() {
+                send(&socket, local, gateway_ip, request, tries).await?;
+                progress.sent[next] = true;
+                in_flight.push(InFlight {
+                    index: next,
+                    attempts,
+                    context: RequestContext::new(gateway_ip, request.opcode(), tries),
+                    deadline,
+                    sent_at: Instant::now(),
+                });
+            } else {
+                failed = true;
+                progress.results[next] = Some(Err(NATPMPError::TimedOut {
+                    context: RequestContext::new(gateway_ip, request.opcode(), attempts.made()),
+                }));
+            }
+
+            next += 1;
+        }
+
+        let Some(&InFlight {
+            deadline, context, ..
+        }) = in_flight.iter().min_by_key(|flight| flight.deadline)
+        else {
+            break;
+        };
+
+        // zeroed every time, so that a short datagram doesn't leave parts of the previous one behind
+        let mut buffer = [0; MappingResponse::SIZE];
+
+        match tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+            Ok(Ok((size, from))) => {
+                // ignore responses that aren't from the gateway we sent the requests to
+                let position = (from.ip() == gateway_ip)
+                    .then(|| match_response(&buffer, requests, &in_flight))
+                    .flatten();
+
+                capture::record(
+                    Direction::Received,
+                    local,
+                    from,
+                    &buffer[..size],
+                    discard_reason(position, from, gateway_ip),
+                );
+
+                let Some(position) = position else {
+                    event!(
+                        Level::DEBUG,
+                        "Ignoring response that isn't from the gateway, or doesn't match any pending request"
+                    );
+
+                    continue;
+                };
+
+                let flight = in_flight.swap_remove(position);
+                let request = &requests[flight.index];
+
+                let response = parse_raw_response(request, flight.context, &buffer);
+
+                telemetry::record_response(
+                    gateway_ip,
+                    request.version(),
+                    request.opcode(),
+                    flight.sent_at.elapsed(),
+                    &response,
+                );
+
+                failed |= response.is_err();
+                progress.results[flight.index] = Some(response);
+            },
+            Ok(Err(error)) => {
+                if let Some(error) = receive_error(error, context) {
+                    return Err(error);
+                }
+            },
+            Err(_) => {
+                failed |= retransmit_expired(
+                    &socket,
+                    local,
+                    gateway_ip,
+                    requests,
+                    &mut in_flight,
+                    &mut progress.results,
+                )
+                .await?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// The error that ends a batch when receiving failed, or `None` when it can keep receiving.
+fn receive_error(error: io::Error, context: RequestContext) -> Option<NATPMPError> {
+    if error.kind() == ErrorKind::WouldBlock {
+        None
+    } else if error.kind() == ErrorKind::ConnectionRefused {
+        // ICMP Port Unreachable, nothing listens on the NAT-PMP port
+        Some(NATPMPError::Refused { context })
+    } else {
+        Some(NATPMPError::Network {
+            context: Some(context),
+            source: error,
+        })
+    }
+}
+
+/// Retransmits the requests in flight whose attempt timed out, or gives up on them when they ran out of attempts.
+///
+/// Returns whether any of them was given up on.
+async fn retransmit_expired<R: PortRequest + zerocopy::Immutable + zerocopy::IntoBytes>(
+    socket: &UdpSocket,
+    local: Option<SocketAddr>,
+    gateway_ip: Ipv4Addr,
+    requests: &[R],
+    in_flight: &mut Vec<InFlight<'_>>,
+    results: &mut [Option<Result<MappingResponse, NATPMPError>>],
+) -> Result<bool, NATPMPError> {
+    let now = Instant::now();
+    let mut gave_up = false;
+    let mut position = 0;
+
+    while let Some(flight) = in_flight.get_mut(position) {
+        if flight.deadline > now {
+            position += 1;
+
+            continue;
+        }
+
+        let request = &requests[flight.index];
+
+        telemetry::record_timeout(request.version(), request.opcode());
+
+        event!(
+            Level::WARN,
+            "Connection timed out, try {}",
+            flight.context.attempt()
+        );
+
+        if let Some((tries, deadline)) = flight.attempts.next() {
+            send(socket, local, gateway_ip, request, tries).await?;
+
+            flight.context = RequestContext::new(gateway_ip, request.opcode(), tries);
+            flight.deadline = deadline;
+            flight.sent_at = Instant::now();
+            position += 1;
+        } else {
+            let flight = in_flight.swap_remove(position);
+
+            gave_up = true;
+            results[flight.index] = Some(Err(NATPMPError::TimedOut {
+                context: RequestContext::new(gateway_ip, request.opcode(), flight.attempts.made()),
+            }));
+        }
+    }
+
+    Ok(gave_up)
+}
+
+async fn send<R: PortRequest + zerocopy::Immutable + zerocopy::IntoBytes>(
+    socket: &UdpSocket,
+    local: Option<SocketAddr>,
+    gateway_ip: Ipv4Addr,
+    request: &R,
+    tries: u32,
+) -> Result<(), NATPMPError> {
+    let _size = send_request(socket, gateway_ip, request.as_bytes(), local)
+        .await
+        .map_err(|source| NATPMPError::Network {
+            context: Some(RequestContext::new(gateway_ip, request.opcode(), tries)),
+            source,
+        })?;
+
+    telemetry::record_sent(request.version(), request.opcode(), tries);
+
+    Ok(())
+}
+
+/// Why a received datagram was ignored, for the capture, `position` is the request in flight it answers.
+fn discard_reason(
+    position: Option<usize>,
+    from: SocketAddr,
+    gateway_ip: Ipv4Addr,
+) -> Option<&'static str> {
+    match position {
+        Some(_) => None,
+        None if from.ip() != gateway_ip => Some("not from the gateway"),
+        None => Some("doesn't match any pending request"),
+    }
+}
+
+/// Finds the request in flight a response belongs to, by its opcode and internal port.
+fn match_response<R: PortRequest>(
+    mut buffer: &[u8],
+    requests: &[R],
+    in_flight: &[InFlight<'_>],
+) -> Option<usize> {
+    // version
+    buffer.advance(1);
+
+    let opcode = buffer.get_u8() & 0x7f;
+
+    // result code and seconds since epoch
+    buffer.advance(6);
+
+    let internal_port = buffer.get_u16();
+
+    in_flight.iter().position(|flight| {
+        let request = &requests[flight.index];
+
+        u8::from(request.opcode()) == opcode && request.internal_port() == internal_port
+    })
+}
@@ -1,20 +1,180 @@
+//! Listens for the external address announcements NAT-PMP gateways send to `224.0.0.1:5350`, and prints every one
+//! of them, highlighting gateways whose address changed or whose epoch regressed.
+
 use std::net::Ipv4Addr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Parser as _;
+use color_eyre::eyre;
+use natpmp_rs::announcements::{
+    ANNOUNCEMENT_MULTICAST_ADDRESS, ANNOUNCEMENT_PORT, AnnouncementListener, ExternalAddressChange,
+    ExternalAddressHistory,
+};
+use nix::ifaddrs::getifaddrs;
+use nix::sys::socket::SockaddrIn;
+use serde_json::json;
+use tracing::{Level, event};
+
+#[derive(clap::Parser)]
+#[command(version, about = "Monitor NAT-PMP gateway announcements")]
+struct Args {
+    /// Listen on this interface, a name like `eth0` or one of its addresses, can be repeated. The kernel picks one
+    /// when omitted
+    #[arg(long, value_name = "NAME|IP")]
+    interface: Vec<String>,
+
+    /// Print a JSON object per line instead of log lines, for log shipping. Logs go to stderr
+    #[arg(long)]
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), eyre::Report> {
+    let args = Args::parse();
+
+    color_eyre::install()?;
+
+    if args.json {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .try_init()
+            .map_err(|error| eyre::eyre!(error))?;
+    } else {
+        tracing_subscriber::fmt()
+            .try_init()
+            .map_err(|error| eyre::eyre!(error))?;
+    }
+
+    let mut interfaces = Vec::<Ipv4Addr>::new();
+
+    for interface in &args.interface {
+        let address = interface_address(interface)?;
+
+        // joining the group twice on the same interface fails
+        if !interfaces.contains(&address) {
+            interfaces.push(address);
+        }
+    }
+
+    let listener = match interfaces.split_first() {
+        Some((&first, rest)) => {
+            let listener = AnnouncementListener::bind(first)?;
+
+            for &interface in rest {
+                listener.join(interface)?;
+            }
 
-fn main() {
-    let ip: u32 = u32::from_str_radix("0128A8C0", 16).unwrap();
+            listener
+        },
+        None => AnnouncementListener::bind(Ipv4Addr::UNSPECIFIED)?,
+    };
 
-    let reversed = ip.swap_bytes();
+    event!(
+        Level::INFO,
+        ?interfaces,
+        "Listening on {}:{}",
+        ANNOUNCEMENT_MULTICAST_ADDRESS,
+        ANNOUNCEMENT_PORT
+    );
 
-    // // ip_in_hex.swap(0, 3);
-    // // ip_in_hex.swap(1, 2);
+    let mut history = ExternalAddressHistory::new();
 
-    // let reversed = [
-    //     (ip_in_hex) as u8,
-    //     (ip_in_hex >> 8) as u8,
-    //     (ip_in_hex >> 16) as u8,
-    //     (ip_in_hex >> 24) as u8,
-    // ];
+    loop {
+        match listener.recv().await {
+            Ok(announcement) => {
+                let change = history.record(announcement.source(), announcement.response());
+
+                if args.json {
+                    print_json(announcement.source(), &change);
+                } else {
+                    log(announcement.source(), &change);
+                }
+            },
+            Err(error) => match error.context() {
+                // the datagram arrived, but isn't a valid announcement
+                Some(context) => {
+                    if args.json {
+                        println!(
+                            "{}",
+                            json!({
+                                "time": now(),
+                                "source": context.gateway().to_string(),
+                                "error": error.to_string(),
+                            })
+                        );
+                    } else {
+                        event!(Level::WARN, source = %context.gateway(), %error, "Invalid announcement");
+                    }
+                },
+                None => return Err(error.into()),
+            },
+        }
+    }
+}
+
+/// `interface` if it's an address, otherwise the first IPv4 address of the interface called `interface`.
+fn interface_address(interface: &str) -> Result<Ipv4Addr, eyre::Report> {
+    if let Ok(address) = interface.parse() {
+        return Ok(address);
+    }
+
+    getifaddrs()?
+        .filter(|address| address.interface_name == interface)
+        .find_map(|address| address.address?.as_sockaddr_in().map(SockaddrIn::ip))
+        .ok_or_else(|| eyre::eyre!("Interface {} has no IPv4 address", interface))
+}
+
+fn log(source: Ipv4Addr, change: &ExternalAddressChange) {
+    let epoch = change.epoch().as_secs();
+
+    if change.epoch_regressed() {
+        event!(
+            Level::WARN,
+            %source,
+            epoch,
+            previous_epoch = change.previous_epoch().map(|previous_epoch| previous_epoch.as_secs()),
+            address = %change.address(),
+            "Epoch regressed, the gateway restarted and might have lost its mappings"
+        );
+    }
+
+    if let Some(previous_address) = change.previous_address()
+        && change.address_changed()
+    {
+        event!(
+            Level::WARN,
+            %source,
+            epoch,
+            address = %change.address(),
+            %previous_address,
+            "External address changed"
+        );
+    }
+
+    if !change.epoch_regressed() && !change.address_changed() {
+        event!(Level::INFO, %source, epoch, address = %change.address(), "Announcement");
+    }
+}
+
+fn print_json(source: Ipv4Addr, change: &ExternalAddressChange) {
+    println!(
+        "{}",
+        json!({
+            "time": now(),
+            "source": source.to_string(),
+            "epoch": change.epoch().as_secs(),
+            "address": change.address().to_string(),
+            "previous_epoch": change.previous_epoch().map(|previous_epoch| previous_epoch.as_secs()),
+            "previous_address": change.previous_address().map(|previous_address| previous_address.to_string()),
+            "epoch_regressed": change.epoch_regressed(),
+            "address_changed": change.address_changed(),
+        })
+    );
+}
 
-    let ip: Ipv4Addr = reversed.into();
-    println!("{}", ip);
+/// Seconds since the Unix epoch, with microseconds
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since_epoch| since_epoch.as_secs_f64())
 }
@@ -0,0 +1,281 @@
+//! Where our requests leave from: the source address, interface and network namespace of the socket.
+
+use std::fs::File;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use nix::sched::{CloneFlags, setns};
+use socket2::{Socket, Type};
+use tokio::sync::oneshot;
+
+/// How to set up the socket requests are sent from. By default the kernel picks the source address and
+/// interface from the routing table, which on multi-homed and policy-routed hosts might not be the one
+/// the gateway expects.
+///
+/// The sockets that a `Client` binds for the mapped traffic, see `Client::bind_public_tcp()`, and the announcement
+/// listener, see `AnnouncementListener::bind_with_binding()`, are set up the same way.
+#[derive(Debug, Clone, Default)]
+pub struct Binding {
+    source: Option<Ipv4Addr>,
+    interface: Option<String>,
+    network_namespace: Option<PathBuf>,
+    /// Shared by the clones of this binding, so that they all create their sockets on the same thread
+    namespace_thread: Option<Arc<NamespaceThread>>,
+}
+
+impl Binding {
+    #[must_use]
+    pub const fn new() -> Binding {
+        Binding {
+            source: None,
+            interface: None,
+            network_namespace: None,
+            namespace_thread: None,
+        }
+    }
+
+    /// Binds the socket to `source`, so that requests are sent from that address.
+    #[must_use]
+    pub fn with_source(mut self, source: Ipv4Addr) -> Binding {
+        self.source = Some(source);
+        self
+    }
+
+    /// Binds the socket to `interface` with `SO_BINDTODEVICE`, which needs `CAP_NET_RAW`.
+    #[must_use]
+    pub fn with_interface(mut self, interface: String) -> Binding {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Creates the socket in another network namespace, which needs `CAP_SYS_ADMIN`.
+    ///
+    /// Takes a name as created by `ip netns add`, or a path like `/proc/<pid>/ns/net`.
+    #[must_use]
+    pub fn with_network_namespace(mut self, network_namespace: &Path) -> Binding {
+        self.network_namespace = Some(if network_namespace.components().count() == 1 {
+            Path::new("/run/netns").join(network_namespace)
+        } else {
+            network_namespace.to_path_buf()
+        });
+        self.namespace_thread = Some(Arc::default());
+        self
+    }
+
+    #[must_use]
+    pub fn source(&self) -> Option<Ipv4Addr> {
+        self.source
+    }
+
+    #[must_use]
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.as_deref()
+    }
+
+    #[must_use]
+    pub fn network_namespace(&self) -> Option<&Path> {
+        self.network_namespace.as_deref()
+    }
+
+    /// A copy that doesn't keep the thread of the network namespace alive, to remember what the binding was.
+    pub(crate) fn detached(&self) -> Binding {
+        Binding {
+            namespace_thread: None,
+            ..self.clone()
+        }
+    }
+
+    /// Creates a non-blocking UDP socket on an ephemeral port to send requests from.
+    pub(crate) async fn socket(&self) -> Result<std::net::UdpSocket, io::Error> {
+        self.udp_socket(0).await
+    }
+
+    /// Creates a non-blocking UDP socket on `port` of the source address, or of all addresses without one.
+    pub(crate) async fn udp_socket(&self, port: u16) -> Result<std::net::UdpSocket, io::Error> {
+        let (source, interface) = (self.source, self.interface.clone());
+
+        self.in_network_namespace(move || {
+            bound_socket(Type::DGRAM, source, interface.as_deref(), port)
+                .map(std::net::UdpSocket::from)
+        })
+        .await
+    }
+
+    /// Creates a non-blocking TCP listener on `port` of the source address, or of all addresses without one.
+    pub(crate) async fn tcp_listener(&self, port: u16) -> Result<std::net::TcpListener, io::Error> {
+        let (source, interface) = (self.source, self.interface.clone());
+
+        self.in_network_namespace(move || {
+            let socket = bound_socket(Type::STREAM, source, interface.as_deref(), port)?;
+
+            socket.listen(1024)?;
+
+            Ok(std::net::TcpListener::from(socket))
+        })
+        .await
+    }
+
+    /// Runs `create` in the network namespace of this binding, or right here without one.
+    pub(crate) async fn in_network_namespace<T, F>(&self, create: F) -> Result<T, io::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, io::Error> + Send + 'static,
+    {
+        let (Some(network_namespace), Some(namespace_thread)) = (
+            self.network_namespace.as_deref(),
+            self.namespace_thread.as_ref(),
+        ) else {
+            return create();
+        };
+
+        let (sender, receiver) = oneshot::channel();
+
+        namespace_thread.run(
+            network_namespace,
+            Box::new(move |entered| {
+                let result = match entered {
+                    Ok(()) => create(),
+                    Err(error) => Err(io::Error::new(error.kind(), error.to_string())),
+                };
+
+                // the caller only goes away when it's cancelled, and then nobody wants the socket anymore
+                let _r = sender.send(result);
+            }),
+        )?;
+
+        receiver.await.map_err(|_| {
+            io::Error::other("Thread creating the socket in the network namespace died")
+        })?
+    }
+}
+
+/// Bindings are equal when they set up sockets the same way.
+impl PartialEq for Binding {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+            && self.interface == other.interface
+            && self.network_namespace == other.network_namespace
+    }
+}
+
+impl Eq for Binding {}
+
+/// Work for the namespace thread, told whether entering the network namespace succeeded.
+type Job = Box<dyn FnOnce(Result<(), &io::Error>) + Send>;
+
+/// A thread that entered a network namespace, and creates the sockets of that namespace. Entering a namespace changes
+/// the calling thread for good, so we do it on a thread of our own, once, and not on a thread of the runtime. The
+/// sockets stay in the namespace they were created in.
+///
+/// The thread is started with the first socket, and ends when the last clone of the binding is dropped.
+#[derive(Debug, Default)]
+struct NamespaceThread {
+    jobs: Mutex<Option<Sender<Job>>>,
+}
+
+impl NamespaceThread {
+    fn run(self: &Arc<Self>, network_namespace: &Path, job: Job) -> Result<(), io::Error> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // the thread ends when it failed to enter the namespace, so that the next socket tries again
+        let job = match jobs.as_ref() {
+            Some(running) => match running.send(job) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::SendError(job)) => job,
+            },
+            None => job,
+        };
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let network_namespace = network_namespace.to_path_buf();
+        // not an `Arc`, the thread would keep the sender alive, and itself running, forever
+        let this = Arc::downgrade(self);
+
+        std::thread::Builder::new()
+            .name(String::from("natpmp-netns"))
+            .spawn(move || {
+                let entered = enter_network_namespace(&network_namespace);
+
+                if entered.is_err() {
+                    // no new jobs are queued from here on, and the ones that already are end the loop below with
+                    // the same error, rather than finding the thread gone
+                    if let Some(this) = this.upgrade() {
+                        *this.jobs.lock().unwrap_or_else(PoisonError::into_inner) = None;
+                    }
+                }
+
+                for job in receiver {
+                    job(entered.as_ref().map(|&()| ()));
+                }
+            })?;
+
+        // can't fail, the thread only drops the receiver once the sender is gone
+        let _r = sender.send(job);
+
+        *jobs = Some(sender);
+
+        Ok(())
+    }
+}
+
+/// Creates a non-blocking socket of `kind` bound to `interface`, and to `port` of `source`, or of all addresses
+/// without one. Port 0 is an ephemeral port, which is picked right away so that the socket knows its port before
+/// it sends anything.
+fn bound_socket(
+    kind: Type,
+    source: Option<Ipv4Addr>,
+    interface: Option<&str>,
+    port: u16,
+) -> Result<Socket, io::Error> {
+    let socket = Socket::new(socket2::Domain::IPV4, kind, None)?;
+
+    if let Some(interface) = interface {
+        socket.bind_device(Some(interface.as_bytes()))?;
+    }
+
+    if kind == Type::STREAM {
+        // like `TcpListener::bind()`, so that a restarted listener doesn't wait for the old connections to time out
+        socket.set_reuse_address(true)?;
+    }
+
+    socket.bind(&SocketAddrV4::new(source.unwrap_or(Ipv4Addr::UNSPECIFIED), port).into())?;
+
+    socket.set_nonblocking(true)?;
+
+    Ok(socket)
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+
+        if let Some(source) = self.source {
+            parts.push(format!("source {}", source));
+        }
+
+        if let Some(ref interface) = self.interface {
+            parts.push(format!("interface {}", interface));
+        }
+
+        if let Some(ref network_namespace) = self.network_namespace {
+            parts.push(format!("network namespace {}", network_namespace.display()));
+        }
+
+        if parts.is_empty() {
+            write!(f, "default")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+fn enter_network_namespace(network_namespace: &Path) -> Result<(), io::Error> {
+    let network_namespace = File::open(network_namespace)?;
+
+    setns(network_namespace, CloneFlags::CLONE_NEWNET)?;
+
+    Ok(())
+}
@@ -0,0 +1,362 @@
+//! Writes every NAT-PMP and PCP datagram we send and receive to a pcapng file, for Wireshark, so that router
+//! quirks can be debugged without running tcpdump next to us.
+//!
+//! Datagrams are written as raw IPv4 packets with made-up IP and UDP headers around the payload. Received
+//! datagrams that were discarded, e.g. because they didn't come from the gateway, carry a comment saying why.
+//! Source: <https://datatracker.ietf.org/doc/draft-ietf-opsawg-pcapng/>
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write as _};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::BufMut as _;
+use tokio::net::UdpSocket;
+use tracing::{Level, event};
+use zerocopy::IntoBytes as _;
+use zerocopy::network_endian::U16;
+
+use crate::binding::Binding;
+
+/// The capture of the transport, `None` unless one was set.
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+/// Packets start at the IPv4 header, there is no link layer.
+/// Source: <https://www.tcpdump.org/linktypes.html>
+const LINKTYPE_RAW: u16 = 101;
+
+const OPTION_END: u16 = 0;
+const OPTION_COMMENT: u16 = 1;
+const OPTION_INTERFACE_NAME: u16 = 2;
+const OPTION_PACKET_FLAGS: u16 = 2;
+
+/// The direction bits of the packet flags
+const FLAGS_INBOUND: u32 = 0b01;
+const FLAGS_OUTBOUND: u32 = 0b10;
+
+const IPV4_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+/// Whether we sent or received a datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Sent,
+    Received,
+}
+
+/// A pcapng file that datagrams are appended to.
+///
+/// The blocks are written by a thread of its own, so that requests never wait for the disk, which flushes the file
+/// whenever it caught up. Dropping the capture waits for the blocks that are still queued.
+#[derive(Debug)]
+pub struct Capture {
+    /// `None` once closed
+    blocks: Option<Sender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Creates, or truncates, the file at `path`, and writes the pcapng headers.
+    ///
+    /// # Errors
+    /// When the file can't be created or written
+    pub fn create(path: &Path) -> Result<Capture, io::Error> {
+        let mut file = File::create(path)?;
+
+        let mut section_header = vec![];
+        section_header.put_u32_le(BYTE_ORDER_MAGIC);
+        // version 1.0
+        section_header.put_u16_le(1);
+        section_header.put_u16_le(0);
+        // the length of the section isn't known up front
+        section_header.put_i64_le(-1);
+
+        let mut interface_description = vec![];
+        interface_description.put_u16_le(LINKTYPE_RAW);
+        interface_description.put_u16_le(0);
+        // no snapshot length limit
+        interface_description.put_u32_le(0);
+        put_option(
+            &mut interface_description,
+            OPTION_INTERFACE_NAME,
+            b"natpmp-rs",
+        );
+        interface_description.put_u16_le(OPTION_END);
+        interface_description.put_u16_le(0);
+
+        file.write_all(&block(SECTION_HEADER_BLOCK, &section_header))?;
+        file.write_all(&block(INTERFACE_DESCRIPTION_BLOCK, &interface_description))?;
+
+        let (blocks, queue) = mpsc::channel();
+
+        let writer = std::thread::Builder::new()
+            .name(String::from("natpmp-capture"))
+            .spawn(move || write_blocks(file, &queue))?;
+
+        Ok(Capture {
+            blocks: Some(blocks),
+            writer: Some(writer),
+        })
+    }
+
+    fn write(
+        &self,
+        direction: Direction,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        datagram: &[u8],
+        discarded: Option<&str>,
+    ) {
+        let packet = match direction {
+            Direction::Sent => ipv4_udp_packet(local, remote, datagram),
+            Direction::Received => ipv4_udp_packet(remote, local, datagram),
+        };
+
+        let length = u32::try_from(packet.len()).unwrap_or(u32::MAX);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| {
+                u64::try_from(since_epoch.as_micros()).unwrap_or(u64::MAX)
+            });
+
+        let mut enhanced_packet = vec![];
+        // the interface described above
+        enhanced_packet.put_u32_le(0);
+        // in microseconds, the default resolution, high half first
+        enhanced_packet.put_u32_le(u32::try_from(timestamp >> 32).unwrap_or(u32::MAX));
+        enhanced_packet.put_u32_le(u32::try_from(timestamp & u64::from(u32::MAX)).unwrap_or(0));
+        enhanced_packet.put_u32_le(length);
+        enhanced_packet.put_u32_le(length);
+        enhanced_packet.put_slice(&packet);
+        pad(&mut enhanced_packet);
+
+        let mut flags = vec![];
+        flags.put_u32_le(match direction {
+            Direction::Sent => FLAGS_OUTBOUND,
+            Direction::Received => FLAGS_INBOUND,
+        });
+
+        put_option(&mut enhanced_packet, OPTION_PACKET_FLAGS, &flags);
+
+        if let Some(reason) = discarded {
+            put_option(
+                &mut enhanced_packet,
+                OPTION_COMMENT,
+                format!("Discarded: {}", reason).as_bytes(),
+            );
+        }
+
+        enhanced_packet.put_u16_le(OPTION_END);
+        enhanced_packet.put_u16_le(0);
+
+        if let Some(ref blocks) = self.blocks {
+            // the writer only goes away when the capture is dropped
+            let _r = blocks.send(block(ENHANCED_PACKET_BLOCK, &enhanced_packet));
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        // the writer stops once it wrote what was queued
+        drop(self.blocks.take());
+
+        if let Some(writer) = self.writer.take()
+            && writer.join().is_err()
+        {
+            event!(Level::ERROR, "Capture writer panicked");
+        }
+    }
+}
+
+/// Makes `capture` the capture of the transport, `None` stops capturing. Replaces, and closes, the previous one,
+/// which waits for its queued datagrams to be written.
+pub fn set_capture(capture: Option<Capture>) {
+    let previous = std::mem::replace(&mut *lock(), capture);
+
+    drop(previous);
+}
+
+/// Stops capturing, and waits for the queued datagrams to be written, without blocking the runtime. Meant for
+/// shutting down.
+pub async fn close_capture() {
+    let Some(capture) = lock().take() else {
+        return;
+    };
+
+    if let Err(error) = tokio::task::spawn_blocking(move || drop(capture)).await {
+        event!(Level::ERROR, ?error, "Failed to close the capture");
+    }
+}
+
+/// The address `socket` sends to `remote` from, when we're capturing.
+///
+/// Unless the binding has a source address, requests are sent from a socket bound to `0.0.0.0`, and the kernel picks
+/// the source address per datagram. We ask it which one it picks for `remote` by connecting a socket that is set up
+/// like `binding`'s, which doesn't send anything.
+pub(crate) async fn local_address(
+    socket: &UdpSocket,
+    remote: SocketAddr,
+    binding: &Binding,
+) -> Option<SocketAddr> {
+    if lock().is_none() {
+        return None;
+    }
+
+    let mut local = socket.local_addr().ok()?;
+
+    if local.ip().is_unspecified() {
+        match outgoing_address(remote, binding).await {
+            Ok(address) => local.set_ip(address),
+            Err(error) => {
+                event!(Level::DEBUG, ?error, %remote, "Failed to look up the source address of the capture");
+            },
+        }
+    }
+
+    Some(local)
+}
+
+async fn outgoing_address(remote: SocketAddr, binding: &Binding) -> Result<IpAddr, io::Error> {
+    let probe = binding.socket().await?;
+
+    probe.connect(remote)?;
+
+    Ok(probe.local_addr()?.ip())
+}
+
+/// Writes `datagram` to the capture, if there is one. `discarded` says why a received datagram was ignored.
+///
+/// Failing to write is logged, and doesn't fail the request.
+pub(crate) fn record(
+    direction: Direction,
+    local: Option<SocketAddr>,
+    remote: SocketAddr,
+    datagram: &[u8],
+    discarded: Option<&str>,
+) {
+    let capture = lock();
+
+    let Some(ref capture) = *capture else {
+        return;
+    };
+
+    // we only speak IPv4, an unknown local address is written as `0.0.0.0`
+    let local = match local {
+        Some(SocketAddr::V4(local)) => local,
+        Some(SocketAddr::V6(_)) | None => SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+    };
+
+    let SocketAddr::V4(remote) = remote else {
+        return;
+    };
+
+    capture.write(direction, local, remote, datagram, discarded);
+}
+
+fn lock() -> MutexGuard<'static, Option<Capture>> {
+    // the capture is always in a valid state, even when a thread panicked while holding the lock
+    CAPTURE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Writes the queued blocks to `file`, and flushes it whenever the queue ran dry, until the capture is closed.
+fn write_blocks(file: File, queue: &Receiver<Vec<u8>>) {
+    let mut file = BufWriter::new(file);
+
+    while let Ok(first) = queue.recv() {
+        let result = std::iter::once(first)
+            .chain(queue.try_iter())
+            .try_for_each(|block| file.write_all(&block))
+            .and_then(|()| file.flush());
+
+        if let Err(error) = result {
+            event!(Level::WARN, ?error, "Failed to write to the capture");
+        }
+    }
+}
+
+/// A block: its type, total length, body, and total length again.
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let length = u32::try_from(body.len() + 12).unwrap_or(u32::MAX);
+
+    let mut block = vec![];
+    block.put_u32_le(block_type);
+    block.put_u32_le(length);
+    block.put_slice(body);
+    block.put_u32_le(length);
+
+    block
+}
+
+/// An option: its code, the length of its value, and the value padded to 32 bits.
+fn put_option(buffer: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buffer.put_u16_le(code);
+    buffer.put_u16_le(u16::try_from(value.len()).unwrap_or(u16::MAX));
+    buffer.put_slice(value);
+    pad(buffer);
+}
+
+fn pad(buffer: &mut Vec<u8>) {
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+/// `datagram` in made-up IPv4 and UDP headers, as the wire would have carried it.
+fn ipv4_udp_packet(source: SocketAddrV4, destination: SocketAddrV4, datagram: &[u8]) -> Vec<u8> {
+    let udp_length = u16::try_from(UDP_HEADER_SIZE + datagram.len()).unwrap_or(u16::MAX);
+    let total_length =
+        u16::try_from(IPV4_HEADER_SIZE + UDP_HEADER_SIZE + datagram.len()).unwrap_or(u16::MAX);
+
+    let mut header = vec![];
+    // version 4, header of 5 32-bit words
+    header.put_u8(0x45);
+    header.put_u8(0);
+    header.put_u16(total_length);
+    // identification, and don't fragment
+    header.put_u16(0);
+    header.put_u16(0x4000);
+    // time to live
+    header.put_u8(64);
+    header.put_u8(IP_PROTOCOL_UDP);
+    header.put_u16(0);
+    header.put_u32(source.ip().to_bits());
+    header.put_u32(destination.ip().to_bits());
+
+    let checksum = ipv4_checksum(&header);
+    header[10..12].copy_from_slice(U16::new(checksum).as_bytes());
+
+    let mut packet = Vec::with_capacity(IPV4_HEADER_SIZE + UDP_HEADER_SIZE + datagram.len());
+    packet.put_slice(&header);
+    packet.put_u16(source.port());
+    packet.put_u16(destination.port());
+    packet.put_u16(udp_length);
+    // no checksum, which IPv4 allows
+    packet.put_u16(0);
+    packet.put_slice(datagram);
+
+    packet
+}
+
+/// The ones' complement of the ones' complement sum of the header's 16-bit words.
+/// Source: <https://www.rfc-editor.org/rfc/rfc791#section-3.1>
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .map(|word| (u32::from(word[0]) << 8) | u32::from(word.get(1).copied().unwrap_or(0)))
+        .sum::<u32>();
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !u16::try_from(sum).unwrap_or(u16::MAX)
+}
@@ -0,0 +1,433 @@
+use std::ffi::OsString;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{ArgGroup, Args, CommandFactory as _, Parser, Subcommand, ValueEnum};
+use natpmp_rs::batch::DEFAULT_CONCURRENCY;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::discovery::discover_gateway_retrying;
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::policy::ExternalPortPolicy;
+use natpmp_rs::port_range::PortRange;
+#[cfg(feature = "torrent-clients")]
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::retry::{RetryPolicy, Rfc6886};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9100`
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Send requests from this address, for when the gateway only accepts requests from one of our addresses
+    #[arg(long, global = true, value_name = "IP")]
+    pub source: Option<Ipv4Addr>,
+
+    /// Send requests through this interface (`SO_BINDTODEVICE`, needs `CAP_NET_RAW`)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub interface: Option<String>,
+
+    /// Send requests from this network namespace, a name from `ip netns` or a path (needs `CAP_SYS_ADMIN`).
+    /// The default gateway is still looked up in our own namespace, so pass `--gateway` as well
+    #[arg(long, global = true, value_name = "NAME|PATH")]
+    pub netns: Option<PathBuf>,
+
+    /// Write every NAT-PMP and PCP datagram sent and received, including discarded ones, to this pcapng file
+    #[arg(long, global = true, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+}
+
+impl Cli {
+    /// Parses the arguments, and checks what clap can't check on its own, exiting like clap does when they don't
+    /// make sense.
+    pub fn parse_checked() -> Cli {
+        let cli = Cli::parse();
+
+        // a mapping renewed no sooner than it expires is gone in between
+        if let Command::Vpn(ref args) = cli.command
+            && args.renew_interval >= u64::from(args.lifetime)
+        {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--renew-interval ({}) must be shorter than --lifetime ({})",
+                        args.renew_interval, args.lifetime
+                    ),
+                )
+                .exit();
+        }
+
+        cli
+    }
+
+    pub fn binding(&self) -> Binding {
+        let mut binding = Binding::new();
+
+        if let Some(source) = self.source {
+            binding = binding.with_source(source);
+        }
+
+        if let Some(ref interface) = self.interface {
+            binding = binding.with_interface(interface.clone());
+        }
+
+        if let Some(ref netns) = self.netns {
+            binding = binding.with_network_namespace(netns);
+        }
+
+        binding
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Map ports, run a command with the mappings exported, and unmap when it exits
+    Run(RunArgs),
+    /// Keep ports mapped and watch the public address until stopped
+    Daemon(DaemonArgs),
+    /// Keep a VPN provider's forwarded port, and write it to a file whenever it changes
+    Vpn(VpnArgs),
+    /// Report the external address, and whether mappings on it can be reached from the internet
+    Probe(ProbeArgs),
+    /// Find out why NAT-PMP doesn't work
+    Doctor(DoctorArgs),
+    /// Check the gateway against RFC 6886, with well-formed and malformed requests
+    Conformance(ConformanceArgs),
+    /// Simulate a NAT-PMP gateway, which maps nothing, to run clients against
+    Simulate(SimulateArgs),
+    /// Answer `probe --verify --reflector` requests, by sending traffic back to where they came from
+    Reflector(ReflectorArgs),
+    /// Pretty-print a NAT-PMP or PCP packet given as hex
+    Decode(DecodeArgs),
+    /// Send a hand-made packet to the gateway, and decode the reply
+    Raw(RawArgs),
+}
+
+#[derive(Args)]
+pub struct GatewayArgs {
+    /// The NAT-PMP gateway, discovered among the default gateways and VPN peers when omitted
+    #[arg(long, value_name = "IP")]
+    pub gateway: Option<Ipv4Addr>,
+
+    /// The number of times to retry a request, defaults to 9 as per specification
+    #[arg(long)]
+    pub retry: Option<u32>,
+}
+
+impl GatewayArgs {
+    pub fn retry_policy(&self) -> Rfc6886 {
+        self.retry
+            .map_or_else(Rfc6886::default, Rfc6886::with_attempts)
+    }
+
+    /// `--gateway`, or the gateway discovered from `binding`, asking the candidates following `retry_policy`.
+    pub async fn gateway_ip<P: RetryPolicy + 'static>(
+        &self,
+        retry_policy: P,
+        binding: &Binding,
+    ) -> Result<Ipv4Addr, NATPMPError> {
+        match self.gateway {
+            Some(gateway_ip) => Ok(gateway_ip),
+            None => discover_gateway_retrying(retry_policy, binding).await,
+        }
+    }
+
+    /// A client for the gateway that sends its requests from `binding`, following `--retry`.
+    pub async fn client(&self, binding: Binding) -> Result<Client, NATPMPError> {
+        let gateway_ip = self.gateway_ip(self.retry_policy(), &binding).await?;
+
+        Ok(Client::new(gateway_ip)
+            .with_retry_policy(self.retry_policy())
+            .with_binding(binding))
+    }
+}
+
+#[derive(Args)]
+pub struct MappingArgs {
+    /// TCP port to map, can be repeated
+    #[arg(long = "tcp", value_name = "PORT")]
+    pub tcp: Vec<NonZeroU16>,
+
+    /// UDP port to map, can be repeated
+    #[arg(long = "udp", value_name = "PORT")]
+    pub udp: Vec<NonZeroU16>,
+
+    /// TCP port range to map, e.g. `6881-6889`, can be repeated
+    #[arg(long = "tcp-range", value_name = "FIRST-LAST")]
+    pub tcp_range: Vec<PortRange>,
+
+    /// UDP port range to map, e.g. `10000-10100`, can be repeated
+    #[arg(long = "udp-range", value_name = "FIRST-LAST")]
+    pub udp_range: Vec<PortRange>,
+
+    /// What to do when the gateway grants a different external port than requested
+    #[arg(long, value_enum, default_value_t = PortPolicy::Any)]
+    pub port_policy: PortPolicy,
+
+    /// External port to try when the requested one isn't granted, with `--port-policy fallback`, can be repeated
+    #[arg(long, value_name = "PORT", required_if_eq("port_policy", "fallback"))]
+    pub fallback_port: Vec<NonZeroU16>,
+
+    /// External ports to try when the requested one isn't granted, with `--port-policy fallback-range`
+    #[arg(
+        long,
+        value_name = "FIRST-LAST",
+        required_if_eq("port_policy", "fallback-range")
+    )]
+    pub fallback_range: Option<PortRange>,
+
+    /// The number of mapping requests that are sent at once
+    #[arg(long, value_name = "REQUESTS", default_value_t = DEFAULT_CONCURRENCY)]
+    pub concurrency: NonZeroUsize,
+
+    /// The requested lifetime of the mappings in seconds
+    #[arg(long, default_value_t = 7200)]
+    pub lifetime: u32,
+}
+
+impl MappingArgs {
+    pub fn external_port_policy(&self) -> ExternalPortPolicy {
+        match self.port_policy {
+            PortPolicy::Any => ExternalPortPolicy::Any,
+            PortPolicy::Exact => ExternalPortPolicy::Exact,
+            PortPolicy::Fallback => ExternalPortPolicy::Fallback(self.fallback_port.clone()),
+            PortPolicy::FallbackRange => self
+                .fallback_range
+                .map_or(ExternalPortPolicy::Exact, ExternalPortPolicy::FallbackRange),
+        }
+    }
+}
+
+/// See `ExternalPortPolicy`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PortPolicy {
+    /// Keep whatever external port the gateway grants
+    Any,
+    /// Delete the mappings and fail
+    Exact,
+    /// Try the `--fallback-port`s in order, then delete the mappings and fail
+    Fallback,
+    /// Try the ports of `--fallback-range` in order, then delete the mappings and fail
+    FallbackRange,
+}
+
+#[derive(Args)]
+pub struct HookArgs {
+    /// Command to run with `sh -c` when the public address or an external port changes, can be repeated
+    #[arg(long, value_name = "COMMAND")]
+    pub hook: Vec<String>,
+
+    /// Directory of executables to run when the public address or an external port changes, in name order
+    #[arg(long, value_name = "DIRECTORY")]
+    pub hook_dir: Option<PathBuf>,
+
+    /// Seconds after which a hook is killed
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub hook_timeout: u64,
+
+    /// qBittorrent Web UI to set the listen port of, e.g. `http://localhost:8080`
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, value_name = "URL")]
+    pub qbittorrent_url: Option<String>,
+
+    /// qBittorrent Web UI username, leave empty when authentication is bypassed
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, value_name = "USERNAME", requires = "qbittorrent_password")]
+    pub qbittorrent_username: Option<String>,
+
+    /// qBittorrent Web UI password, best passed through the environment so that it doesn't show up in `ps`
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, env = "QBITTORRENT_PASSWORD", hide_env_values = true)]
+    pub qbittorrent_password: Option<String>,
+
+    /// Transmission RPC endpoint to set the peer port of, e.g. `http://localhost:9091/transmission/rpc`
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, value_name = "URL")]
+    pub transmission_url: Option<String>,
+
+    /// Transmission RPC username, leave empty when authentication is disabled
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, value_name = "USERNAME", requires = "transmission_password")]
+    pub transmission_username: Option<String>,
+
+    /// Transmission RPC password, best passed through the environment so that it doesn't show up in `ps`
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, env = "TRANSMISSION_PASSWORD", hide_env_values = true)]
+    pub transmission_password: Option<String>,
+
+    /// The internal port of the mapping whose external port the torrent clients are set to, the first mapping of
+    /// `--torrent-protocol` when omitted
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, value_name = "PORT")]
+    pub torrent_port: Option<NonZeroU16>,
+
+    /// The protocol of that mapping
+    #[cfg(feature = "torrent-clients")]
+    #[arg(long, value_name = "tcp|udp", default_value = "tcp")]
+    pub torrent_protocol: MappingProtocol,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Seconds between public address checks, at most a day, on top of listening for gateway announcements
+    #[arg(long, value_name = "SECONDS", default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..=86400))]
+    pub poll_interval: u64,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("ports").args(["tcp", "udp", "tcp_range", "udp_range"]).required(true).multiple(true)))]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+
+    #[command(flatten)]
+    pub mappings: MappingArgs,
+
+    #[command(flatten)]
+    pub hooks: HookArgs,
+
+    #[command(flatten)]
+    pub watch: WatchArgs,
+
+    /// The command to run, and its arguments
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    pub command: Vec<OsString>,
+}
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+
+    #[command(flatten)]
+    pub mappings: MappingArgs,
+
+    #[command(flatten)]
+    pub hooks: HookArgs,
+
+    #[command(flatten)]
+    pub watch: WatchArgs,
+}
+
+#[derive(Args)]
+pub struct VpnArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+
+    #[command(flatten)]
+    pub hooks: HookArgs,
+
+    /// The public port to request, providers hand out a port of their own choosing when asked for port 1
+    #[arg(long, value_name = "PORT", default_value = "1")]
+    pub public_port: NonZeroU16,
+
+    /// The internal port to request
+    #[arg(long, value_name = "PORT", default_value = "1")]
+    pub internal_port: NonZeroU16,
+
+    /// The requested lifetime of the mappings in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    pub lifetime: u32,
+
+    /// Seconds between renewals, which needs to be comfortably shorter than the lifetime, and is rejected otherwise
+    #[arg(long, value_name = "SECONDS", default_value_t = 45, value_parser = clap::value_parser!(u64).range(1..))]
+    pub renew_interval: u64,
+
+    /// File to write the forwarded port to
+    #[arg(long, value_name = "FILE")]
+    pub port_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ProbeArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+
+    /// The NAT in front of the gateway, e.g. the ISP's router, to ask whether it speaks PCP
+    #[arg(long, value_name = "IP")]
+    pub upstream: Option<Ipv4Addr>,
+
+    /// STUN server to compare the external address with, e.g. `stun.l.google.com:19302`, port 3478 when omitted
+    #[arg(long, value_name = "HOST[:PORT]")]
+    pub stun: Option<String>,
+
+    /// Map a TCP and a UDP port, and check that traffic to their public address arrives
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Reflector to send the traffic of `--verify` from outside the NAT, see `reflector`.
+    /// Without one we send it ourselves, which only works when the gateway supports hairpinning
+    #[arg(long, value_name = "ADDRESS", requires = "verify")]
+    pub reflector: Option<SocketAddr>,
+
+    /// Seconds to wait for the traffic of `--verify` to arrive
+    #[arg(long, value_name = "SECONDS", default_value_t = 5, requires = "verify")]
+    pub verify_timeout: u64,
+}
+
+#[derive(Args)]
+pub struct ReflectorArgs {
+    /// The address to accept requests on
+    #[arg(long, value_name = "ADDRESS")]
+    pub listen: SocketAddr,
+}
+
+#[derive(Args)]
+pub struct DoctorArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+}
+
+#[derive(Args)]
+pub struct ConformanceArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+
+    /// Also check deleting all mappings at once. This deletes every TCP mapping of this host, also other programs'
+    #[arg(long)]
+    pub destructive: bool,
+}
+
+#[derive(Args)]
+pub struct SimulateArgs {
+    /// The address to answer requests on
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:5351")]
+    pub listen: SocketAddrV4,
+
+    /// The external address to report
+    #[arg(long, value_name = "IP", default_value = "203.0.113.1")]
+    pub external_address: Ipv4Addr,
+}
+
+#[derive(Args)]
+pub struct DecodeArgs {
+    /// The packet as hex, e.g. `0080000000001234c0a80101`. Whitespace, `:` and `-` between bytes are ignored
+    #[arg(required = true, value_name = "HEX")]
+    pub hex: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct RawArgs {
+    #[command(flatten)]
+    pub gateway: GatewayArgs,
+
+    /// The first byte of the packet, 0 for NAT-PMP and 2 for PCP
+    #[arg(long, default_value_t = 0)]
+    pub version: u8,
+
+    /// The second byte of the packet
+    #[arg(long)]
+    pub opcode: u8,
+
+    /// The rest of the packet as hex, e.g. `00001f901f900000012c` for a 300 second TCP mapping of port 8080
+    #[arg(long, value_name = "HEX", default_value = "")]
+    pub payload: String,
+}
@@ -0,0 +1,452 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{Level, event};
+
+use crate::batch::{MappingEntry, map_entries, renew_mappings, unmap_mappings};
+use crate::binding::Binding;
+use crate::conformance::{self, ConformanceReport};
+use crate::diagnostics::{self, Diagnosis};
+use crate::discovery::discover_gateway;
+use crate::errors::{NATPMPError, StunError};
+use crate::mapping_guard::MappingGuard;
+use crate::packet::{self, RawReply};
+use crate::policy::{ExternalPortPolicy, map_accepting};
+use crate::protocol::MappingProtocol;
+use crate::public_socket::PublicSocket;
+use crate::reachability::{self, ReachabilityReport};
+use crate::responses::{ExternalAddressResponse, MappingResponse};
+use crate::retry::{RetryPolicy, Rfc6886};
+use crate::stun::{self, StunCrossCheck};
+use crate::watch::{self, PublicAddressWatch};
+use crate::{request_external_address, request_mapping, request_public_address, request_unmap};
+
+/// Talks to a single NAT-PMP gateway.
+///
+/// Unlike the free functions, mappings created with `Client::map()` are owned by a `MappingGuard`,
+/// which keeps them renewed and deletes them again.
+#[derive(Debug, Clone)]
+pub struct Client {
+    gateway_ip: Ipv4Addr,
+    retry_policy: Arc<dyn RetryPolicy>,
+    binding: Binding,
+    external_port_policy: ExternalPortPolicy,
+}
+
+impl Client {
+    #[must_use]
+    pub fn new(gateway_ip: Ipv4Addr) -> Client {
+        Client {
+            gateway_ip,
+            retry_policy: Arc::new(Rfc6886::default()),
+            binding: Binding::new(),
+            external_port_policy: ExternalPortPolicy::Any,
+        }
+    }
+
+    /// Creates a client for the gateway found by `discover_gateway()`.
+    ///
+    /// # Errors
+    /// See `discover_gateway()`
+    pub async fn discover() -> Result<Client, NATPMPError> {
+        Ok(Client::new(discover_gateway().await?))
+    }
+
+    /// The number of times to retry a request if unsuccessful, defaults to 9 as per specification.
+    #[must_use]
+    pub fn with_retry(self, retry: u32) -> Client {
+        self.with_retry_policy(Rfc6886::with_attempts(retry))
+    }
+
+    /// How long to wait for responses and when to give up, defaults to `Rfc6886`.
+    ///
+    /// E.g. `Rfc6886::default().with_deadline(Duration::from_secs(2))` for an interactive tool.
+    #[must_use]
+    pub fn with_retry_policy<P: RetryPolicy + 'static>(self, retry_policy: P) -> Client {
+        self.with_shared_retry_policy(Arc::new(retry_policy))
+    }
+
+    pub(crate) fn with_shared_retry_policy(mut self, retry_policy: Arc<dyn RetryPolicy>) -> Client {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// The source address, interface or network namespace to send requests from, defaults to `Binding::new()`.
+    #[must_use]
+    pub fn with_binding(mut self, binding: Binding) -> Client {
+        self.binding = binding;
+        self
+    }
+
+    /// What `map()` does when the gateway grants a different external port, defaults to `ExternalPortPolicy::Any`.
+    ///
+    /// Renewals take whatever port the gateway grants, `MappingGuard::external_port()` tells when it changed.
+    #[must_use]
+    pub fn with_external_port_policy(mut self, external_port_policy: ExternalPortPolicy) -> Client {
+        self.external_port_policy = external_port_policy;
+        self
+    }
+
+    #[must_use]
+    pub fn gateway_ip(&self) -> Ipv4Addr {
+        self.gateway_ip
+    }
+
+    #[must_use]
+    pub fn retry_policy(&self) -> &dyn RetryPolicy {
+        &*self.retry_policy
+    }
+
+    #[must_use]
+    pub fn binding(&self) -> &Binding {
+        &self.binding
+    }
+
+    /// Returns the public address of the gateway.
+    ///
+    /// # Errors
+    /// Described by the Error component of the Result
+    pub async fn get_public_address(&self) -> Result<Ipv4Addr, NATPMPError> {
+        request_public_address(self.gateway_ip, self.retry_policy(), &self.binding).await
+    }
+
+    /// Returns the complete answer to an external address request, see `ExternalAddressResponse`.
+    ///
+    /// # Errors
+    /// Described by the Error component of the Result
+    pub async fn get_external_address(&self) -> Result<ExternalAddressResponse, NATPMPError> {
+        request_external_address(self.gateway_ip, self.retry_policy(), &self.binding).await
+    }
+
+    /// Tells whether mappings on the gateway can be reached from the internet, judging by its external address.
+    ///
+    /// With `upstream`, the gateway in front of ours, e.g. the ISP's router of a double NAT, is asked whether it
+    /// speaks PCP.
+    ///
+    /// # Errors
+    /// When the gateway doesn't tell its external address. The upstream gateway's answer is part of the report.
+    pub async fn probe_reachability(
+        &self,
+        upstream: Option<Ipv4Addr>,
+    ) -> Result<ReachabilityReport, NATPMPError> {
+        reachability::probe(self, upstream).await
+    }
+
+    /// Compares the gateway's external address with the address `stun_server` sees our requests come from,
+    /// which tells a gateway behind another NAT, or one that reports a stale address.
+    ///
+    /// # Errors
+    /// When either the gateway or the STUN server doesn't tell
+    pub async fn cross_check_stun(
+        &self,
+        stun_server: SocketAddrV4,
+    ) -> Result<StunCrossCheck, StunError> {
+        let external_address = self
+            .get_external_address()
+            .await
+            .map_err(StunError::Gateway)?;
+
+        stun::cross_check(
+            external_address,
+            stun_server,
+            self.retry_policy(),
+            &self.binding,
+        )
+        .await
+    }
+
+    /// Finds out everything we can about the gateway: the route to it, whether its NAT-PMP port answers, which
+    /// protocols it speaks, and whether it maps ports. Test mappings are deleted again.
+    pub async fn diagnose(&self) -> Diagnosis {
+        diagnostics::diagnose(self).await
+    }
+
+    /// Checks the gateway against RFC 6886, with well-formed and malformed requests. Test mappings are deleted
+    /// again.
+    pub async fn check_conformance(&self) -> ConformanceReport {
+        conformance::run(self.gateway_ip, self.retry_policy(), &self.binding, false).await
+    }
+
+    /// Like `check_conformance()`, but also checks deleting all mappings at once. That deletes every TCP mapping of
+    /// this host, also the ones of other programs.
+    pub async fn check_conformance_destructively(&self) -> ConformanceReport {
+        conformance::run(self.gateway_ip, self.retry_policy(), &self.binding, true).await
+    }
+
+    /// Watches the public address, listening for the gateway's announcements and asking it every `poll_interval`,
+    /// at least `watch::MIN_POLL_INTERVAL`, when none arrive. See `PublicAddressWatch`.
+    #[must_use]
+    pub fn watch_public_address(&self, poll_interval: Duration) -> PublicAddressWatch {
+        watch::watch(self.clone(), poll_interval)
+    }
+
+    /// Sends `datagram` to the gateway as is, e.g. a request we have no type for or a malformed one, and returns
+    /// whatever the gateway sends back. `packet::decode()` makes sense of both.
+    ///
+    /// # Errors
+    /// When sending fails, or the gateway doesn't answer
+    pub async fn send_raw(&self, datagram: &[u8]) -> Result<RawReply, NATPMPError> {
+        packet::send_raw(
+            self.gateway_ip,
+            datagram,
+            self.retry_policy(),
+            &self.binding,
+        )
+        .await
+    }
+
+    /// Maps `public_port` to `private_port`, and keeps the mapping renewed until the returned guard is
+    /// released or dropped.
+    ///
+    /// # Arguments
+    /// * `protocol` - `Protocol::TCP` or `Protocol::UDP`
+    /// * `private_port` - the private port of the mapping requested
+    /// * `public_port` - the public port of the mapping requested
+    /// * `lifetime` - the duration of the mapping in seconds, defaults to 7200, per specification.
+    ///
+    /// # Errors
+    /// When the initial mapping request fails, or the granted port is not allowed by the external port policy
+    pub async fn map(
+        &self,
+        protocol: MappingProtocol,
+        private_port: NonZeroU16,
+        public_port: Option<NonZeroU16>,
+        lifetime: Option<u32>,
+    ) -> Result<MappingGuard, NATPMPError> {
+        let lifetime = lifetime.unwrap_or(7200);
+
+        let response = map_accepting(
+            self,
+            protocol,
+            private_port,
+            public_port,
+            &self.external_port_policy,
+            lifetime,
+        )
+        .await?;
+
+        Ok(MappingGuard::new(self.clone(), lifetime, response))
+    }
+
+    /// Maps all `entries` at once, or none of them, see `map_ports()`.
+    ///
+    /// # Errors
+    /// The first mapping that failed, after the others have been deleted again
+    pub async fn map_ports(
+        &self,
+        entries: &[MappingEntry],
+        lifetime: Option<u32>,
+    ) -> Result<Vec<MappingResponse>, NATPMPError> {
+        let concurrency = NonZeroUsize::new(entries.len()).unwrap_or(NonZeroUsize::MIN);
+
+        self.map_ports_bounded(entries, lifetime, concurrency).await
+    }
+
+    /// Like `map_ports()`, but with at most `concurrency` requests in flight at once, see `map_ports_bounded()`.
+    ///
+    /// # Errors
+    /// The first mapping that failed, after the others have been deleted again
+    pub async fn map_ports_bounded(
+        &self,
+        entries: &[MappingEntry],
+        lifetime: Option<u32>,
+        concurrency: NonZeroUsize,
+    ) -> Result<Vec<MappingResponse>, NATPMPError> {
+        map_entries(
+            entries,
+            lifetime.unwrap_or(7200),
+            self.gateway_ip,
+            self.retry_policy(),
+            &self.binding,
+            concurrency,
+        )
+        .await
+    }
+
+    /// Renews `mappings`, requesting the external ports they were granted, see `renew_ports()`.
+    ///
+    /// # Errors
+    /// When the requests could not be sent at all
+    pub async fn renew_ports(
+        &self,
+        mappings: &[MappingResponse],
+        lifetime: Option<u32>,
+        concurrency: NonZeroUsize,
+    ) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+        renew_mappings(
+            mappings,
+            lifetime.unwrap_or(7200),
+            self.gateway_ip,
+            self.retry_policy(),
+            &self.binding,
+            concurrency,
+        )
+        .await
+    }
+
+    /// Deletes `mappings`, see `unmap_ports()`.
+    ///
+    /// # Errors
+    /// When the requests could not be sent at all
+    pub async fn unmap_ports(
+        &self,
+        mappings: &[MappingResponse],
+        concurrency: NonZeroUsize,
+    ) -> Result<Vec<Result<MappingResponse, NATPMPError>>, NATPMPError> {
+        unmap_mappings(
+            mappings,
+            self.gateway_ip,
+            self.retry_policy(),
+            &self.binding,
+            concurrency,
+        )
+        .await
+    }
+
+    /// Binds a `TcpListener` on `port`, or on an ephemeral port, and maps the same public port to it.
+    ///
+    /// The listener is bound like the requests are sent, to the source address, interface and in the network
+    /// namespace of the client's binding.
+    ///
+    /// # Errors
+    /// When binding fails, or when the gateway doesn't map the port or doesn't tell us its public address
+    pub async fn bind_public_tcp(
+        &self,
+        port: Option<NonZeroU16>,
+        lifetime: Option<u32>,
+    ) -> Result<PublicSocket<TcpListener>, NATPMPError> {
+        let listener = TcpListener::from_std(
+            self.binding
+                .tcp_listener(port.map_or(0, NonZeroU16::get))
+                .await?,
+        )?;
+        let local_address = listener.local_addr()?;
+
+        self.publish(listener, local_address, MappingProtocol::TCP, lifetime)
+            .await
+    }
+
+    /// Binds a `UdpSocket` on `port`, or on an ephemeral port, and maps the same public port to it.
+    ///
+    /// Use the returned socket for the traffic that is meant to go through the mapping, as the mapping
+    /// is tied to the socket's local port. It is bound like `bind_public_tcp()` binds its listener.
+    ///
+    /// # Errors
+    /// When binding fails, or when the gateway doesn't map the port or doesn't tell us its public address
+    pub async fn bind_public_udp(
+        &self,
+        port: Option<NonZeroU16>,
+        lifetime: Option<u32>,
+    ) -> Result<PublicSocket<UdpSocket>, NATPMPError> {
+        let socket = UdpSocket::from_std(
+            self.binding
+                .udp_socket(port.map_or(0, NonZeroU16::get))
+                .await?,
+        )?;
+        let local_address = socket.local_addr()?;
+
+        self.publish(socket, local_address, MappingProtocol::UDP, lifetime)
+            .await
+    }
+
+    async fn publish<S>(
+        &self,
+        socket: S,
+        local_address: SocketAddr,
+        protocol: MappingProtocol,
+        lifetime: Option<u32>,
+    ) -> Result<PublicSocket<S>, NATPMPError> {
+        let Some(local_port) = NonZeroU16::new(local_address.port()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                "Socket was bound without a port",
+            )
+            .into());
+        };
+
+        let guard = self
+            .map(protocol, local_port, Some(local_port), lifetime)
+            .await?;
+
+        let public_ip = match self.get_public_address().await {
+            Ok(public_ip) => public_ip,
+            Err(error) => {
+                // nobody can use the mapping without knowing where it is
+                if let Err(error) = guard.release().await {
+                    event!(Level::ERROR, ?error, "Failed to unmap port");
+                }
+
+                return Err(error);
+            },
+        };
+
+        let external_port = guard.external_port().borrow().map_or(0, NonZeroU16::get);
+
+        Ok(PublicSocket::new(
+            socket,
+            SocketAddrV4::new(public_ip, external_port),
+            guard,
+        ))
+    }
+
+    /// Deletes the mapping of `private_port`.
+    ///
+    /// # Errors
+    /// Described by the Error component of the Result
+    pub async fn unmap(
+        &self,
+        protocol: MappingProtocol,
+        private_port: NonZeroU16,
+    ) -> Result<MappingResponse, NATPMPError> {
+        request_unmap(
+            protocol,
+            private_port,
+            self.gateway_ip,
+            self.retry_policy(),
+            &self.binding,
+        )
+        .await
+    }
+
+    /// Maps `public_port` to `private_port` once, without a guard, for callers that renew and delete the mapping
+    /// themselves.
+    ///
+    /// # Errors
+    /// Described by the Error component of the Result
+    pub async fn map_once(
+        &self,
+        protocol: MappingProtocol,
+        private_port: NonZeroU16,
+        public_port: Option<NonZeroU16>,
+        lifetime: u32,
+    ) -> Result<MappingResponse, NATPMPError> {
+        request_mapping(
+            protocol,
+            private_port,
+            public_port,
+            lifetime,
+            self.gateway_ip,
+            self.retry_policy(),
+            &self.binding,
+        )
+        .await
+    }
+
+    /// Like `map_once()`, but only accepts the external ports `policy` allows, see `map_port_with_policy()`.
+    ///
+    /// # Errors
+    /// `NATPMPError::ExternalPortMismatch` when none of the acceptable ports was granted
+    pub async fn map_once_accepting(
+        &self,
+        protocol: MappingProtocol,
+        private_port: NonZeroU16,
+        public_port: Option<NonZeroU16>,
+        policy: &ExternalPortPolicy,
+        lifetime: u32,
+    ) -> Result<MappingResponse, NATPMPError> {
+        map_accepting(self, protocol, private_port, public_port, policy, lifetime).await
+    }
+}
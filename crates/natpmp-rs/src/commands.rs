@@ -0,0 +1,10 @@
+pub mod conformance;
+pub mod daemon;
+pub mod decode;
+pub mod doctor;
+pub mod probe;
+pub mod raw;
+pub mod reflector;
+pub mod run;
+pub mod simulate;
+pub mod vpn;
@@ -0,0 +1,41 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::retry::Rfc6886;
+
+use crate::cli::ConformanceArgs;
+
+/// Attempts per request, unless `--retry` says otherwise. Checks that expect no answer wait for all of them.
+const CONFORMANCE_ATTEMPTS: u32 = 3;
+
+/// Checks the gateway against RFC 6886 and prints a pass/fail line per requirement.
+///
+/// Exits with a failure when any check fails.
+pub async fn conformance(
+    args: ConformanceArgs,
+    binding: Binding,
+) -> Result<ExitCode, eyre::Report> {
+    let retry_policy = Rfc6886::with_attempts(args.gateway.retry.unwrap_or(CONFORMANCE_ATTEMPTS));
+
+    let gateway_ip = args.gateway.gateway_ip(retry_policy, &binding).await?;
+
+    let client = Client::new(gateway_ip)
+        .with_retry_policy(retry_policy)
+        .with_binding(binding);
+
+    let report = if args.destructive {
+        client.check_conformance_destructively().await
+    } else {
+        client.check_conformance().await
+    };
+
+    println!("{}", report);
+
+    Ok(if report.is_conformant() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,42 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{Level, event};
+
+use crate::cli::DaemonArgs;
+use crate::maintainer::Maintainer;
+
+/// Maps the requested ports and keeps them alive, running the hooks whenever the public address
+/// or an external port changes. Stops on `SIGINT` or `SIGTERM`, after which all mappings are deleted.
+pub async fn daemon(args: DaemonArgs, binding: Binding) -> Result<ExitCode, eyre::Report> {
+    // listen before mapping, so that we always get to clean up
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    let mut maintainer = Maintainer::start(
+        &args.gateway,
+        &args.mappings,
+        &args.hooks,
+        &args.watch,
+        binding,
+    )
+    .await?;
+
+    event!(Level::INFO, public_ip = %maintainer.public_ip(), "Started");
+
+    loop {
+        tokio::select! {
+            _ = interrupt.recv() => break,
+            _ = terminate.recv() => break,
+            () = maintainer.step() => {},
+        }
+    }
+
+    event!(Level::INFO, "Stopping");
+
+    maintainer.stop().await;
+
+    Ok(ExitCode::SUCCESS)
+}
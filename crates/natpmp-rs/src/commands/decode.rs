@@ -0,0 +1,15 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre;
+use natpmp_rs::packet::{decode as decode_packet, parse_hex};
+
+use crate::cli::DecodeArgs;
+
+/// Pretty-prints the NAT-PMP or PCP packet given as hex, e.g. from a support ticket or a tcpdump.
+pub fn decode(args: &DecodeArgs) -> Result<ExitCode, eyre::Report> {
+    let datagram = parse_hex(&args.hex.join(""))?;
+
+    println!("{}", decode_packet(&datagram)?);
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,143 @@
+use std::num::NonZeroU16;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::diagnostics::{Diagnosis, MappingCheck};
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::get_gateway_addr;
+use natpmp_rs::retry::Rfc6886;
+
+use crate::cli::DoctorArgs;
+
+/// Attempts per request, unless `--retry` says otherwise. Enough to get through a lost packet, without waiting
+/// minutes for a gateway that will never answer.
+const DOCTOR_ATTEMPTS: u32 = 4;
+
+/// Finds out why NAT-PMP doesn't work: the gateway and route, whether the NAT-PMP port answers, the protocols
+/// the gateway speaks, its external address and epoch, and whether it maps ports, with round-trip times.
+///
+/// Exits with a failure when the gateway doesn't tell its external address or doesn't map ports.
+pub async fn doctor(args: DoctorArgs, binding: Binding) -> Result<ExitCode, eyre::Report> {
+    let retry_policy = Rfc6886::with_attempts(args.gateway.retry.unwrap_or(DOCTOR_ATTEMPTS));
+
+    let gateway_ip = match args.gateway.gateway {
+        Some(gateway_ip) => gateway_ip,
+        None => match args.gateway.gateway_ip(retry_policy, &binding).await {
+            Ok(gateway_ip) => {
+                println!("Discovery:      {} answered first", gateway_ip);
+
+                gateway_ip
+            },
+            Err(error) => {
+                println!("Discovery:      no candidate answered: {}", error);
+
+                // still worth a look, to tell why it doesn't answer
+                get_gateway_addr()?
+            },
+        },
+    };
+
+    let client = Client::new(gateway_ip)
+        .with_retry_policy(retry_policy)
+        .with_binding(binding);
+
+    let diagnosis = client.diagnose().await;
+
+    print_diagnosis(&diagnosis);
+
+    Ok(if diagnosis.is_healthy() {
+        println!("Verdict:        NAT-PMP works");
+
+        ExitCode::SUCCESS
+    } else {
+        println!("Verdict:        NAT-PMP doesn't work");
+
+        ExitCode::FAILURE
+    })
+}
+
+fn print_diagnosis(diagnosis: &Diagnosis) {
+    match diagnosis.interface() {
+        Some(interface) => println!(
+            "Gateway:        {}, default route via {}",
+            diagnosis.gateway(),
+            interface
+        ),
+        None => println!(
+            "Gateway:        {}, not on a default route",
+            diagnosis.gateway()
+        ),
+    }
+
+    if let Some(local_address) = diagnosis.local_address() {
+        println!("Local address:  {}", local_address);
+    }
+
+    println!("UDP 5351:       {}", diagnosis.port());
+
+    if let Some(natpmp) = diagnosis.natpmp() {
+        match natpmp.result() {
+            Ok(response) => println!(
+                "NAT-PMP:        yes, in {}, external address {} ({}), epoch {} s",
+                millis(natpmp.elapsed()),
+                response.ipv4_address(),
+                response.reachability(),
+                response.gateway_epoch().as_secs()
+            ),
+            Err(error) => println!("NAT-PMP:        no, {}", error),
+        }
+    }
+
+    if let Some(pcp) = diagnosis.pcp() {
+        match pcp.result() {
+            Ok(response) => println!(
+                "PCP:            yes, in {}, ANNOUNCE answered with {}, epoch {} s",
+                millis(pcp.elapsed()),
+                response.result(),
+                response.gateway_epoch().as_secs()
+            ),
+            Err(&NATPMPError::Unsupported { .. }) => println!("PCP:            no"),
+            Err(error) => println!("PCP:            no, {}", error),
+        }
+    }
+
+    for mapping in diagnosis.mappings() {
+        print_mapping(mapping);
+    }
+}
+
+fn print_mapping(mapping: &MappingCheck) {
+    let label = format!("{} mapping:", mapping.protocol());
+
+    match mapping.mapping().result() {
+        Ok(response) => {
+            println!(
+                "{:<15} yes, in {}, asked for port {}, granted {}{}",
+                label,
+                millis(mapping.mapping().elapsed()),
+                mapping.internal_port(),
+                response.external_port().map_or(0, NonZeroU16::get),
+                if mapping.is_different_port() {
+                    ", a different port"
+                } else {
+                    ""
+                }
+            );
+
+            if let Some(unmapping) = mapping.unmapping() {
+                match unmapping.result() {
+                    Ok(_) => println!("{:<15} deleted in {}", "", millis(unmapping.elapsed())),
+                    Err(error) => println!("{:<15} failed to delete: {}", "", error),
+                }
+            }
+        },
+        Err(error) => println!("{:<15} no, {}", label, error),
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{} ms", duration.as_millis())
+}
@@ -0,0 +1,148 @@
+use std::net::{SocketAddr, SocketAddrV4};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::reachability::Reachability;
+use natpmp_rs::stun::DEFAULT_STUN_PORT;
+use natpmp_rs::verify::{Verifier, VerifyOutcome, verify_tcp, verify_udp};
+
+use crate::cli::ProbeArgs;
+
+/// Seconds the mappings of `--verify` live, in case we don't get to delete them.
+const VERIFY_LIFETIME: u32 = 60;
+
+/// Reports the gateway's external address, and whether mappings on it can be reached from the internet.
+///
+/// With `--stun`, the external address is compared with the address a STUN server sees us as. With `--verify`,
+/// a TCP and a UDP port are mapped, and traffic is sent to their public address.
+///
+/// Exits with a failure when mappings can't be reached, the addresses differ, or the traffic didn't arrive, so that scripts can tell
+/// without parsing the report.
+pub async fn probe(args: ProbeArgs, binding: Binding) -> Result<ExitCode, eyre::Report> {
+    let client = args.gateway.client(binding).await?;
+    let gateway_ip = client.gateway_ip();
+
+    let report = client.probe_reachability(args.upstream).await?;
+    let external_address = report.external_address();
+    let reachability = report.reachability();
+
+    println!("Gateway:          {}", gateway_ip);
+    println!("External address: {}", external_address.ipv4_address());
+    println!(
+        "Gateway epoch:    {} s",
+        external_address.gateway_epoch().as_secs()
+    );
+    println!("Reachability:     {}", reachability);
+
+    match reachability {
+        Reachability::Public => {},
+        Reachability::CarrierGradeNat => println!(
+            "The ISP's carrier-grade NAT sits in front of the gateway, mappings are not reachable from the internet"
+        ),
+        Reachability::Private => println!(
+            "Another NAT sits in front of the gateway, mappings are not reachable from the internet unless it forwards them too"
+        ),
+        Reachability::Unroutable => {
+            println!("The gateway has no usable external address, is its uplink down?");
+        },
+    }
+
+    if let Some(upstream) = report.upstream() {
+        println!("Upstream:         {}", upstream);
+    }
+
+    if report.upstream().is_none() && reachability.is_double_nat() {
+        println!(
+            "Pass --upstream with the address of the NAT in front of the gateway to check whether it speaks PCP"
+        );
+    }
+
+    let stun_mismatch = match args.stun {
+        Some(ref stun_server) => {
+            let stun_server = resolve_stun_server(stun_server).await?;
+            let check = client.cross_check_stun(stun_server).await?;
+
+            println!("STUN:             {}", check);
+
+            check.is_mismatch()
+        },
+        None => false,
+    };
+
+    let verified = if args.verify {
+        verify(&client, &args).await?
+    } else {
+        true
+    };
+
+    Ok(if reachability.is_public() && !stun_mismatch && verified {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Maps a TCP and a UDP port, and checks that traffic to their public address arrives. Returns whether none of
+/// them was found to be unreachable.
+async fn verify(client: &Client, args: &ProbeArgs) -> Result<bool, eyre::Report> {
+    let verifier = args
+        .reflector
+        .map_or(Verifier::Hairpin, Verifier::Reflector);
+    let timeout = Duration::from_secs(args.verify_timeout);
+
+    let tcp = client.bind_public_tcp(None, Some(VERIFY_LIFETIME)).await?;
+    let tcp_outcome = verify_tcp(&tcp, verifier, timeout).await;
+
+    print_outcome(MappingProtocol::TCP, tcp.public_address(), &tcp_outcome);
+
+    let udp = client.bind_public_udp(None, Some(VERIFY_LIFETIME)).await?;
+    let udp_outcome = verify_udp(&udp, verifier, timeout).await;
+
+    print_outcome(MappingProtocol::UDP, udp.public_address(), &udp_outcome);
+
+    for guard in [tcp.into_parts().2, udp.into_parts().2] {
+        guard.release().await?;
+    }
+
+    Ok([tcp_outcome, udp_outcome].iter().all(|outcome| {
+        matches!(
+            *outcome,
+            Ok(VerifyOutcome::Reachable | VerifyOutcome::HairpinUnsupported)
+        )
+    }))
+}
+
+fn print_outcome(
+    protocol: MappingProtocol,
+    public_address: SocketAddrV4,
+    outcome: &Result<VerifyOutcome, std::io::Error>,
+) {
+    match *outcome {
+        Ok(outcome) => println!("Verify {}:       {}: {}", protocol, public_address, outcome),
+        Err(ref error) => println!(
+            "Verify {}:       {}: failed: {}",
+            protocol, public_address, error
+        ),
+    }
+}
+
+/// Resolves `HOST[:PORT]` to the first IPv4 address, as our requests go out over IPv4.
+async fn resolve_stun_server(stun_server: &str) -> Result<SocketAddrV4, eyre::Report> {
+    let with_port = if stun_server.contains(':') {
+        stun_server.to_owned()
+    } else {
+        format!("{}:{}", stun_server, DEFAULT_STUN_PORT)
+    };
+
+    tokio::net::lookup_host(&with_port)
+        .await?
+        .find_map(|address| match address {
+            SocketAddr::V4(address) => Some(address),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| eyre::eyre!("STUN server {} has no IPv4 address", stun_server))
+}
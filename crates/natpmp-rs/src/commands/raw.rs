@@ -0,0 +1,47 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::packet::{decode, parse_hex, to_hex};
+use natpmp_rs::retry::Rfc6886;
+
+use crate::cli::RawArgs;
+
+/// Attempts, unless `--retry` says otherwise. A hand-made packet may well never be answered, so we don't wait
+/// through the 9 attempts of RFC 6886.
+const RAW_ATTEMPTS: u32 = 3;
+
+/// Sends `--version`, `--opcode` and `--payload` to the gateway as a single datagram, and prints what was sent
+/// and what came back, decoded where possible.
+pub async fn raw(args: RawArgs, binding: Binding) -> Result<ExitCode, eyre::Report> {
+    let retry_policy = Rfc6886::with_attempts(args.gateway.retry.unwrap_or(RAW_ATTEMPTS));
+
+    let gateway_ip = args.gateway.gateway_ip(retry_policy, &binding).await?;
+
+    let mut datagram = vec![args.version, args.opcode];
+    datagram.extend(parse_hex(&args.payload)?);
+
+    println!("Sent to {}:", gateway_ip);
+    print_datagram(&datagram);
+
+    let client = Client::new(gateway_ip)
+        .with_retry_policy(retry_policy)
+        .with_binding(binding);
+
+    let reply = client.send_raw(&datagram).await?;
+
+    println!();
+    println!("Received after {} ms:", reply.round_trip().as_millis());
+    print_datagram(reply.datagram());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The decoded datagram, or its hex and why it can't be decoded.
+fn print_datagram(datagram: &[u8]) {
+    match decode(datagram) {
+        Ok(packet) => println!("{}", packet),
+        Err(error) => println!("{} ({})", to_hex(datagram), error),
+    }
+}
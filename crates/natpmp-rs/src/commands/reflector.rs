@@ -0,0 +1,19 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre;
+use natpmp_rs::verify::serve_reflector;
+use tokio::net::TcpListener;
+use tracing::{Level, event};
+
+use crate::cli::ReflectorArgs;
+
+/// Answers `probe --verify --reflector` requests until the listener fails, run it outside the NAT.
+pub async fn reflector(args: ReflectorArgs) -> Result<ExitCode, eyre::Report> {
+    let listener = TcpListener::bind(args.listen).await?;
+
+    event!(Level::INFO, listen = %args.listen, "Reflecting");
+
+    serve_reflector(listener).await?;
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,178 @@
+use std::io;
+use std::num::NonZeroU16;
+use std::os::unix::process::ExitStatusExt as _;
+use std::process::{ExitCode, ExitStatus};
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::capture::close_capture;
+use nix::sys::signal::{self, SigHandler, Signal, killpg, raise};
+use nix::unistd::Pid;
+use tokio::process::Command;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tracing::{Level, event};
+
+use crate::cli::RunArgs;
+use crate::maintainer::Maintainer;
+
+/// The signals we pass on to the child, so that it can shut down gracefully.
+const FORWARDED_SIGNALS: [SignalKind; 6] = [
+    SignalKind::hangup(),
+    SignalKind::interrupt(),
+    SignalKind::quit(),
+    SignalKind::terminate(),
+    SignalKind::user_defined1(),
+    SignalKind::user_defined2(),
+];
+
+/// Maps the requested ports, runs the command with the mappings exported in its environment
+/// and keeps the mappings alive while the command runs.
+///
+/// The command gets:
+/// * `NATPMP_PUBLIC_IP` - the public IP of the gateway
+/// * `NATPMP_TCP_<internal port>` / `NATPMP_UDP_<internal port>` - the granted external port
+///
+/// The command runs in its own process group, so that a Ctrl-C on the terminal reaches it once, through us, rather
+/// than twice. That also means it isn't in the foreground, and can't read from the terminal.
+///
+/// When the command exits all mappings are deleted, and its exit status is returned. When it was killed by a signal
+/// we die of the same signal.
+pub async fn run(args: RunArgs, binding: Binding) -> Result<ExitCode, eyre::Report> {
+    // listen before mapping, so that we don't miss any signal meant for the child
+    let signal_receiver = listen_for_signals()?;
+
+    let mut maintainer = Maintainer::start(
+        &args.gateway,
+        &args.mappings,
+        &args.hooks,
+        &args.watch,
+        binding,
+    )
+    .await?;
+
+    let result = run_with_mappings(&args, &mut maintainer, signal_receiver).await;
+
+    maintainer.stop().await;
+
+    let status = result?;
+
+    if let Some(signal) = status
+        .signal()
+        .and_then(|signal| Signal::try_from(signal).ok())
+    {
+        // dying skips the clean-up of `main()`
+        close_capture().await;
+
+        die_of(signal);
+    }
+
+    Ok(exit_code(status))
+}
+
+async fn run_with_mappings(
+    args: &RunArgs,
+    maintainer: &mut Maintainer,
+    mut signal_receiver: UnboundedReceiver<SignalKind>,
+) -> Result<ExitStatus, eyre::Report> {
+    let (program, program_args) = args
+        .command
+        .split_first()
+        .expect("Clap requires the command to be present");
+
+    let mut command = Command::new(program);
+
+    command
+        .args(program_args)
+        .process_group(0)
+        .env("NATPMP_PUBLIC_IP", maintainer.public_ip().to_string());
+
+    for mapping in maintainer.mappings() {
+        command.env(
+            format!("NATPMP_{}_{}", mapping.protocol(), mapping.internal_port()),
+            mapping
+                .external_port()
+                .map_or(0, NonZeroU16::get)
+                .to_string(),
+        );
+    }
+
+    let mut child = command.spawn()?;
+
+    // the child leads its process group, so the group has the child's pid
+    let child_group = child
+        .id()
+        .and_then(|id| i32::try_from(id).ok())
+        .map(Pid::from_raw);
+
+    loop {
+        tokio::select! {
+            status = child.wait() => {
+                let status = status?;
+
+                event!(Level::INFO, %status, "Command exited");
+
+                return Ok(status);
+            },
+            Some(kind) = signal_receiver.recv() => {
+                if let (Some(child_group), Ok(forwarded)) = (child_group, Signal::try_from(kind.as_raw_value())) {
+                    event!(Level::DEBUG, signal = %forwarded, "Forwarding signal to command");
+
+                    // to the whole group, like the terminal would have done
+                    if let Err(error) = killpg(child_group, forwarded) {
+                        event!(Level::ERROR, ?error, signal = %forwarded, "Failed to forward signal");
+                    }
+                }
+            },
+            () = maintainer.step() => {},
+        }
+    }
+}
+
+/// Installs handlers for `FORWARDED_SIGNALS`, which replaces their default action, and funnels them into one channel.
+fn listen_for_signals() -> Result<UnboundedReceiver<SignalKind>, io::Error> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    for kind in FORWARDED_SIGNALS {
+        let mut listener = signal(kind)?;
+        let sender = sender.clone();
+
+        tokio::task::spawn(async move {
+            while listener.recv().await.is_some() {
+                if sender.send(kind).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok(receiver)
+}
+
+/// Kills us with `signal`, so that our parent sees how the child really ended, e.g. a shell stopping a loop on Ctrl-C.
+/// Returns when `signal` doesn't terminate by default.
+fn die_of(signal: Signal) {
+    event!(Level::DEBUG, %signal, "Command was killed, re-raising the signal");
+
+    // SAFETY: we don't rely on our handler anymore, and the default action has no preconditions
+    if let Err(error) = unsafe { signal::signal(signal, SigHandler::SigDfl) } {
+        event!(Level::ERROR, ?error, %signal, "Failed to restore the default signal action");
+
+        return;
+    }
+
+    if let Err(error) = raise(signal) {
+        event!(Level::ERROR, ?error, %signal, "Failed to re-raise signal");
+    }
+}
+
+/// Converts the child's exit status into ours, using the shell convention of 128 + signal number
+/// when the child was killed by a signal that didn't kill us.
+fn exit_code(status: ExitStatus) -> ExitCode {
+    let code = status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1);
+
+    ExitCode::from(u8::try_from(code).unwrap_or(1))
+}
@@ -0,0 +1,18 @@
+use std::process::ExitCode;
+
+use color_eyre::eyre;
+use natpmp_rs::simulator::Simulator;
+use tracing::{Level, event};
+
+use crate::cli::SimulateArgs;
+
+/// Runs the simulated gateway until it fails, for `conformance` and other clients to be run against.
+pub async fn simulate(args: SimulateArgs) -> Result<ExitCode, eyre::Report> {
+    let simulator = Simulator::bind(args.listen, args.external_address).await?;
+
+    event!(Level::INFO, listen = %simulator.local_address()?, external_address = %args.external_address, "Simulating a NAT-PMP gateway");
+
+    simulator.run().await?;
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,177 @@
+use std::num::NonZeroU16;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::responses::MappingResponse;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::MissedTickBehavior;
+use tracing::{Level, event};
+
+use crate::cli::VpnArgs;
+use crate::hooks::{Change, Hooks};
+use crate::prometheus::{METRICS_REFRESH_INTERVAL, record_mappings};
+
+/// The order in which we request the mappings, the port of the first one is the one that is written to the port file.
+const PROTOCOLS: [MappingProtocol; 2] = [MappingProtocol::UDP, MappingProtocol::TCP];
+
+/// Requests a port from a VPN provider's gateway, and keeps renewing it until `SIGINT` or `SIGTERM`.
+///
+/// Providers ignore the requested public port and hand out one of their own, which can change
+/// at any renewal. Whenever it does, the port is written to the port file and the hooks are run.
+/// The very first port is reported as a change from port 0.
+pub async fn vpn(args: VpnArgs, binding: Binding) -> Result<ExitCode, eyre::Report> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    let client = args.gateway.client(binding).await?;
+
+    let hooks = Hooks::spawn(&args.hooks)?;
+
+    // the last mapping we were granted, per protocol in `PROTOCOLS`
+    let mut mappings = [const { None }; PROTOCOLS.len()];
+
+    let mut renewal = tokio::time::interval(Duration::from_secs(args.renew_interval));
+    renewal.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut metrics_refresh = tokio::time::interval(METRICS_REFRESH_INTERVAL);
+    metrics_refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        // the renewal is cancelled when we're asked to stop, rather than waiting for its requests to time out
+        tokio::select! {
+            _ = interrupt.recv() => break,
+            _ = terminate.recv() => break,
+            () = async {
+                tokio::select! {
+                    _ = renewal.tick() => renew(&args, &client, &hooks, &mut mappings).await,
+                    _ = metrics_refresh.tick() => {},
+                }
+            } => {
+                record_mappings(mappings.iter().flatten());
+            },
+        }
+    }
+
+    event!(Level::INFO, "Stopping");
+
+    for protocol in PROTOCOLS {
+        if let Err(error) = client.unmap(protocol, args.internal_port).await {
+            event!(Level::ERROR, ?error, %protocol, "Failed to unmap port");
+        }
+    }
+
+    // the port is no longer ours, don't let anyone pick it up
+    if let Some(port_file) = args.port_file.as_deref()
+        && let Err(error) = tokio::fs::remove_file(port_file).await
+    {
+        event!(Level::WARN, ?error, port_file = %port_file.display(), "Failed to remove port file");
+    }
+
+    record_mappings([]);
+
+    hooks.shutdown().await;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Requests the port for every protocol, and reports the ones that changed. A mapping that fails to renew keeps
+/// its previous response.
+async fn renew(
+    args: &VpnArgs,
+    client: &Client,
+    hooks: &Hooks,
+    mappings: &mut [Option<MappingResponse>; PROTOCOLS.len()],
+) {
+    let forwarded_port = external_port(mappings[0].as_ref());
+
+    for (&protocol, mapping) in PROTOCOLS.iter().zip(mappings.iter_mut()) {
+        let Some(granted) = request_port(args, client, protocol).await else {
+            continue;
+        };
+
+        let old = external_port(mapping.as_ref());
+        let new = external_port(Some(&granted));
+
+        if new != old {
+            hooks.notify(Change::ExternalPort {
+                gateway: client.gateway_ip(),
+                protocol,
+                internal_port: args.internal_port,
+                old,
+                new,
+            });
+        }
+
+        *mapping = Some(granted);
+    }
+
+    let external_ports = mappings.each_ref().map(Option::as_ref).map(external_port);
+
+    if external_ports.iter().any(|&port| port != external_ports[0]) {
+        event!(
+            Level::WARN,
+            ?external_ports,
+            "Gateway forwarded different ports per protocol"
+        );
+    }
+
+    if let Some(port_file) = args.port_file.as_deref()
+        && external_ports[0] != forwarded_port
+    {
+        write_port_file(port_file, external_ports[0]).await;
+    }
+}
+
+/// The external port of `mapping`, 0 when there is none.
+fn external_port(mapping: Option<&MappingResponse>) -> u16 {
+    mapping
+        .and_then(MappingResponse::external_port)
+        .map_or(0, NonZeroU16::get)
+}
+
+async fn request_port(
+    args: &VpnArgs,
+    client: &Client,
+    protocol: MappingProtocol,
+) -> Option<MappingResponse> {
+    match client
+        .map_once(
+            protocol,
+            args.internal_port,
+            Some(args.public_port),
+            args.lifetime,
+        )
+        .await
+    {
+        Ok(response) => {
+            event!(Level::DEBUG, %response, "Renewed forwarded port");
+
+            Some(response)
+        },
+        Err(error) => {
+            event!(Level::ERROR, ?error, %protocol, "Failed to request forwarded port");
+
+            None
+        },
+    }
+}
+
+/// Writes the port to a temporary file first, so that readers never see a partially written file.
+async fn write_port_file(port_file: &Path, port: u16) {
+    let temporary = port_file.with_extension("tmp");
+
+    let result = async {
+        tokio::fs::write(&temporary, format!("{}\n", port)).await?;
+        tokio::fs::rename(&temporary, port_file).await
+    }
+    .await;
+
+    if let Err(error) = result {
+        event!(Level::ERROR, ?error, port_file = %port_file.display(), "Failed to write port file");
+    }
+}
@@ -0,0 +1,654 @@
+//! Checks a gateway against what RFC 6886 requires of it, with well-formed and deliberately malformed requests.
+//!
+//! Test mappings are made for ports that are free on this host, with a short lifetime, and deleted again.
+
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf as _, BufMut as _};
+use tokio::net::UdpSocket;
+use zerocopy::IntoBytes as _;
+
+use crate::binding::Binding;
+use crate::capture::Direction;
+use crate::diagnostics::{hold_free_port, record};
+use crate::protocol::MappingProtocol;
+use crate::requests::external_address_request::ExternalAddressRequest;
+use crate::requests::mapping_request::MappingRequest;
+use crate::requests::unmap_all_request::UnmapAllPortsRequest;
+use crate::requests::unmap_request::UnmapPortRequest;
+use crate::responses::epoch_regressed;
+use crate::retry::{Attempts, RetryPolicy};
+use crate::{NATPMP_PORT, VERSION};
+
+/// Seconds the test mappings live, in case deleting them fails.
+const TEST_MAPPING_LIFETIME: u32 = 60;
+
+/// The largest result code RFC 6886 defines.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.5>
+const MAX_RESULT_CODE: u16 = 5;
+
+/// Whether a gateway did what the RFC requires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    /// With what it did instead
+    Fail(String),
+}
+
+/// A single requirement of the RFC, and whether the gateway met it.
+#[derive(Debug)]
+pub struct Check {
+    name: &'static str,
+    section: &'static str,
+    outcome: Outcome,
+}
+
+impl Check {
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The section of RFC 6886 that requires it
+    #[must_use]
+    pub fn section(&self) -> &'static str {
+        self.section
+    }
+
+    #[must_use]
+    pub fn outcome(&self) -> &Outcome {
+        &self.outcome
+    }
+
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Pass
+    }
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.outcome {
+            Outcome::Pass => write!(f, "PASS {} (section {})", self.name, self.section),
+            Outcome::Fail(ref reason) => write!(
+                f,
+                "FAIL {} (section {}): {}",
+                self.name, self.section, reason
+            ),
+        }
+    }
+}
+
+/// What `Client::check_conformance()` found.
+#[derive(Debug)]
+pub struct ConformanceReport {
+    gateway: Ipv4Addr,
+    checks: Vec<Check>,
+}
+
+impl ConformanceReport {
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    /// In the order they ran. Only the first when the gateway doesn't tell its external address, without "unmap all"
+    /// unless the checks were destructive.
+    #[must_use]
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    /// The check called `name`, if it ran
+    #[must_use]
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+
+    #[must_use]
+    pub fn passed(&self) -> usize {
+        self.checks.iter().filter(|check| check.passed()).count()
+    }
+
+    /// Whether every check passed
+    #[must_use]
+    pub fn is_conformant(&self) -> bool {
+        self.checks.iter().all(Check::passed)
+    }
+
+    fn push(&mut self, name: &'static str, section: &'static str, result: Result<(), String>) {
+        self.checks.push(Check {
+            name,
+            section,
+            outcome: match result {
+                Ok(()) => Outcome::Pass,
+                Err(reason) => Outcome::Fail(reason),
+            },
+        });
+    }
+}
+
+impl std::fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{}", check)?;
+        }
+
+        write!(
+            f,
+            "{} of {} checks passed for {}",
+            self.passed(),
+            self.checks.len(),
+            self.gateway
+        )
+    }
+}
+
+/// A response, and the opcode of the request it answers.
+struct Exchange {
+    request_opcode: u8,
+    response: Vec<u8>,
+    received_at: Instant,
+}
+
+/// The common response header.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.5>
+struct Header {
+    version: u8,
+    opcode: u8,
+    result_code: u16,
+    seconds_since_epoch: u32,
+}
+
+impl Header {
+    /// `None` when the response is too short to hold one
+    fn parse(mut response: &[u8]) -> Option<Header> {
+        (response.len() >= 8).then(|| Header {
+            version: response.get_u8(),
+            opcode: response.get_u8(),
+            result_code: response.get_u16(),
+            seconds_since_epoch: response.get_u32(),
+        })
+    }
+}
+
+/// The fields of a mapping response after the header.
+struct PortFields {
+    internal_port: u16,
+    external_port: u16,
+    lifetime: u32,
+}
+
+impl PortFields {
+    fn parse(response: &[u8]) -> Option<PortFields> {
+        let mut fields = response.get(8..16)?;
+
+        Some(PortFields {
+            internal_port: fields.get_u16(),
+            external_port: fields.get_u16(),
+            lifetime: fields.get_u32(),
+        })
+    }
+}
+
+/// A connected socket, so that only the gateway's responses arrive, and every response it received.
+struct Session<'policy> {
+    socket: UdpSocket,
+    retry_policy: &'policy dyn RetryPolicy,
+    exchanges: Vec<Exchange>,
+}
+
+impl Session<'_> {
+    /// Sends `request` until something comes back, `None` when nothing does.
+    async fn exchange(&mut self, request: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let mut buffer = [0; 1100];
+
+        // late answers to the previous request would be taken for answers to this one
+        while self.socket.try_recv(&mut buffer).is_ok() {}
+
+        let mut attempts = Attempts::new(self.retry_policy);
+
+        while let Some((_tries, wait_until)) = attempts.next() {
+            self.socket
+                .send(request)
+                .await
+                .map_err(|error| format!("Failed to send: {}", error))?;
+
+            record(&self.socket, Direction::Sent, request);
+
+            match tokio::time::timeout_at(wait_until, self.socket.recv(&mut buffer)).await {
+                Ok(Ok(size)) => {
+                    let response = buffer[..size].to_vec();
+
+                    record(&self.socket, Direction::Received, &response);
+
+                    self.exchanges.push(Exchange {
+                        request_opcode: request.get(1).copied().unwrap_or_default(),
+                        response: response.clone(),
+                        received_at: Instant::now(),
+                    });
+
+                    return Ok(Some(response));
+                },
+                Ok(Err(error)) if error.kind() == ErrorKind::ConnectionRefused => {
+                    return Err(String::from("Refused, nothing listens on the NAT-PMP port"));
+                },
+                Ok(Err(error)) => return Err(format!("Failed to receive: {}", error)),
+                Err(_) => {},
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Sends `request` and expects an answer with its opcode + 128 and `result_code`.
+    async fn expect(
+        &mut self,
+        request: &[u8],
+        result_code: u16,
+    ) -> Result<(Header, Vec<u8>), String> {
+        let opcode = request.get(1).copied().unwrap_or_default();
+
+        let Some(response) = self.exchange(request).await? else {
+            return Err(String::from("No response"));
+        };
+
+        let Some(header) = Header::parse(&response) else {
+            return Err(format!(
+                "Response of {} bytes is shorter than a header",
+                response.len()
+            ));
+        };
+
+        if header.opcode != opcode + 128 {
+            return Err(format!(
+                "Response opcode {} instead of {}",
+                header.opcode,
+                opcode + 128
+            ));
+        }
+
+        if header.result_code != result_code {
+            return Err(format!(
+                "Result code {} instead of {}",
+                header.result_code, result_code
+            ));
+        }
+
+        Ok((header, response))
+    }
+
+    /// Sends `request` and expects nothing back, or at least no success.
+    async fn expect_no_success(&mut self, request: &[u8]) -> Result<(), String> {
+        match self.exchange(request).await? {
+            None => Ok(()),
+            Some(response) => match Header::parse(&response) {
+                Some(header) if header.result_code != 0 => Ok(()),
+                Some(_) | None => Err(format!(
+                    "Answered a {} byte request with {:02x?}",
+                    request.len(),
+                    response
+                )),
+            },
+        }
+    }
+}
+
+/// Runs the checks, and with `destructive` also the ones that delete mappings other than its own.
+pub(crate) async fn run(
+    gateway: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+    destructive: bool,
+) -> ConformanceReport {
+    let mut report = ConformanceReport {
+        gateway,
+        checks: vec![],
+    };
+
+    let socket = match connect(gateway, binding).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            report.push(
+                "external address",
+                "3.2",
+                Err(format!("Failed to set up the socket: {}", error)),
+            );
+
+            return report;
+        },
+    };
+
+    let mut session = Session {
+        socket,
+        retry_policy,
+        exchanges: vec![],
+    };
+
+    let external_address = check_external_address(&mut session).await;
+    let answered = external_address.is_ok();
+
+    report.push("external address", "3.2", external_address);
+
+    // every other check would wait for it to answer, in vain
+    if !answered {
+        return report;
+    }
+
+    report.push(
+        "unsupported version",
+        "3.5",
+        check_unsupported_version(&mut session).await,
+    );
+    report.push(
+        "unknown opcode",
+        "3.5",
+        check_unknown_opcode(&mut session).await,
+    );
+    report.push(
+        "responses are ignored",
+        "3.5",
+        check_responses_ignored(&mut session).await,
+    );
+    report.push(
+        "truncated requests",
+        "3.3",
+        check_truncated(&mut session).await,
+    );
+    report.push(
+        "mapping and lifetime 0 deletion",
+        "3.3, 3.4",
+        check_map_and_delete(&mut session).await,
+    );
+
+    // deletes every TCP mapping of this host, also other programs'
+    if destructive {
+        report.push("unmap all", "3.4", check_unmap_all(&mut session).await);
+    }
+
+    // one last response for the epoch to be compared with
+    let last = session
+        .expect(ExternalAddressRequest::new().as_bytes(), 0)
+        .await
+        .map(|_| ());
+
+    report.push(
+        "epoch monotonicity",
+        "3.6",
+        last.and_then(|()| check_epochs(&session.exchanges)),
+    );
+    report.push(
+        "response opcode is request opcode + 128",
+        "3.5",
+        check_response_opcodes(&session.exchanges),
+    );
+    report.push(
+        "result code layout",
+        "3.5",
+        check_result_codes(&session.exchanges),
+    );
+
+    report
+}
+
+async fn connect(gateway: Ipv4Addr, binding: &Binding) -> Result<UdpSocket, std::io::Error> {
+    let socket = UdpSocket::from_std(binding.socket().await?)?;
+
+    socket.connect((gateway, NATPMP_PORT)).await?;
+
+    Ok(socket)
+}
+
+async fn check_external_address(session: &mut Session<'_>) -> Result<(), String> {
+    let (_header, response) = session
+        .expect(ExternalAddressRequest::new().as_bytes(), 0)
+        .await?;
+
+    if response.len() != 12 {
+        return Err(format!(
+            "Response of {} bytes instead of 12",
+            response.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Any version but 0 is answered with result code 1.
+async fn check_unsupported_version(session: &mut Session<'_>) -> Result<(), String> {
+    let (header, _response) = session.expect(&[1, 0], 1).await?;
+
+    if header.version != VERSION {
+        return Err(format!("Response version {} instead of 0", header.version));
+    }
+
+    Ok(())
+}
+
+/// Opcodes below 128 other than 0, 1 and 2 are answered with result code 5.
+async fn check_unknown_opcode(session: &mut Session<'_>) -> Result<(), String> {
+    session.expect(&[VERSION, 3], 5).await.map(|_| ())
+}
+
+/// Opcodes of 128 and above are responses, which a gateway must not answer.
+async fn check_responses_ignored(session: &mut Session<'_>) -> Result<(), String> {
+    let mut response = vec![];
+    response.put_u8(VERSION);
+    response.put_u8(128);
+    response.put_u16(0);
+    response.put_u32(0);
+    response.put_u32(0);
+
+    match session.exchange(&response).await? {
+        None => Ok(()),
+        Some(answer) => Err(format!("Answered a response with {:02x?}", answer)),
+    }
+}
+
+/// A request cut short must not create a mapping.
+async fn check_truncated(session: &mut Session<'_>) -> Result<(), String> {
+    let (_held, port) = hold_free_port(MappingProtocol::UDP)
+        .map_err(|error| format!("Failed to find a free port: {}", error))?;
+
+    let request = MappingRequest::new(
+        MappingProtocol::UDP,
+        port,
+        port.get(),
+        TEST_MAPPING_LIFETIME,
+    );
+
+    session.expect_no_success(&[VERSION]).await?;
+    session.expect_no_success(&request.as_bytes()[..8]).await
+}
+
+/// A mapping is granted, and deleted by a request with lifetime 0.
+async fn check_map_and_delete(session: &mut Session<'_>) -> Result<(), String> {
+    let (_held, port) = hold_free_port(MappingProtocol::UDP)
+        .map_err(|error| format!("Failed to find a free port: {}", error))?;
+
+    let fields = map(session, MappingProtocol::UDP, port).await?;
+
+    if fields.external_port == 0 || fields.lifetime == 0 {
+        return Err(format!(
+            "Granted external port {} with lifetime {}",
+            fields.external_port, fields.lifetime
+        ));
+    }
+
+    let (_header, response) = session
+        .expect(
+            UnmapPortRequest::new(MappingProtocol::UDP, port).as_bytes(),
+            0,
+        )
+        .await
+        .map_err(|reason| format!("Deleting: {}", reason))?;
+
+    expect_port_fields(&response, port.get(), 0, 0)
+}
+
+/// A request with internal port, external port and lifetime 0 deletes all of the client's mappings.
+async fn check_unmap_all(session: &mut Session<'_>) -> Result<(), String> {
+    let (_first_held, first) = hold_free_port(MappingProtocol::TCP)
+        .map_err(|error| format!("Failed to find a free port: {}", error))?;
+    let (_second_held, second) = hold_free_port(MappingProtocol::TCP)
+        .map_err(|error| format!("Failed to find a free port: {}", error))?;
+
+    map(session, MappingProtocol::TCP, first).await?;
+    map(session, MappingProtocol::TCP, second).await?;
+
+    let (_header, response) = session
+        .expect(
+            UnmapAllPortsRequest::new(MappingProtocol::TCP).as_bytes(),
+            0,
+        )
+        .await
+        .map_err(|reason| format!("Deleting all: {}", reason))?;
+
+    expect_port_fields(&response, 0, 0, 0)
+}
+
+async fn map(
+    session: &mut Session<'_>,
+    protocol: MappingProtocol,
+    port: NonZeroU16,
+) -> Result<PortFields, String> {
+    let request = MappingRequest::new(protocol, port, port.get(), TEST_MAPPING_LIFETIME);
+
+    let (_header, response) = session
+        .expect(request.as_bytes(), 0)
+        .await
+        .map_err(|reason| format!("Mapping {} port {}: {}", protocol, port, reason))?;
+
+    let fields = PortFields::parse(&response)
+        .ok_or_else(|| format!("Mapping response of {} bytes instead of 16", response.len()))?;
+
+    if fields.internal_port != port.get() {
+        return Err(format!(
+            "Mapping response for internal port {} instead of {}",
+            fields.internal_port, port
+        ));
+    }
+
+    Ok(fields)
+}
+
+fn expect_port_fields(
+    response: &[u8],
+    internal_port: u16,
+    external_port: u16,
+    lifetime: u32,
+) -> Result<(), String> {
+    let fields = PortFields::parse(response)
+        .ok_or_else(|| format!("Response of {} bytes instead of 16", response.len()))?;
+
+    if (fields.internal_port, fields.external_port, fields.lifetime)
+        != (internal_port, external_port, lifetime)
+    {
+        return Err(format!(
+            "Response with internal port {}, external port {} and lifetime {}, instead of {}, {} and {}",
+            fields.internal_port,
+            fields.external_port,
+            fields.lifetime,
+            internal_port,
+            external_port,
+            lifetime
+        ));
+    }
+
+    Ok(())
+}
+
+/// The epoch never goes back, and keeps up with our clock, see `epoch_regressed()`.
+fn check_epochs(exchanges: &[Exchange]) -> Result<(), String> {
+    let epochs = exchanges
+        .iter()
+        .filter_map(|exchange| {
+            Header::parse(&exchange.response)
+                .map(|header| (header.seconds_since_epoch, exchange.received_at))
+        })
+        .collect::<Vec<_>>();
+
+    for pair in epochs.windows(2) {
+        let [(previous, previous_at), (epoch, received_at)] = *pair else {
+            continue;
+        };
+
+        let elapsed = received_at.duration_since(previous_at);
+
+        if epoch_regressed(
+            Duration::from_secs(u64::from(previous)),
+            Duration::from_secs(u64::from(epoch)),
+            elapsed,
+        ) {
+            return Err(format!(
+                "Epoch went from {} to {} in {} seconds",
+                previous,
+                epoch,
+                elapsed.as_secs()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_response_opcodes(exchanges: &[Exchange]) -> Result<(), String> {
+    for exchange in exchanges {
+        let opcode = exchange.response.get(1).copied().unwrap_or_default();
+
+        if u16::from(opcode) != u16::from(exchange.request_opcode) + 128 {
+            return Err(format!(
+                "Answered opcode {} with opcode {}",
+                exchange.request_opcode, opcode
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Every response starts with version 0, the opcode, a 16-bit result code that the RFC defines, and the epoch.
+/// Successful responses carry the fields of their opcode.
+fn check_result_codes(exchanges: &[Exchange]) -> Result<(), String> {
+    for exchange in exchanges {
+        let Some(header) = Header::parse(&exchange.response) else {
+            return Err(format!(
+                "Response of {} bytes to opcode {} is shorter than a header",
+                exchange.response.len(),
+                exchange.request_opcode
+            ));
+        };
+
+        if header.version != VERSION {
+            return Err(format!("Response version {}", header.version));
+        }
+
+        if header.result_code > MAX_RESULT_CODE {
+            return Err(format!(
+                "Result code {} is not defined, or not in network byte order",
+                header.result_code
+            ));
+        }
+
+        let size = match (exchange.request_opcode, header.result_code) {
+            (0, 0) => 12,
+            (1 | 2, 0) => 16,
+            _ => 8,
+        };
+
+        if exchange.response.len() < size {
+            return Err(format!(
+                "Response of {} bytes to opcode {} with result code {}, instead of {}",
+                exchange.response.len(),
+                exchange.request_opcode,
+                header.result_code,
+                size
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,390 @@
+//! Everything we can find out about a gateway, for when NAT-PMP "doesn't work".
+
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::NonZeroU16;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use zerocopy::IntoBytes as _;
+
+use crate::capture::{self, Direction};
+use crate::client::Client;
+use crate::errors::NATPMPError;
+use crate::pcp::{PcpResponse, request_announce};
+use crate::protocol::MappingProtocol;
+use crate::requests::external_address_request::ExternalAddressRequest;
+use crate::responses::{ExternalAddressResponse, MappingResponse};
+use crate::retry::Attempts;
+use crate::{
+    NATPMP_PORT, default_routes, request_external_address, request_mapping, request_unmap,
+};
+
+/// Seconds the test mappings live, in case deleting them fails.
+const TEST_MAPPING_LIFETIME: u32 = 30;
+
+/// What came back from the NAT-PMP port.
+#[derive(Debug)]
+pub enum PortCheck {
+    /// Something answered, after this long
+    Answered(Duration),
+    /// ICMP port unreachable: the gateway is there, but nothing listens on the NAT-PMP port
+    Refused,
+    /// Another error, e.g. ICMP host unreachable
+    Failed(io::Error),
+    /// Nothing at all, e.g. because a firewall drops the requests
+    Silent,
+}
+
+impl std::fmt::Display for PortCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            PortCheck::Answered(round_trip) => {
+                write!(f, "answered in {} ms", round_trip.as_millis())
+            },
+            PortCheck::Refused => write!(f, "refused, nothing listens on UDP {}", NATPMP_PORT),
+            PortCheck::Failed(ref error) => write!(f, "failed: {}", error),
+            PortCheck::Silent => write!(f, "no answer"),
+        }
+    }
+}
+
+/// The result of a request, and how long it took, including retries.
+#[derive(Debug)]
+pub struct Timed<T> {
+    result: Result<T, NATPMPError>,
+    elapsed: Duration,
+}
+
+impl<T> Timed<T> {
+    async fn measure<F: Future<Output = Result<T, NATPMPError>>>(request: F) -> Timed<T> {
+        let started_at = Instant::now();
+        let result = request.await;
+
+        Timed {
+            result,
+            elapsed: started_at.elapsed(),
+        }
+    }
+
+    /// # Errors
+    /// When the request failed
+    pub fn result(&self) -> Result<&T, &NATPMPError> {
+        self.result.as_ref()
+    }
+
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+/// A test mapping of a free local port.
+#[derive(Debug)]
+pub struct MappingCheck {
+    protocol: MappingProtocol,
+    internal_port: NonZeroU16,
+    mapping: Timed<MappingResponse>,
+    unmapping: Option<Timed<MappingResponse>>,
+}
+
+impl MappingCheck {
+    #[must_use]
+    pub fn protocol(&self) -> MappingProtocol {
+        self.protocol
+    }
+
+    /// The internal port, which is also the external port we asked for
+    #[must_use]
+    pub fn internal_port(&self) -> NonZeroU16 {
+        self.internal_port
+    }
+
+    #[must_use]
+    pub fn mapping(&self) -> &Timed<MappingResponse> {
+        &self.mapping
+    }
+
+    /// Deleting the mapping again, `None` when mapping failed
+    #[must_use]
+    pub fn unmapping(&self) -> Option<&Timed<MappingResponse>> {
+        self.unmapping.as_ref()
+    }
+
+    /// Whether the gateway granted another external port than the one we asked for
+    #[must_use]
+    pub fn is_different_port(&self) -> bool {
+        self.mapping
+            .result()
+            .is_ok_and(MappingResponse::is_different_port)
+    }
+}
+
+/// What `Client::diagnose()` found out.
+#[derive(Debug)]
+pub struct Diagnosis {
+    gateway: Ipv4Addr,
+    interface: Option<String>,
+    local_address: Option<Ipv4Addr>,
+    port: PortCheck,
+    natpmp: Option<Timed<ExternalAddressResponse>>,
+    pcp: Option<Timed<PcpResponse>>,
+    mappings: Vec<MappingCheck>,
+}
+
+impl Diagnosis {
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    /// The interface of the default route through the gateway, `None` when no default route goes through it
+    #[must_use]
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.as_deref()
+    }
+
+    /// The address our requests leave from
+    #[must_use]
+    pub fn local_address(&self) -> Option<Ipv4Addr> {
+        self.local_address
+    }
+
+    #[must_use]
+    pub fn port(&self) -> &PortCheck {
+        &self.port
+    }
+
+    /// The external address request, `None` when nothing answered on the NAT-PMP port
+    #[must_use]
+    pub fn natpmp(&self) -> Option<&Timed<ExternalAddressResponse>> {
+        self.natpmp.as_ref()
+    }
+
+    /// A PCP ANNOUNCE, `None` when nothing answered on the NAT-PMP port
+    #[must_use]
+    pub fn pcp(&self) -> Option<&Timed<PcpResponse>> {
+        self.pcp.as_ref()
+    }
+
+    /// A TCP and a UDP test mapping, empty when the gateway doesn't speak NAT-PMP
+    #[must_use]
+    pub fn mappings(&self) -> &[MappingCheck] {
+        &self.mappings
+    }
+
+    /// Whether the gateway tells its external address and maps ports.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.natpmp
+            .as_ref()
+            .is_some_and(|natpmp| natpmp.result.is_ok())
+            && !self.mappings.is_empty()
+            && self
+                .mappings
+                .iter()
+                .all(|mapping| mapping.mapping.result.is_ok())
+    }
+}
+
+pub(crate) async fn diagnose(client: &Client) -> Diagnosis {
+    let gateway = client.gateway_ip();
+
+    let interface = default_routes()
+        .into_iter()
+        .find(|&(_, route_gateway)| route_gateway == gateway)
+        .map(|(interface, _)| interface);
+
+    let (port, local_address) = check_port(client).await;
+
+    let mut diagnosis = Diagnosis {
+        gateway,
+        interface,
+        local_address,
+        port,
+        natpmp: None,
+        pcp: None,
+        mappings: vec![],
+    };
+
+    if !matches!(diagnosis.port, PortCheck::Answered(_)) {
+        return diagnosis;
+    }
+
+    let natpmp = Timed::measure(request_external_address(
+        gateway,
+        client.retry_policy(),
+        client.binding(),
+    ))
+    .await;
+
+    diagnosis.pcp = Some(
+        Timed::measure(request_announce(
+            gateway,
+            local_address.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            client.retry_policy(),
+            client.binding(),
+        ))
+        .await,
+    );
+
+    if natpmp.result.is_ok() {
+        for protocol in [MappingProtocol::TCP, MappingProtocol::UDP] {
+            diagnosis
+                .mappings
+                .push(check_mapping(client, protocol).await);
+        }
+    }
+
+    diagnosis.natpmp = Some(natpmp);
+
+    diagnosis
+}
+
+/// Sends an external address request over a connected socket, as only those are told about ICMP errors.
+async fn check_port(client: &Client) -> (PortCheck, Option<Ipv4Addr>) {
+    let socket = match connect(client).await {
+        Ok(socket) => socket,
+        Err(error) => return (PortCheck::Failed(error), None),
+    };
+
+    let local_address = match socket.local_addr() {
+        Ok(SocketAddr::V4(local_address)) => Some(*local_address.ip()),
+        Ok(SocketAddr::V6(_)) | Err(_) => None,
+    };
+
+    let request = ExternalAddressRequest::new();
+    let mut attempts = Attempts::new(client.retry_policy());
+    let mut buffer = [0; 16];
+
+    while let Some((_tries, wait_until)) = attempts.next() {
+        let sent_at = Instant::now();
+
+        if let Err(error) = socket.send(request.as_bytes()).await {
+            return (port_error(error), local_address);
+        }
+
+        record(&socket, Direction::Sent, request.as_bytes());
+
+        match tokio::time::timeout_at(wait_until, socket.recv(&mut buffer)).await {
+            Ok(Ok(size)) => {
+                record(&socket, Direction::Received, &buffer[..size]);
+
+                return (PortCheck::Answered(sent_at.elapsed()), local_address);
+            },
+            Ok(Err(error)) => return (port_error(error), local_address),
+            Err(_) => {},
+        }
+    }
+
+    (PortCheck::Silent, local_address)
+}
+
+async fn connect(client: &Client) -> Result<UdpSocket, io::Error> {
+    let socket = UdpSocket::from_std(client.binding().socket().await?)?;
+
+    socket.connect((client.gateway_ip(), NATPMP_PORT)).await?;
+
+    Ok(socket)
+}
+
+/// Captures a datagram of a connected socket.
+pub(crate) fn record(socket: &UdpSocket, direction: Direction, datagram: &[u8]) {
+    if let Ok(peer) = socket.peer_addr() {
+        capture::record(direction, socket.local_addr().ok(), peer, datagram, None);
+    }
+}
+
+fn port_error(error: io::Error) -> PortCheck {
+    if error.kind() == ErrorKind::ConnectionRefused {
+        PortCheck::Refused
+    } else {
+        PortCheck::Failed(error)
+    }
+}
+
+async fn check_mapping(client: &Client, protocol: MappingProtocol) -> MappingCheck {
+    // a port that is free right now, held while we map it so that nothing else takes it
+    let (_held, internal_port) = match hold_free_port(protocol) {
+        Ok(held) => held,
+        Err(error) => {
+            return MappingCheck {
+                protocol,
+                internal_port: NonZeroU16::MIN,
+                mapping: Timed {
+                    result: Err(error.into()),
+                    elapsed: Duration::ZERO,
+                },
+                unmapping: None,
+            };
+        },
+    };
+
+    let mapping = Timed::measure(request_mapping(
+        protocol,
+        internal_port,
+        Some(internal_port),
+        TEST_MAPPING_LIFETIME,
+        client.gateway_ip(),
+        client.retry_policy(),
+        client.binding(),
+    ))
+    .await;
+
+    let unmapping = if mapping.result.is_ok() {
+        Some(
+            Timed::measure(request_unmap(
+                protocol,
+                internal_port,
+                client.gateway_ip(),
+                client.retry_policy(),
+                client.binding(),
+            ))
+            .await,
+        )
+    } else {
+        None
+    };
+
+    MappingCheck {
+        protocol,
+        internal_port,
+        mapping,
+        unmapping,
+    }
+}
+
+#[expect(dead_code, reason = "Only held, so that nothing else takes the port")]
+pub(crate) enum HeldPort {
+    Tcp(std::net::TcpListener),
+    Udp(std::net::UdpSocket),
+}
+
+/// Binds a free port of `protocol`, for test mappings.
+pub(crate) fn hold_free_port(
+    protocol: MappingProtocol,
+) -> Result<(HeldPort, NonZeroU16), io::Error> {
+    let (held, local_address) = match protocol {
+        MappingProtocol::TCP => {
+            let listener = std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            let local_address = listener.local_addr()?;
+
+            (HeldPort::Tcp(listener), local_address)
+        },
+        MappingProtocol::UDP => {
+            let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            let local_address = socket.local_addr()?;
+
+            (HeldPort::Udp(socket), local_address)
+        },
+    };
+
+    let port = NonZeroU16::new(local_address.port()).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::AddrNotAvailable,
+            "Socket was bound without a port",
+        )
+    })?;
+
+    Ok((held, port))
+}
@@ -0,0 +1,236 @@
+//! Finding the NAT-PMP gateway when there are several candidates, e.g. a LAN router and a VPN server.
+
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
+use nix::sys::socket::SockaddrIn;
+use tokio::task::JoinSet;
+use tracing::{Level, event};
+
+use crate::binding::Binding;
+use crate::errors::NATPMPError;
+use crate::retry::{RetryPolicy, Rfc6886};
+use crate::{get_gateway_addrs, request_public_address};
+
+/// How long a discovered gateway is used before racing the candidates again, unless told otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_mins(5);
+
+/// The gateway `discover_gateway()` found last, the binding it was found through, and until when to use it, `None`
+/// for forever.
+static DISCOVERED: Mutex<Option<(Binding, Ipv4Addr, Option<Instant>)>> = Mutex::new(None);
+
+/// The addresses that might be a NAT-PMP gateway, in order of preference.
+#[derive(Debug, Clone, Default)]
+pub struct Candidates {
+    addresses: Vec<Ipv4Addr>,
+}
+
+impl Candidates {
+    #[must_use]
+    pub fn new() -> Candidates {
+        Candidates::default()
+    }
+
+    /// The default gateways, then the VPN peers.
+    #[must_use]
+    pub fn system() -> Candidates {
+        Candidates::new().with_default_gateways().with_vpn_peers()
+    }
+
+    /// Adds the gateway of every default route, see `get_gateway_addrs()`.
+    #[must_use]
+    pub fn with_default_gateways(self) -> Candidates {
+        self.with_addresses(
+            get_gateway_addrs()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(_interface, gateway)| gateway),
+        )
+    }
+
+    /// Adds the remote end of every point-to-point interface, e.g. an `OpenVPN` tunnel.
+    ///
+    /// `WireGuard` interfaces don't have a remote end, add the VPN server's internal address with `with_address()`.
+    #[must_use]
+    pub fn with_vpn_peers(self) -> Candidates {
+        let peers = match getifaddrs() {
+            Ok(interfaces) => interfaces
+                .filter(|interface| {
+                    interface.flags.contains(InterfaceFlags::IFF_POINTOPOINT)
+                        && interface.flags.contains(InterfaceFlags::IFF_UP)
+                })
+                .filter_map(|interface| {
+                    let destination = interface.destination?;
+
+                    destination.as_sockaddr_in().map(SockaddrIn::ip)
+                })
+                .collect::<Vec<_>>(),
+            Err(error) => {
+                event!(Level::WARN, ?error, "Failed to list network interfaces");
+
+                vec![]
+            },
+        };
+
+        self.with_addresses(peers)
+    }
+
+    /// Adds a configured address.
+    #[must_use]
+    pub fn with_address(self, address: Ipv4Addr) -> Candidates {
+        self.with_addresses([address])
+    }
+
+    #[must_use]
+    pub fn with_addresses<I: IntoIterator<Item = Ipv4Addr>>(mut self, addresses: I) -> Candidates {
+        for address in addresses {
+            if !self.addresses.contains(&address) {
+                self.addresses.push(address);
+            }
+        }
+
+        self
+    }
+
+    #[must_use]
+    pub fn addresses(&self) -> &[Ipv4Addr] {
+        &self.addresses
+    }
+}
+
+/// Asks every candidate for its external address at once, from `binding`, and returns the first that answers.
+///
+/// The requests to the other candidates are abandoned as soon as one answers.
+///
+/// # Errors
+/// `NATPMPError::NoGateway` when there are no candidates, otherwise the error of the first candidate when
+/// none of them answered
+pub async fn race<P: RetryPolicy + 'static>(
+    candidates: &Candidates,
+    retry_policy: P,
+    binding: &Binding,
+) -> Result<Ipv4Addr, NATPMPError> {
+    let retry_policy = Arc::new(retry_policy);
+    let binding = Arc::new(binding.clone());
+
+    let mut requests = JoinSet::new();
+
+    for (index, &candidate) in candidates.addresses().iter().enumerate() {
+        let retry_policy = Arc::clone(&retry_policy);
+        let binding = Arc::clone(&binding);
+
+        requests.spawn(async move {
+            let result = request_public_address(candidate, &*retry_policy, &binding).await;
+
+            (index, candidate, result)
+        });
+    }
+
+    let mut first_error: Option<(usize, NATPMPError)> = None;
+
+    while let Some(joined) = requests.join_next().await {
+        let (index, candidate, result) = match joined {
+            Ok(joined) => joined,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        };
+
+        match result {
+            Ok(public_ip) => {
+                event!(Level::DEBUG, gateway = %candidate, %public_ip, "Found NAT-PMP gateway");
+
+                return Ok(candidate);
+            },
+            Err(error) => {
+                event!(Level::DEBUG, ?error, gateway = %candidate, "Candidate is not a NAT-PMP gateway");
+
+                if first_error.as_ref().is_none_or(|&(first, _)| index < first) {
+                    first_error = Some((index, error));
+                }
+            },
+        }
+    }
+
+    Err(first_error.map_or(NATPMPError::NoGateway, |(_, error)| error))
+}
+
+/// Races the `Candidates::system()` candidates, and remembers the winner for `DEFAULT_TTL`.
+///
+/// This is the gateway the free functions use when they're not given one.
+///
+/// # Errors
+/// See `race()`
+pub async fn discover_gateway() -> Result<Ipv4Addr, NATPMPError> {
+    // a few attempts are plenty on a LAN, and we don't want to keep the caller waiting for the full schedule
+    discover_gateway_retrying(Rfc6886::with_attempts(4), &Binding::new()).await
+}
+
+/// Like `discover_gateway()`, but asks the candidates following `retry_policy` and from `binding`, e.g. those of
+/// the request the gateway is needed for.
+///
+/// A remembered gateway is only used when it was found through the same binding, a gateway in another network
+/// namespace is another gateway.
+///
+/// # Errors
+/// See `race()`
+pub async fn discover_gateway_retrying<P: RetryPolicy + 'static>(
+    retry_policy: P,
+    binding: &Binding,
+) -> Result<Ipv4Addr, NATPMPError> {
+    let remembered = lock()
+        .as_ref()
+        .filter(|&&(ref found_through, _, valid_until)| {
+            found_through == binding && is_valid(valid_until)
+        })
+        .map(|&(_, gateway, _)| gateway);
+
+    if let Some(gateway) = remembered {
+        return Ok(gateway);
+    }
+
+    discover_gateway_with(&Candidates::system(), retry_policy, binding, DEFAULT_TTL).await
+}
+
+/// Races `candidates`, and remembers the winner for `ttl`, replacing the gateway discovered before.
+///
+/// # Errors
+/// See `race()`
+pub async fn discover_gateway_with<P: RetryPolicy + 'static>(
+    candidates: &Candidates,
+    retry_policy: P,
+    binding: &Binding,
+    ttl: Duration,
+) -> Result<Ipv4Addr, NATPMPError> {
+    let gateway = race(candidates, retry_policy, binding).await?;
+
+    // a TTL too long to represent never expires
+    *lock() = Some((binding.detached(), gateway, Instant::now().checked_add(ttl)));
+
+    Ok(gateway)
+}
+
+/// The gateway discovered last, unless it's older than its TTL.
+#[must_use]
+pub fn discovered_gateway() -> Option<Ipv4Addr> {
+    lock()
+        .as_ref()
+        .filter(|&&(_, _, valid_until)| is_valid(valid_until))
+        .map(|&(_, gateway, _)| gateway)
+}
+
+/// Forgets the discovered gateway, e.g. after a network change, so that the next `discover_gateway()` races again.
+pub fn forget_discovered_gateway() {
+    *lock() = None;
+}
+
+/// Whether a gateway remembered until `valid_until`, `None` for forever, is still valid.
+fn is_valid(valid_until: Option<Instant>) -> bool {
+    valid_until.is_none_or(|valid_until| Instant::now() < valid_until)
+}
+
+fn lock() -> MutexGuard<'static, Option<(Binding, Ipv4Addr, Option<Instant>)>> {
+    // the cache is always in a valid state, even when a thread panicked while holding the lock
+    DISCOVERED.lock().unwrap_or_else(PoisonError::into_inner)
+}
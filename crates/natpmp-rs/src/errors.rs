@@ -1,44 +1,278 @@
 use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::num::NonZeroU16;
 
 use thiserror::Error;
 
+use crate::protocol::{MappingProtocol, Opcode};
+
+/// The request an error happened on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestContext {
+    gateway: Ipv4Addr,
+    opcode: Opcode,
+    attempt: u32,
+}
+
+impl RequestContext {
+    pub(crate) fn new(gateway: Ipv4Addr, opcode: Opcode, attempt: u32) -> RequestContext {
+        RequestContext {
+            gateway,
+            opcode,
+            attempt,
+        }
+    }
+
+    /// The gateway the request was sent to, or the gateway that sent an announcement
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    #[must_use]
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// The attempt the error happened on, starting at 1. For timeouts, the number of attempts made.
+    /// 0 for announcements, which aren't requested.
+    #[must_use]
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+impl std::fmt::Display for RequestContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gateway {}, opcode {}, attempt {}",
+            self.gateway, self.opcode, self.attempt
+        )
+    }
+}
+
 #[derive(Error, Debug)]
 // class NATPMPError(Exception):
 //     """Generic exception state.  May be used to represent unknown errors."""
 //     pass
 pub enum NATPMPError {
-    #[error("NAT Gateway error response as per RFC-6886")]
-    Response(NATPMPResultError),
+    #[error("NAT Gateway error response as per RFC-6886: {result} ({context})")]
+    Response {
+        context: RequestContext,
+        result: NATPMPResultError,
+    },
     #[error("Network error while trying to communicate with NAT Gateway")]
-    Network(#[from] io::Error),
-    #[error("NAT Gateway does not support NAT-PMP (inferred when calls fail after x retries)")]
-    Unsupported,
-    #[error("NAT Gateway responded with nonsensical response")]
-    Deserialize(String),
-    #[error("Generic error that doesn't fit in anything else")]
-    Generic(String),
+    Network {
+        /// `None` when the socket couldn't be set up, before any request was sent
+        context: Option<RequestContext>,
+        #[source]
+        source: io::Error,
+    },
+    #[error("NAT Gateway did not respond ({context})")]
+    TimedOut { context: RequestContext },
+    #[error("NAT Gateway does not support the protocol version of the request ({context})")]
+    Unsupported { context: RequestContext },
+    /// ICMP port unreachable: nothing listens on the NAT-PMP port, e.g. while the gateway restarts
+    #[error("NAT Gateway refused the request, nothing listens on the NAT-PMP port ({context})")]
+    Refused { context: RequestContext },
+    #[error("NAT Gateway responded with nonsensical response: {reason} ({context})")]
+    InvalidResponse {
+        context: RequestContext,
+        reason: InvalidResponse,
+    },
+    #[error("No default gateway found")]
+    NoGateway,
+    /// Replies only tell mappings apart by protocol and internal port, so a batch can't map one twice
+    #[error("Internal port {internal_port}/{protocol} is mapped more than once in the batch")]
+    DuplicateEntry {
+        protocol: MappingProtocol,
+        internal_port: NonZeroU16,
+    },
+    #[error("NAT Gateway granted external port {granted} instead of {requested}")]
+    ExternalPortMismatch {
+        requested: u16,
+        /// 0 when the gateway granted no external port at all
+        granted: u16,
+    },
+}
+
+impl From<io::Error> for NATPMPError {
+    fn from(source: io::Error) -> Self {
+        NATPMPError::Network {
+            context: None,
+            source,
+        }
+    }
+}
+
+impl NATPMPError {
+    /// The request the error happened on, if it happened on one.
+    #[must_use]
+    pub fn context(&self) -> Option<&RequestContext> {
+        match *self {
+            NATPMPError::Response { ref context, .. }
+            | NATPMPError::TimedOut { ref context }
+            | NATPMPError::Unsupported { ref context }
+            | NATPMPError::Refused { ref context }
+            | NATPMPError::InvalidResponse { ref context, .. } => Some(context),
+            NATPMPError::Network { ref context, .. } => context.as_ref(),
+            NATPMPError::NoGateway
+            | NATPMPError::DuplicateEntry { .. }
+            | NATPMPError::ExternalPortMismatch { .. } => None,
+        }
+    }
+
+    /// Whether the same request might succeed later, e.g. because the gateway was busy or unreachable.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            NATPMPError::Response { ref result, .. } => result.is_retryable(),
+            NATPMPError::Network { .. }
+            | NATPMPError::TimedOut { .. }
+            | NATPMPError::Refused { .. }
+            | NATPMPError::NoGateway => true,
+            NATPMPError::Unsupported { .. }
+            | NATPMPError::InvalidResponse { .. }
+            | NATPMPError::DuplicateEntry { .. }
+            | NATPMPError::ExternalPortMismatch { .. } => false,
+        }
+    }
+
+    /// Whether the same request will keep failing, e.g. because the gateway doesn't speak NAT-PMP or refuses us.
+    ///
+    /// Errors that are neither retryable nor permanent, like an unknown result code, are up to the caller.
+    #[must_use]
+    pub fn is_permanent(&self) -> bool {
+        match *self {
+            NATPMPError::Response { ref result, .. } => result.is_permanent(),
+            NATPMPError::Unsupported { .. } | NATPMPError::DuplicateEntry { .. } => true,
+            NATPMPError::Network { .. }
+            | NATPMPError::TimedOut { .. }
+            | NATPMPError::Refused { .. }
+            | NATPMPError::InvalidResponse { .. }
+            | NATPMPError::NoGateway
+            | NATPMPError::ExternalPortMismatch { .. } => false,
+        }
+    }
 }
 
-#[derive(Debug)]
+/// Why a response didn't make sense.
+#[derive(Error, Debug)]
+pub enum InvalidResponse {
+    #[error("Response opcode {received} does not match request opcode {expected}")]
+    OpcodeMismatch { expected: u8, received: u8 },
+    #[error("Response has unknown protocol {0}")]
+    UnknownProtocol(u8),
+    #[error("Response has internal port 0")]
+    ZeroInternalPort,
+}
+
+/// The result codes of RFC 6886.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.5>
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NATPMPResultError {
-    UnsupportedVersion = 1,
+    #[error("Unsupported Version")]
+    UnsupportedVersion,
+    #[error("Not Authorized/Refused")]
     NotAuthorizedRefused,
+    #[error("Network Failure")]
     NetworkFailure,
+    #[error("Out of resources")]
     OutOfResources,
+    #[error("Unsupported opcode")]
     UnsupportedOpcode,
+    /// A result code that RFC 6886 doesn't define
+    #[error("Unknown result code {0}")]
+    Unknown(u16),
 }
 
-impl TryFrom<u16> for NATPMPResultError {
-    type Error = String;
+impl NATPMPResultError {
+    #[must_use]
+    pub fn code(&self) -> u16 {
+        match *self {
+            NATPMPResultError::UnsupportedVersion => 1,
+            NATPMPResultError::NotAuthorizedRefused => 2,
+            NATPMPResultError::NetworkFailure => 3,
+            NATPMPResultError::OutOfResources => 4,
+            NATPMPResultError::UnsupportedOpcode => 5,
+            NATPMPResultError::Unknown(code) => code,
+        }
+    }
+
+    /// The gateway can't map right now, e.g. because it has no DHCP lease yet, or no ports left.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            *self,
+            NATPMPResultError::NetworkFailure | NATPMPResultError::OutOfResources
+        )
+    }
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+    /// The gateway doesn't speak our version, or won't let us map.
+    #[must_use]
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            *self,
+            NATPMPResultError::UnsupportedVersion
+                | NATPMPResultError::NotAuthorizedRefused
+                | NATPMPResultError::UnsupportedOpcode
+        )
+    }
+}
+
+impl From<u16> for NATPMPResultError {
+    fn from(value: u16) -> Self {
         match value {
-            1 => Ok(NATPMPResultError::UnsupportedVersion),
-            2 => Ok(NATPMPResultError::NotAuthorizedRefused),
-            3 => Ok(NATPMPResultError::NetworkFailure),
-            4 => Ok(NATPMPResultError::OutOfResources),
-            5 => Ok(NATPMPResultError::UnsupportedOpcode),
-            _ => Err(String::from("Unrecognized Error Result Code")),
+            1 => NATPMPResultError::UnsupportedVersion,
+            2 => NATPMPResultError::NotAuthorizedRefused,
+            3 => NATPMPResultError::NetworkFailure,
+            4 => NATPMPResultError::OutOfResources,
+            5 => NATPMPResultError::UnsupportedOpcode,
+            code => NATPMPResultError::Unknown(code),
         }
     }
 }
+
+/// Why bytes aren't a NAT-PMP or PCP packet we can decode.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("Invalid hex: {0}")]
+    InvalidHex(String),
+    #[error("Unknown protocol version {0}, NAT-PMP is 0 and PCP is 2")]
+    UnknownVersion(u8),
+    #[error("Packet is truncated: {length} bytes, {expected} expected")]
+    Truncated { expected: usize, length: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum StunError {
+    #[error("Network error while trying to communicate with STUN server")]
+    Network(#[from] io::Error),
+    #[error("STUN server {server} did not respond after {attempts} attempts")]
+    TimedOut { server: SocketAddrV4, attempts: u32 },
+    #[error("STUN server {server} responded with error {code}: {reason}")]
+    ErrorResponse {
+        server: SocketAddrV4,
+        code: u16,
+        reason: String,
+    },
+    #[error("STUN server {server} responded with nonsensical response: {reason}")]
+    InvalidResponse {
+        server: SocketAddrV4,
+        reason: &'static str,
+    },
+    #[error("NAT Gateway did not tell its external address")]
+    Gateway(#[source] NATPMPError),
+}
+
+#[cfg(feature = "torrent-clients")]
+#[derive(Error, Debug)]
+pub enum IntegrationError {
+    #[error("HTTP request to the application failed")]
+    Http(#[from] reqwest::Error),
+    #[error("The application rejected our credentials")]
+    Authentication,
+    #[error("The application responded with something we didn't expect: {0}")]
+    UnexpectedResponse(String),
+}
@@ -0,0 +1,236 @@
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre;
+use natpmp_rs::protocol::MappingProtocol;
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+use tokio::process::Command;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::{Level, event};
+
+use crate::cli::HookArgs;
+#[cfg(feature = "torrent-clients")]
+use crate::torrent_clients::TorrentClients;
+
+/// A change that hooks are notified of.
+#[derive(Debug)]
+pub enum Change {
+    PublicIp {
+        gateway: Ipv4Addr,
+        old: Ipv4Addr,
+        new: Ipv4Addr,
+    },
+    ExternalPort {
+        gateway: Ipv4Addr,
+        protocol: MappingProtocol,
+        internal_port: NonZeroU16,
+        old: u16,
+        new: u16,
+    },
+}
+
+impl Change {
+    /// The environment variables that describe this change to a hook.
+    fn environment(&self) -> Vec<(&'static str, String)> {
+        match *self {
+            Change::PublicIp { gateway, old, new } => vec![
+                ("NATPMP_EVENT", String::from("public_ip")),
+                ("NATPMP_GATEWAY", gateway.to_string()),
+                ("NATPMP_OLD_PUBLIC_IP", old.to_string()),
+                ("NATPMP_NEW_PUBLIC_IP", new.to_string()),
+            ],
+            Change::ExternalPort {
+                gateway,
+                protocol,
+                internal_port,
+                old,
+                new,
+            } => vec![
+                ("NATPMP_EVENT", String::from("external_port")),
+                ("NATPMP_GATEWAY", gateway.to_string()),
+                ("NATPMP_PROTOCOL", protocol.to_string()),
+                ("NATPMP_INTERNAL_PORT", internal_port.to_string()),
+                ("NATPMP_OLD_EXTERNAL_PORT", old.to_string()),
+                ("NATPMP_NEW_EXTERNAL_PORT", new.to_string()),
+            ],
+        }
+    }
+}
+
+/// Runs the configured hooks for every change, one at a time, in the order the changes were reported.
+///
+/// The external port of one mapping is also pushed to the configured torrent clients.
+pub struct Hooks {
+    sender: UnboundedSender<Change>,
+    handle: JoinHandle<()>,
+}
+
+impl Hooks {
+    #[cfg_attr(
+        not(feature = "torrent-clients"),
+        expect(
+            clippy::unnecessary_wraps,
+            reason = "Only creating the torrent clients can fail"
+        )
+    )]
+    pub fn spawn(hook_args: &HookArgs) -> Result<Hooks, eyre::Report> {
+        #[cfg(feature = "torrent-clients")]
+        let torrent_clients = TorrentClients::new(hook_args)?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let handle = tokio::task::spawn(run_hooks(
+            hook_args.hook.clone(),
+            hook_args.hook_dir.clone(),
+            #[cfg(feature = "torrent-clients")]
+            torrent_clients,
+            Duration::from_secs(hook_args.hook_timeout),
+            receiver,
+        ));
+
+        Ok(Hooks { sender, handle })
+    }
+
+    pub fn notify(&self, change: Change) {
+        event!(Level::INFO, ?change, "Detected change");
+
+        if self.sender.send(change).is_err() {
+            event!(Level::ERROR, "Hook runner is gone, change not processed");
+        }
+    }
+
+    /// Waits for the hooks of all reported changes to finish.
+    pub async fn shutdown(self) {
+        drop(self.sender);
+
+        if let Err(error) = self.handle.await {
+            event!(Level::ERROR, ?error, "Hook runner failed");
+        }
+    }
+}
+
+async fn run_hooks(
+    commands: Vec<String>,
+    hook_dir: Option<PathBuf>,
+    #[cfg(feature = "torrent-clients")] mut torrent_clients: TorrentClients,
+    timeout: Duration,
+    mut receiver: UnboundedReceiver<Change>,
+) {
+    while let Some(change) = receiver.recv().await {
+        #[cfg(feature = "torrent-clients")]
+        torrent_clients.update(&change, timeout).await;
+
+        let environment = change.environment();
+
+        for command in &commands {
+            let mut hook = Command::new("sh");
+
+            hook.arg("-c").arg(command);
+
+            run_hook(hook, command, &environment, timeout).await;
+        }
+
+        if let Some(hook_dir) = hook_dir.as_deref() {
+            for path in executables_in(hook_dir).await {
+                run_hook(
+                    Command::new(&path),
+                    &path.display().to_string(),
+                    &environment,
+                    timeout,
+                )
+                .await;
+            }
+        }
+    }
+}
+
+/// Lists the executables in `directory`, sorted by name. Read on every change so that hooks can be added
+/// and removed while we run.
+async fn executables_in(directory: &Path) -> Vec<PathBuf> {
+    let mut executables = vec![];
+
+    let mut entries = match tokio::fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        Err(error) => {
+            event!(Level::ERROR, ?error, directory = %directory.display(), "Failed to read hook directory");
+
+            return executables;
+        },
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Ok(metadata) = tokio::fs::metadata(entry.path()).await
+            && !metadata.is_dir()
+            && metadata.permissions().mode() & 0o111 != 0
+        {
+            executables.push(entry.path());
+        }
+    }
+
+    executables.sort();
+
+    executables
+}
+
+/// Runs `hook` in its own process group, so that on timeout we kill whatever it started as well, not just `sh`.
+async fn run_hook(
+    mut hook: Command,
+    name: &str,
+    environment: &[(&'static str, String)],
+    timeout: Duration,
+) {
+    hook.envs(environment.iter().map(|&(key, ref value)| (key, value)))
+        .process_group(0)
+        .kill_on_drop(true);
+
+    let mut child = match hook.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            event!(Level::ERROR, ?error, hook = name, "Failed to start hook");
+
+            return;
+        },
+    };
+
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {
+            event!(Level::DEBUG, hook = name, "Hook finished");
+        },
+        Ok(Ok(status)) => {
+            event!(Level::WARN, hook = name, %status, "Hook failed");
+        },
+        Ok(Err(error)) => {
+            event!(Level::ERROR, ?error, hook = name, "Failed to wait for hook");
+        },
+        Err(_) => {
+            event!(
+                Level::WARN,
+                hook = name,
+                ?timeout,
+                "Hook timed out, killing it"
+            );
+
+            // the hook leads its process group, so the group has the hook's pid
+            let group = child
+                .id()
+                .and_then(|id| i32::try_from(id).ok())
+                .map(Pid::from_raw);
+
+            if let Some(group) = group
+                && let Err(error) = killpg(group, Signal::SIGKILL)
+            {
+                event!(Level::ERROR, ?error, hook = name, "Failed to kill hook");
+            }
+
+            // reap it, and make sure `sh` itself is gone even when the group couldn't be killed
+            if let Err(error) = child.kill().await {
+                event!(Level::ERROR, ?error, hook = name, "Failed to kill hook");
+            }
+        },
+    }
+}
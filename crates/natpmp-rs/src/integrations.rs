@@ -0,0 +1,4 @@
+//! Applications that need to know which external port they have been given, so that they can advertise it.
+
+pub mod qbittorrent;
+pub mod transmission;
@@ -0,0 +1,79 @@
+use reqwest::header::REFERER;
+use reqwest::{Client, StatusCode};
+
+use crate::errors::IntegrationError;
+
+/// Sets qBittorrent's listen port through its Web UI API.
+/// Source: <https://github.com/qbittorrent/qBittorrent/wiki/WebUI-API-(qBittorrent-5.0)>
+pub struct QBittorrent {
+    client: Client,
+    base_url: String,
+    credentials: Option<(String, String)>,
+}
+
+impl QBittorrent {
+    /// `base_url` is where the Web UI lives, e.g. `http://localhost:8080`.
+    ///
+    /// Without `credentials` we don't log in, which works when the Web UI bypasses authentication for our address.
+    ///
+    /// # Errors
+    /// When the HTTP client cannot be created
+    pub fn new(
+        base_url: &str,
+        credentials: Option<(String, String)>,
+    ) -> Result<QBittorrent, IntegrationError> {
+        let client = Client::builder().cookie_store(true).build()?;
+
+        Ok(QBittorrent {
+            client,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            credentials,
+        })
+    }
+
+    /// Logs in, when we have credentials, and sets the listen port.
+    ///
+    /// # Errors
+    /// When the Web UI cannot be reached, rejects our credentials or refuses the new preferences
+    pub async fn set_listen_port(&self, port: u16) -> Result<(), IntegrationError> {
+        if let Some((ref username, ref password)) = self.credentials {
+            self.login(username, password).await?;
+        }
+
+        let preferences = serde_json::json!({ "listen_port": port }).to_string();
+
+        let response = self
+            .client
+            .post(format!("{}/api/v2/app/setPreferences", self.base_url))
+            // the Web UI refuses requests whose `Referer` doesn't match its own address
+            .header(REFERER, &self.base_url)
+            .form(&[("json", preferences)])
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::FORBIDDEN => Err(IntegrationError::Authentication),
+            status if status.is_success() => Ok(()),
+            status => Err(IntegrationError::UnexpectedResponse(status.to_string())),
+        }
+    }
+
+    /// Logs in, the session cookie is kept by the client.
+    async fn login(&self, username: &str, password: &str) -> Result<(), IntegrationError> {
+        let response = self
+            .client
+            .post(format!("{}/api/v2/auth/login", self.base_url))
+            .header(REFERER, &self.base_url)
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // a failed login is still a 200, with `Fails.` as body
+        if response.text().await? == "Ok." {
+            Ok(())
+        } else {
+            Err(IntegrationError::Authentication)
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use reqwest::{Client, StatusCode};
+use serde_json::Value as JsonValue;
+
+use crate::errors::IntegrationError;
+
+/// Transmission's protection against cross-site request forgery, the id is handed out with a `409 Conflict`.
+/// Source: <https://github.com/transmission/transmission/blob/main/docs/rpc-spec.md#231-csrf-protection>
+const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
+
+/// Sets Transmission's peer port through its RPC interface.
+/// Source: <https://github.com/transmission/transmission/blob/main/docs/rpc-spec.md>
+pub struct Transmission {
+    client: Client,
+    rpc_url: String,
+    credentials: Option<(String, String)>,
+    session_id: Option<String>,
+}
+
+impl Transmission {
+    /// `rpc_url` is the RPC endpoint, e.g. `http://localhost:9091/transmission/rpc`.
+    ///
+    /// # Errors
+    /// When the HTTP client cannot be created
+    pub fn new(
+        rpc_url: &str,
+        credentials: Option<(String, String)>,
+    ) -> Result<Transmission, IntegrationError> {
+        Ok(Transmission {
+            client: Client::builder().build()?,
+            rpc_url: rpc_url.to_owned(),
+            credentials,
+            session_id: None,
+        })
+    }
+
+    /// Sets the peer port, fetching a new session id first when Transmission asks for it.
+    ///
+    /// # Errors
+    /// When Transmission cannot be reached, rejects our credentials or fails to set the port
+    pub async fn set_peer_port(&mut self, port: u16) -> Result<(), IntegrationError> {
+        let request = serde_json::json!({
+            "method": "session-set",
+            "arguments": { "peer-port": port },
+        });
+
+        let mut response = self.send(&request).await?;
+
+        if response.status() == StatusCode::CONFLICT {
+            self.session_id = response
+                .headers()
+                .get(SESSION_ID_HEADER)
+                .and_then(|session_id| session_id.to_str().ok())
+                .map(str::to_owned);
+
+            response = self.send(&request).await?;
+        }
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(IntegrationError::Authentication);
+        }
+
+        let body: JsonValue = response.error_for_status()?.json().await?;
+
+        match body.get("result").and_then(JsonValue::as_str) {
+            Some("success") => Ok(()),
+            result => Err(IntegrationError::UnexpectedResponse(format!(
+                "{:?}",
+                result
+            ))),
+        }
+    }
+
+    async fn send(&self, request: &JsonValue) -> Result<reqwest::Response, IntegrationError> {
+        let mut builder = self.client.post(&self.rpc_url).json(request);
+
+        if let Some(session_id) = self.session_id.as_deref() {
+            builder = builder.header(SESSION_ID_HEADER, session_id);
+        }
+
+        if let Some((ref username, ref password)) = self.credentials {
+            builder = builder.basic_auth(username, Some(password));
+        }
+
+        Ok(builder.send().await?)
+    }
+}
@@ -1,52 +1,136 @@
+pub mod announcements;
+pub mod batch;
+pub mod binding;
+pub mod capture;
+pub mod client;
+pub mod conformance;
+pub mod diagnostics;
+pub mod discovery;
 pub mod errors;
+#[cfg(feature = "torrent-clients")]
+pub mod integrations;
+pub mod mapping_guard;
+pub mod multi_gateway;
+pub mod packet;
+pub mod pcp;
+pub mod policy;
+pub mod port_range;
 pub mod protocol;
+pub mod public_socket;
+pub mod reachability;
 pub mod requests;
 pub mod responses;
+pub mod retry;
+pub mod simulator;
+pub mod stun;
+pub mod telemetry;
+pub mod verify;
+pub mod watch;
 use std::fs::read_to_string;
 use std::io::ErrorKind;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::num::NonZeroU16;
-use std::time::Duration;
+use std::time::Instant;
 
 use protocol::MappingProtocol;
 use requests::external_address_request::ExternalAddressRequest;
 use requests::mapping_request::MappingRequest;
 use requests::unmap_all_request::UnmapAllPortsRequest;
 use requests::unmap_request::UnmapPortRequest;
-use responses::parse_raw_response;
-use socket2::Socket;
 use tokio::net::UdpSocket;
 use tracing::{Level, event};
 
-use crate::errors::NATPMPError;
+use crate::binding::Binding;
+use crate::capture::Direction;
+use crate::discovery::discover_gateway_retrying;
+use crate::errors::{NATPMPError, RequestContext};
 use crate::requests::Request;
-use crate::responses::{MappingResponse, Response as _};
+use crate::responses::{ExternalAddressResponse, MappingResponse, Response as _, UnmapAllResponse};
+use crate::retry::{Attempts, RetryPolicy, Rfc6886};
 
 const VERSION: u8 = 0;
 const NATPMP_PORT: u16 = 5351;
 
 const PATH_PROC_NET_ROUTE: &str = "/proc/net/route";
 
-fn get_gateway_addr() -> Result<Ipv4Addr, NATPMPError> {
-    let route_text = read_to_string(PATH_PROC_NET_ROUTE).unwrap_or_default();
+/// Returns the gateway of the first default route in `/proc/net/route`.
+///
+/// # Errors
+/// When there is no default route
+pub fn get_gateway_addr() -> Result<Ipv4Addr, NATPMPError> {
+    default_gateways()
+        .into_iter()
+        .next()
+        .ok_or(NATPMPError::NoGateway)
+}
 
-    // skip title
-    for line in route_text.lines().skip(1) {
-        // skip name
-        let mut iter = line.split('\t').skip(1).map(str::trim);
-
-        let destination = iter.next().map(|v| u32::from_str_radix(v, 16));
-        let gateway = iter.next().map(|v| u32::from_str_radix(v, 16));
-
-        if let (Some(Ok(d)), Some(Ok(g))) = (destination, gateway)
-            && d == 0
-            && g != 0
-        {
-            return Ok(g.to_be().into());
+/// Returns the interface and gateway of all default routes in `/proc/net/route`, e.g. one per uplink, in the order
+/// of the routing table. A gateway that is the default route of an interface several times, e.g. with different
+/// metrics, is returned once. The same address on several interfaces, e.g. two routers that both use `192.168.1.1`,
+/// is returned for every interface, as those are different gateways.
+///
+/// # Errors
+/// When there is no default route
+pub fn get_gateway_addrs() -> Result<Vec<(String, Ipv4Addr)>, NATPMPError> {
+    let mut gateways = Vec::<(String, Ipv4Addr)>::new();
+
+    for route in default_routes() {
+        if !gateways.contains(&route) {
+            gateways.push(route);
         }
     }
 
-    Err(NATPMPError::Generic("No default gateway found".into()))
+    if gateways.is_empty() {
+        return Err(NATPMPError::NoGateway);
+    }
+
+    Ok(gateways)
+}
+
+/// `gateway_ip`, or the gateway found by `discover_gateway_retrying()` when there is none, asking the candidates
+/// following the `retry_policy` of the request the gateway is needed for.
+async fn resolve_gateway<P: RetryPolicy + 'static>(
+    gateway_ip: Option<Ipv4Addr>,
+    retry_policy: P,
+) -> Result<Ipv4Addr, NATPMPError> {
+    match gateway_ip {
+        Some(g) => Ok(g),
+        None => discover_gateway_retrying(retry_policy, &Binding::new()).await,
+    }
+}
+
+fn default_gateways() -> Vec<Ipv4Addr> {
+    default_routes()
+        .into_iter()
+        .map(|(_interface, gateway)| gateway)
+        .collect()
+}
+
+/// The interface and gateway of every default route in `/proc/net/route`, in the order of the routing table.
+pub(crate) fn default_routes() -> Vec<(String, Ipv4Addr)> {
+    let route_text = read_to_string(PATH_PROC_NET_ROUTE).unwrap_or_default();
+
+    // skip title
+    route_text
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut iter = line.split('\t').map(str::trim);
+
+            let interface = iter.next()?;
+            let destination = iter.next().map(|v| u32::from_str_radix(v, 16));
+            let gateway = iter.next().map(|v| u32::from_str_radix(v, 16));
+
+            if let (Some(Ok(d)), Some(Ok(g))) = (destination, gateway)
+                && d == 0
+                && g != 0
+            {
+                return Some((interface.to_owned(), g.to_be().into()));
+            }
+
+            None
+        })
+        .collect()
 }
 
 // /// Takes a gateway address string and returns a non-blocking UDP
@@ -80,24 +164,16 @@ fn get_gateway_addr() -> Result<Ipv4Addr, NATPMPError> {
 //     Ok(socket)
 // }
 
-/// Creates a non-blocking UDP socket. We don't bind to anything as we want to use it to send stuff randomly.
-fn build_socket() -> Result<UdpSocket, NATPMPError> {
+/// Creates a non-blocking UDP socket. Unless `binding` says otherwise, we don't bind to anything as we want to use
+/// it to send stuff randomly.
+async fn build_socket(binding: &Binding) -> Result<UdpSocket, NATPMPError> {
     // bind to the multicast address
     // Source: https://www.rfc-editor.org/rfc/rfc6886#page-6:~:text=Clients%20should%20therefore%0A%20%20%20bind%20specifically%20to%20224.0.0.1%3A5350
     // const MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
     // ^ Above doesn't work. TODO...
 
-    // use socket so that we don't have to bind to any address explicitly
-    let socket = Socket::new(
-        socket2::Domain::IPV4,
-        socket2::Type::DGRAM,
-        Some(socket2::Protocol::UDP),
-    )?;
-
-    socket.set_nonblocking(true)?;
-
     // can't create a tokio socket from socket2
-    let socket = std::net::UdpSocket::from(socket);
+    let socket = binding.socket().await?;
 
     let socket = tokio::net::UdpSocket::from_std(socket)?;
 
@@ -108,7 +184,7 @@ fn build_socket() -> Result<UdpSocket, NATPMPError> {
 /// the current host by querying the NAT-PMP gateway.
 ///
 /// # Arguments
-/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or auto-detect the IP via `get_gateway_addr()`
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
 /// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
 ///
 /// # Returns
@@ -120,19 +196,33 @@ pub async fn get_public_address(
     gateway_ip: Option<Ipv4Addr>,
     retry: Option<u32>,
 ) -> Result<Ipv4Addr, NATPMPError> {
-    let gateway_ip = match gateway_ip {
-        Some(g) => g,
-        None => get_gateway_addr()?,
-    };
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+    request_public_address(gateway_ip, &Rfc6886::from_retry(retry), &Binding::new()).await
+}
 
-    let address_response = send_request_with_retry(
+pub(crate) async fn request_public_address(
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<Ipv4Addr, NATPMPError> {
+    let address_response = request_external_address(gateway_ip, retry_policy, binding).await;
+
+    address_response.map(|r| r.ipv4_address())
+}
+
+pub(crate) async fn request_external_address(
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<ExternalAddressResponse, NATPMPError> {
+    send_request_with_retry(
         gateway_ip,
         ExternalAddressRequest::new(),
-        retry.unwrap_or(9),
+        retry_policy,
+        binding,
     )
-    .await;
-
-    address_response.map(|r| r.ipv4_address())
+    .await
 }
 
 /// A high-level wrapper to `map_port()` that requests a mapping
@@ -144,7 +234,7 @@ pub async fn get_public_address(
 /// * `public_port` - the public port of the mapping requested
 /// * `private_port` - the private port of the mapping requested
 /// * `lifetime` - the duration of the mapping in seconds, defaults to 7200, per specification.
-/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or auto-detect the IP via `get_gateway_addr()`
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
 /// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
 ///
 /// # Errors
@@ -177,7 +267,7 @@ pub async fn map_tcp_port(
 /// * `public_port` - the public port of the mapping requested
 /// * `private_port` - the private port of the mapping requested
 /// * `lifetime` - the duration of the mapping in seconds, defaults to 7200, per specification.
-/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or auto-detect the IP via `get_gateway_addr()`
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
 /// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
 ///
 /// # Errors
@@ -210,7 +300,7 @@ pub async fn map_udp_port(
 /// * `private_port` - the private port of the mapping requested
 /// * `public_port` - the public port of the mapping requested
 /// * `lifetime` - the duration of the mapping in seconds, defaults to 7200, per specification.
-/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or auto-detect the IP via `get_gateway_addr()`
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
 /// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
 ///
 /// # Errors
@@ -224,22 +314,37 @@ pub async fn map_port(
     gateway_ip: Option<Ipv4Addr>,
     retry: Option<u32>,
 ) -> Result<MappingResponse, NATPMPError> {
-    let gateway_ip = match gateway_ip {
-        Some(g) => g,
-        None => get_gateway_addr()?,
-    };
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
 
+    request_mapping(
+        protocol,
+        private_port,
+        public_port,
+        lifetime.unwrap_or(7200),
+        gateway_ip,
+        &Rfc6886::from_retry(retry),
+        &Binding::new(),
+    )
+    .await
+}
+
+pub(crate) async fn request_mapping(
+    protocol: MappingProtocol,
+    private_port: NonZeroU16,
+    public_port: Option<NonZeroU16>,
+    lifetime: u32,
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<MappingResponse, NATPMPError> {
     let port_mapping_request = MappingRequest::new(
         protocol,
         private_port,
         public_port.map_or(0, Into::into),
-        lifetime.unwrap_or(7200),
+        lifetime,
     );
 
-    let port_mapping_response =
-        send_request_with_retry(gateway_ip, port_mapping_request, retry.unwrap_or(9)).await;
-
-    port_mapping_response
+    send_request_with_retry(gateway_ip, port_mapping_request, retry_policy, binding).await
 }
 
 /// A function to unmap a `private_port` of a protocol.
@@ -249,7 +354,7 @@ pub async fn map_port(
 /// # Arguments
 /// * `protocol` - `Protocol::TCP` or `Protocol::UDP`
 /// * `private_port` - the private port of the mapping requested
-/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or auto-detect the IP via `get_gateway_addr()`
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
 /// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
 ///
 /// # Errors
@@ -261,17 +366,28 @@ pub async fn unmap_port(
     gateway_ip: Option<Ipv4Addr>,
     retry: Option<u32>,
 ) -> Result<MappingResponse, NATPMPError> {
-    let gateway_ip = match gateway_ip {
-        Some(g) => g,
-        None => get_gateway_addr()?,
-    };
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
 
-    let port_mapping_request = UnmapPortRequest::new(protocol, private_port);
+    request_unmap(
+        protocol,
+        private_port,
+        gateway_ip,
+        &Rfc6886::from_retry(retry),
+        &Binding::new(),
+    )
+    .await
+}
 
-    let port_mapping_response =
-        send_request_with_retry(gateway_ip, port_mapping_request, retry.unwrap_or(9)).await;
+pub(crate) async fn request_unmap(
+    protocol: MappingProtocol,
+    private_port: NonZeroU16,
+    gateway_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<MappingResponse, NATPMPError> {
+    let port_mapping_request = UnmapPortRequest::new(protocol, private_port);
 
-    port_mapping_response
+    send_request_with_retry(gateway_ip, port_mapping_request, retry_policy, binding).await
 }
 
 /// A function to unmap all private ports of a protocol.
@@ -280,7 +396,7 @@ pub async fn unmap_port(
 ///
 /// # Arguments
 /// * `protocol` - `Protocol::TCP` or `Protocol::UDP`
-/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or auto-detect the IP via `get_gateway_addr()`
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
 /// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
 ///
 /// # Errors
@@ -291,77 +407,126 @@ pub async fn unmap_all_ports(
     protocol: MappingProtocol,
     gateway_ip: Option<Ipv4Addr>,
     retry: Option<u32>,
-) -> Result<MappingResponse, NATPMPError> {
-    let gateway_ip = match gateway_ip {
-        Some(g) => g,
-        None => get_gateway_addr()?,
-    };
+) -> Result<UnmapAllResponse, NATPMPError> {
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
 
     let port_mapping_request = UnmapAllPortsRequest::new(protocol);
 
-    let port_mapping_response =
-        send_request_with_retry(gateway_ip, port_mapping_request, retry.unwrap_or(9)).await;
+    let port_mapping_response = send_request_with_retry(
+        gateway_ip,
+        port_mapping_request,
+        &Rfc6886::from_retry(retry),
+        &Binding::new(),
+    )
+    .await;
 
     port_mapping_response
 }
 
+/// Sends `datagram` to the gateway, `local` is the address it's sent from as far as the capture is concerned, see
+/// `capture::local_address()`.
 async fn send_request(
     gateway_socket: &UdpSocket,
     gateway_ip: Ipv4Addr,
-    request: &(impl zerocopy::Immutable + zerocopy::IntoBytes),
-) -> Result<usize, NATPMPError> {
-    gateway_socket
-        .send_to(request.as_bytes(), (gateway_ip, NATPMP_PORT))
-        .await
-        .map_err(Into::into)
+    datagram: &[u8],
+    local: Option<SocketAddr>,
+) -> Result<usize, std::io::Error> {
+    let gateway = SocketAddr::from((gateway_ip, NATPMP_PORT));
+
+    let size = gateway_socket.send_to(datagram, gateway).await?;
+
+    capture::record(Direction::Sent, local, gateway, datagram, None);
+
+    Ok(size)
 }
 
 async fn send_request_with_retry<R: Request + zerocopy::Immutable + zerocopy::IntoBytes>(
     gateway_ip: Ipv4Addr,
     request: R,
-    retry: u32,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
 ) -> Result<R::Response, NATPMPError> {
-    // start at 250 milliseconds wait and then increase
-    // Source: https://www.rfc-editor.org/rfc/rfc6886#page-6:~:text=and%20waits%20250%20ms%20for%20a%20response.%20%20If%20no%0A%20%20%20NAT%2DPMP%20response%20is%20received%20from%20the%20gateway%20after%20250%20ms%2C%20the%0A%20%20%20client%20retransmits%20its%20request%20and%20waits%20500%20ms
-    const BASE_TIMEOUT: u64 = 250;
-
-    let socket = build_socket()?;
+    let socket = build_socket(binding).await?;
+    let local = capture::local_address(
+        &socket,
+        SocketAddr::from((gateway_ip, NATPMP_PORT)),
+        binding,
+    )
+    .await;
 
     // buffer is at minimum the size of a response, e.g. 12 for external address or 16 for port mapping
     // an error is 8, so that'll always fit
     let mut buffer = R::Response::get_buffer();
 
-    for tries in 1..=retry {
-        let _size = send_request(&socket, gateway_ip, &request).await?;
+    let mut attempts = Attempts::new(retry_policy);
+
+    while let Some((tries, wait_until)) = attempts.next() {
+        let sent_at = Instant::now();
+
+        let _size = send_request(&socket, gateway_ip, request.as_bytes(), local)
+            .await
+            .map_err(|source| NATPMPError::Network {
+                context: Some(RequestContext::new(gateway_ip, request.opcode(), tries)),
+                source,
+            })?;
+
+        telemetry::record_sent(request.version(), request.opcode(), tries);
+
+        match tokio::time::timeout_at(wait_until, socket.recv_from(&mut buffer)).await {
+            Ok(Ok((size, from))) => {
+                capture::record(
+                    Direction::Received,
+                    local,
+                    from,
+                    &buffer[..size],
+                    (from.ip() != gateway_ip).then_some("not from the gateway"),
+                );
 
-        match tokio::time::timeout(
-            Duration::from_millis(BASE_TIMEOUT * 2_u64.pow(tries)),
-            socket.recv_from(&mut buffer),
-        )
-        .await
-        {
-            Ok(Ok((_size, from))) => {
                 // ignore response if it isn't from the gateway we sent it to
                 // source: https://www.rfc-editor.org/rfc/rfc6886#page-6:~:text=Upon%20receiving%20a%20response%20packet%2C%20the%20client%20MUST%20check%20the%20source%20IP%0A%20%20%20address%2C%20and%20silently%20discard%20the%20packet%20if%20the%20address%20is%20not%20the%0A%20%20%20address%20of%20the%20gateway%20to%20which%20the%20request%20was%20sent.
                 if from.ip() == gateway_ip {
-                    return parse_raw_response::<R>(&request, &buffer.freeze());
+                    let response = request.parse_response(
+                        RequestContext::new(gateway_ip, request.opcode(), tries),
+                        &buffer.freeze(),
+                    );
+
+                    telemetry::record_response(
+                        gateway_ip,
+                        request.version(),
+                        request.opcode(),
+                        sent_at.elapsed(),
+                        &response,
+                    );
+
+                    return response;
                 }
 
-                // ignore try. We clear the buffer as recv_from might return less next time and overwrite only the first bytes.
-                buffer.clear();
+                // ignore try. We zero the buffer as recv_from might return less next time and overwrite only the first bytes.
+                // Clearing it instead would leave no room to receive into.
+                buffer.fill(0);
+            },
+            Ok(Err(error)) if error.kind() == ErrorKind::WouldBlock => {},
+            // ICMP Port Unreachable, nothing listens on the NAT-PMP port
+            Ok(Err(error)) if error.kind() == ErrorKind::ConnectionRefused => {
+                return Err(NATPMPError::Refused {
+                    context: RequestContext::new(gateway_ip, request.opcode(), tries),
+                });
             },
             Ok(Err(error)) => {
-                if error.kind() != ErrorKind::WouldBlock {
-                    event!(Level::ERROR, ?error);
-                    // TODO if error = ICMP Port Unreachable we should break, else continue
-                    break;
-                }
+                return Err(NATPMPError::Network {
+                    context: Some(RequestContext::new(gateway_ip, request.opcode(), tries)),
+                    source: error,
+                });
             },
             Err(_) => {
-                event!(Level::WARN, "Connection timed out, try {}/{}", tries, retry);
+                telemetry::record_timeout(request.version(), request.opcode());
+
+                event!(Level::WARN, "Connection timed out, try {}", tries);
             },
         }
     }
 
-    Err(NATPMPError::Unsupported)
+    Err(NATPMPError::TimedOut {
+        context: RequestContext::new(gateway_ip, request.opcode(), attempts.made()),
+    })
 }
@@ -1,21 +1,29 @@
 mod build_env;
+mod cli;
+mod commands;
+mod hooks;
+mod maintainer;
+mod mapping_set;
+mod prometheus;
+#[cfg(feature = "torrent-clients")]
+mod torrent_clients;
 mod utils;
 
 use std::env;
 use std::env::VarError;
-use std::net::Ipv4Addr;
-use std::num::NonZeroU16;
+use std::process::ExitCode;
 
 use color_eyre::config::HookBuilder;
 use color_eyre::eyre;
-use natpmp_rs::protocol::MappingProtocol;
-use natpmp_rs::{get_public_address, map_port};
+use natpmp_rs::binding::Binding;
+use natpmp_rs::capture::{Capture, close_capture, set_capture};
 use tracing::{Level, event};
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
 use tracing_subscriber::{EnvFilter, Layer as _};
 
 use crate::build_env::get_build_env;
+use crate::cli::{Cli, Command};
 use crate::utils::flatten_handle;
 
 #[global_allocator]
@@ -70,10 +78,12 @@ fn print_header() {
     );
 }
 
-fn main() -> Result<(), eyre::Report> {
+fn main() -> Result<ExitCode, eyre::Report> {
     // set up .env
     // dotenv().expect(".env file not found");
 
+    let cli = Cli::parse_checked();
+
     HookBuilder::default()
         .capture_span_trace_by_default(true)
         .display_env_section(false)
@@ -87,7 +97,7 @@ fn main() -> Result<(), eyre::Report> {
     parsing_error.map_or(Ok(()), Err)?;
 
     // initialize the runtime
-    let result: Result<(), eyre::Report> = tokio::runtime::Builder::new_multi_thread()
+    let result: Result<ExitCode, eyre::Report> = tokio::runtime::Builder::new_multi_thread()
         .enable_io()
         .enable_time()
         .build()
@@ -95,7 +105,7 @@ fn main() -> Result<(), eyre::Report> {
         .block_on(async {
             // explicitly launch everything in a spawned task
             // see https://docs.rs/tokio/latest/tokio/attr.main.html#non-worker-async-function
-            let handle = tokio::task::spawn(start_tasks());
+            let handle = tokio::task::spawn(start_tasks(cli));
 
             flatten_handle(handle).await
         });
@@ -105,40 +115,42 @@ fn main() -> Result<(), eyre::Report> {
 
 /// starts all the tasks, such as the web server, the key refresh, ...
 /// ensures all tasks are gracefully shutdown in case of error, ctrl-c or `SIGTERM`
-async fn start_tasks() -> Result<(), eyre::Report> {
+async fn start_tasks(cli: Cli) -> Result<ExitCode, eyre::Report> {
     print_header();
 
-    let result = map_port(
-        MappingProtocol::TCP,
-        NonZeroU16::new(9_999).unwrap(),
-        // Some(unsafe { NonZeroU16::new_unchecked(10_000) }),
-        None,
-        None,
-        // None,
-        Ipv4Addr::new(192, 168, 12, 55).into(),
-        None,
-    )
-    .await;
-
-    match result {
-        Ok(response) => {
-            println!("{}", response);
-        },
-        Err(error) => {
-            println!("{}", error);
-        },
+    if let Some(metrics_listen) = cli.metrics_listen {
+        prometheus::install(metrics_listen)?;
+
+        event!(Level::INFO, %metrics_listen, "Serving metrics");
     }
 
-    let result = get_public_address(Ipv4Addr::new(192, 168, 12, 1).into(), None).await;
+    let binding = cli.binding();
 
-    match result {
-        Ok(ip_addr) => {
-            println!("{}", ip_addr);
-        },
-        Err(error) => {
-            println!("{}", error);
-        },
+    if binding != Binding::default() {
+        event!(Level::INFO, %binding, "Binding requests");
     }
 
-    Ok(())
+    if let Some(ref capture) = cli.capture {
+        event!(Level::INFO, file = %capture.display(), "Capturing NAT-PMP and PCP traffic");
+
+        set_capture(Some(Capture::create(capture)?));
+    }
+
+    let result = match cli.command {
+        Command::Run(args) => commands::run::run(args, binding).await,
+        Command::Daemon(args) => commands::daemon::daemon(args, binding).await,
+        Command::Vpn(args) => commands::vpn::vpn(args, binding).await,
+        Command::Probe(args) => commands::probe::probe(args, binding).await,
+        Command::Doctor(args) => commands::doctor::doctor(args, binding).await,
+        Command::Conformance(args) => commands::conformance::conformance(args, binding).await,
+        Command::Simulate(args) => commands::simulate::simulate(args).await,
+        Command::Reflector(args) => commands::reflector::reflector(args).await,
+        Command::Decode(args) => commands::decode::decode(&args),
+        Command::Raw(args) => commands::raw::raw(args, binding).await,
+    };
+
+    // the datagrams still queued for the capture would be lost when we exit
+    close_capture().await;
+
+    result
 }
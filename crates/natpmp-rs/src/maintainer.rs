@@ -0,0 +1,256 @@
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use natpmp_rs::announcements::{Announcement, AnnouncementListener, ExternalAddressHistory};
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::responses::{ExternalAddressResponse, MappingResponse};
+use tokio::time::{Instant, sleep_until};
+use tracing::{Level, event};
+
+use crate::cli::{GatewayArgs, HookArgs, MappingArgs, WatchArgs};
+use crate::hooks::{Change, Hooks};
+use crate::mapping_set::MappingSet;
+use crate::prometheus::{METRICS_REFRESH_INTERVAL, record_external_address, record_mappings};
+
+/// Keeps a `MappingSet` alive and watches the public address, running the hooks for every change.
+///
+/// Changes are picked up from renewal responses, from periodically asking the gateway for its address,
+/// and from the gateway's announcements.
+pub struct Maintainer {
+    client: Client,
+    mapping_set: MappingSet,
+    public_ip: Ipv4Addr,
+    history: ExternalAddressHistory,
+    poll_interval: Duration,
+    next_renewal: Instant,
+    /// `None` when the poll interval is too long to represent, and we never poll
+    next_poll: Option<Instant>,
+    next_metrics_refresh: Instant,
+    announcements: Option<AnnouncementListener>,
+    hooks: Hooks,
+}
+
+enum Wakeup {
+    Renew,
+    Poll,
+    RefreshMetrics,
+    Announcement(Result<Announcement, NATPMPError>),
+}
+
+impl Maintainer {
+    pub async fn start(
+        gateway_args: &GatewayArgs,
+        mapping_args: &MappingArgs,
+        hook_args: &HookArgs,
+        watch_args: &WatchArgs,
+        binding: Binding,
+    ) -> Result<Maintainer, eyre::Report> {
+        let client = gateway_args.client(binding).await?;
+        let gateway_ip = client.gateway_ip();
+
+        let mut mapping_set = MappingSet::map(client.clone(), mapping_args).await?;
+
+        let external_address = match client.get_external_address().await {
+            Ok(external_address) => external_address,
+            Err(error) => {
+                mapping_set.unmap().await;
+
+                return Err(error.into());
+            },
+        };
+
+        let public_ip = external_address.ipv4_address();
+
+        // the epoch to tell a restart of the gateway by
+        let mut history = ExternalAddressHistory::new();
+        history.record(gateway_ip, &external_address);
+
+        // announcements are a nice-to-have, we still poll when we can't receive them
+        let announcements =
+            AnnouncementListener::bind_with_binding(Ipv4Addr::UNSPECIFIED, client.binding())
+                .await
+                .inspect_err(|error| {
+                    event!(
+                        Level::WARN,
+                        ?error,
+                        "Failed to listen for gateway announcements"
+                    );
+                })
+                .ok();
+
+        let hooks = match Hooks::spawn(hook_args) {
+            Ok(hooks) => hooks,
+            Err(error) => {
+                mapping_set.unmap().await;
+
+                return Err(error);
+            },
+        };
+
+        // report where we start from, so that hooks and torrent clients don't need to wait for the first change
+        hooks.notify(Change::PublicIp {
+            gateway: gateway_ip,
+            old: Ipv4Addr::UNSPECIFIED,
+            new: public_ip,
+        });
+
+        for mapping in mapping_set.mappings() {
+            hooks.notify(Change::ExternalPort {
+                gateway: gateway_ip,
+                protocol: mapping.protocol(),
+                internal_port: mapping.internal_port(),
+                old: 0,
+                new: mapping.external_port().map_or(0, NonZeroU16::get),
+            });
+        }
+
+        let poll_interval = Duration::from_secs(watch_args.poll_interval);
+        let now = Instant::now();
+
+        Ok(Maintainer {
+            client,
+            next_renewal: now + mapping_set.renew_in(),
+            next_poll: now.checked_add(poll_interval),
+            next_metrics_refresh: now,
+            mapping_set,
+            public_ip,
+            history,
+            poll_interval,
+            announcements,
+            hooks,
+        })
+    }
+
+    pub fn public_ip(&self) -> Ipv4Addr {
+        self.public_ip
+    }
+
+    pub fn mappings(&self) -> impl Iterator<Item = &MappingResponse> {
+        self.mapping_set.mappings()
+    }
+
+    /// Waits for the next renewal, poll or announcement, and handles it.
+    ///
+    /// Meant to be called in a loop, and can be cancelled at any time.
+    pub async fn step(&mut self) {
+        let wakeup = tokio::select! {
+            () = sleep_until(self.next_renewal) => Wakeup::Renew,
+            () = poll_at(self.next_poll) => Wakeup::Poll,
+            () = sleep_until(self.next_metrics_refresh) => Wakeup::RefreshMetrics,
+            announcement = next_announcement(self.announcements.as_ref()) => Wakeup::Announcement(announcement),
+        };
+
+        match wakeup {
+            Wakeup::Renew => self.renew().await,
+            Wakeup::Poll => {
+                self.next_poll = Instant::now().checked_add(self.poll_interval);
+
+                match self.client.get_external_address().await {
+                    Ok(response) => self.handle_external_address(&response).await,
+                    Err(error) => {
+                        event!(Level::ERROR, ?error, "Failed to get public address");
+                    },
+                }
+            },
+            Wakeup::RefreshMetrics => {
+                self.next_metrics_refresh = Instant::now() + METRICS_REFRESH_INTERVAL;
+
+                self.refresh_metrics();
+            },
+            Wakeup::Announcement(Ok(announcement)) => {
+                self.handle_announcement(&announcement).await;
+            },
+            Wakeup::Announcement(Err(error)) => {
+                event!(Level::WARN, ?error, "Received invalid announcement");
+            },
+        }
+    }
+
+    /// Deletes the mappings and waits for the hooks that are still queued.
+    pub async fn stop(mut self) {
+        self.mapping_set.unmap().await;
+        self.hooks.shutdown().await;
+    }
+
+    async fn renew(&mut self) {
+        for change in self.mapping_set.renew().await {
+            self.hooks.notify(change);
+        }
+
+        self.next_renewal = Instant::now() + self.mapping_set.renew_in();
+
+        self.refresh_metrics();
+    }
+
+    async fn handle_announcement(&mut self, announcement: &Announcement) {
+        // only our gateway has a say about our public address
+        if announcement.source() != self.client.gateway_ip() {
+            event!(Level::DEBUG, source = %announcement.source(), "Ignoring announcement from other gateway");
+
+            return;
+        }
+
+        self.handle_external_address(announcement.response()).await;
+    }
+
+    /// Takes in what the gateway told about its external address, through an announcement or when polled, and
+    /// renews the mappings when the address changed or the gateway restarted and might have lost them.
+    async fn handle_external_address(&mut self, response: &ExternalAddressResponse) {
+        let change = self.history.record(self.client.gateway_ip(), response);
+        let public_ip_changed = response.ipv4_address() != self.public_ip;
+
+        self.update_public_ip(response.ipv4_address());
+
+        if public_ip_changed || change.epoch_regressed() {
+            event!(
+                Level::INFO,
+                public_ip_changed,
+                gateway_restarted = change.epoch_regressed(),
+                "Renewing mappings"
+            );
+
+            self.renew().await;
+        }
+    }
+
+    fn refresh_metrics(&self) {
+        record_mappings(self.mappings());
+        record_external_address(self.client.gateway_ip(), None, self.public_ip);
+    }
+
+    fn update_public_ip(&mut self, public_ip: Ipv4Addr) {
+        if public_ip != self.public_ip {
+            record_external_address(self.client.gateway_ip(), Some(self.public_ip), public_ip);
+
+            self.hooks.notify(Change::PublicIp {
+                gateway: self.client.gateway_ip(),
+                old: self.public_ip,
+                new: public_ip,
+            });
+
+            self.public_ip = public_ip;
+
+            self.refresh_metrics();
+        }
+    }
+}
+
+async fn poll_at(next_poll: Option<Instant>) {
+    match next_poll {
+        Some(next_poll) => sleep_until(next_poll).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_announcement(
+    listener: Option<&AnnouncementListener>,
+) -> Result<Announcement, NATPMPError> {
+    match listener {
+        Some(listener) => listener.recv().await,
+        None => std::future::pending().await,
+    }
+}
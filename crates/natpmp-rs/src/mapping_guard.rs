@@ -0,0 +1,207 @@
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until};
+use tracing::{Level, event};
+
+use crate::client::Client;
+use crate::errors::NATPMPError;
+use crate::protocol::MappingProtocol;
+use crate::responses::MappingResponse;
+
+/// Owns a mapping: keeps it renewed in the background, and deletes it when released or dropped.
+///
+/// Dropping the guard deletes the mapping on a spawned task, which is best effort: when the runtime
+/// is shutting down the request might never be sent, and the mapping stays until it expires.
+/// Use `release()` to know the mapping is gone.
+#[must_use = "the mapping is deleted when the guard is dropped"]
+pub struct MappingGuard {
+    client: Client,
+    protocol: MappingProtocol,
+    internal_port: NonZeroU16,
+    response: watch::Receiver<MappingResponse>,
+    external_port: watch::Receiver<Option<NonZeroU16>>,
+    renewal: Option<JoinHandle<()>>,
+}
+
+impl MappingGuard {
+    pub(crate) fn new(client: Client, lifetime: u32, response: MappingResponse) -> MappingGuard {
+        let protocol = response.protocol();
+        let internal_port = response.internal_port();
+
+        let (external_port_sender, external_port) = watch::channel(response.external_port());
+        let (response_sender, response) = watch::channel(response);
+
+        let renewal = tokio::task::spawn(keep_renewed(
+            client.clone(),
+            lifetime,
+            response_sender,
+            external_port_sender,
+        ));
+
+        MappingGuard {
+            client,
+            protocol,
+            internal_port,
+            response,
+            external_port,
+            renewal: Some(renewal),
+        }
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    #[must_use]
+    pub fn protocol(&self) -> MappingProtocol {
+        self.protocol
+    }
+
+    #[must_use]
+    pub fn internal_port(&self) -> NonZeroU16 {
+        self.internal_port
+    }
+
+    /// The response of the last successful (re)mapping.
+    #[must_use]
+    pub fn response(&self) -> MappingResponse {
+        self.response.borrow().clone()
+    }
+
+    /// The external port we were granted last. The receiver is notified when a renewal grants a different one.
+    #[must_use]
+    pub fn external_port(&self) -> watch::Receiver<Option<NonZeroU16>> {
+        self.external_port.clone()
+    }
+
+    /// Stops renewing, and deletes the mapping.
+    ///
+    /// # Errors
+    /// When the gateway doesn't confirm the deletion
+    pub async fn release(mut self) -> Result<(), NATPMPError> {
+        if let Some(renewal) = self.renewal.take() {
+            renewal.abort();
+        }
+
+        self.client
+            .unmap(self.protocol, self.internal_port)
+            .await
+            .map(|_| ())
+    }
+}
+
+impl Drop for MappingGuard {
+    fn drop(&mut self) {
+        // already released
+        let Some(renewal) = self.renewal.take() else {
+            return;
+        };
+
+        renewal.abort();
+
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            event!(Level::WARN, protocol = %self.protocol, internal_port = self.internal_port, "No runtime to delete the mapping, it stays until it expires");
+
+            return;
+        };
+
+        let client = self.client.clone();
+        let protocol = self.protocol;
+        let internal_port = self.internal_port;
+
+        runtime.spawn(async move {
+            if let Err(error) = client.unmap(protocol, internal_port).await {
+                event!(Level::ERROR, ?error, %protocol, internal_port, "Failed to unmap port");
+            }
+        });
+    }
+}
+
+/// How long to wait before the first renewal after the mapping expired.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Halfway to expiry, as the client SHOULD begin trying to renew the mapping halfway to expiry time.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.3>
+///
+/// Once the mapping expired, `backoff` from now, which doubles every time up to the lifetime, so that a gateway that
+/// keeps failing isn't asked every second.
+fn next_renewal(expires_at: Instant, backoff: &mut Duration, lifetime: u32) -> Instant {
+    let now = Instant::now();
+
+    if now < expires_at {
+        return now + ((expires_at - now) / 2).max(Duration::from_secs(1));
+    }
+
+    let renew_at = now + *backoff;
+
+    *backoff = (*backoff * 2).min(Duration::from_secs(lifetime.into()).max(FIRST_BACKOFF));
+
+    renew_at
+}
+
+/// Renews the mapping halfway to its expiry, requesting the external port we were granted before.
+///
+/// A failed renewal is tried again halfway to the expiry of the last successful one, unless the error is permanent,
+/// and with a growing backoff once that expired.
+async fn keep_renewed(
+    client: Client,
+    lifetime: u32,
+    response: watch::Sender<MappingResponse>,
+    external_port: watch::Sender<Option<NonZeroU16>>,
+) {
+    let mut expires_at = Instant::from_std(response.borrow().expires_at());
+    let mut backoff = FIRST_BACKOFF;
+    let mut renew_at = next_renewal(expires_at, &mut backoff, lifetime);
+
+    loop {
+        tokio::select! {
+            () = sleep_until(renew_at) => {},
+            // the guard is gone
+            () = response.closed() => break,
+        }
+
+        let current = response.borrow().clone();
+
+        match client
+            .map_once(
+                current.protocol(),
+                current.internal_port(),
+                current.external_port(),
+                lifetime,
+            )
+            .await
+        {
+            Ok(renewed) => {
+                event!(Level::DEBUG, mapping = %renewed, "Renewed mapping");
+
+                expires_at = Instant::from_std(renewed.expires_at());
+
+                external_port.send_if_modified(|external_port| {
+                    let changed = *external_port != renewed.external_port();
+
+                    *external_port = renewed.external_port();
+
+                    changed
+                });
+
+                response.send_replace(renewed);
+
+                backoff = FIRST_BACKOFF;
+            },
+            Err(error) if error.is_permanent() => {
+                // asking again won't change the gateway's mind, the mapping stays until it expires
+                event!(Level::ERROR, ?error, mapping = %current, "Failed to renew mapping, giving up");
+
+                break;
+            },
+            Err(error) => {
+                event!(Level::ERROR, ?error, mapping = %current, "Failed to renew mapping");
+            },
+        }
+
+        renew_at = next_renewal(expires_at, &mut backoff, lifetime);
+    }
+}
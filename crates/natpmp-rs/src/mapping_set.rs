@@ -0,0 +1,221 @@
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::time::{Duration, Instant};
+
+use natpmp_rs::batch::MappingEntry;
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::policy::ExternalPortPolicy;
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::responses::MappingResponse;
+use tracing::{Level, event};
+
+use crate::cli::MappingArgs;
+use crate::hooks::Change;
+
+/// A group of mappings that are created, renewed and deleted together.
+pub struct MappingSet {
+    client: Client,
+    lifetime: u32,
+    concurrency: NonZeroUsize,
+    mappings: Vec<MappingResponse>,
+}
+
+impl MappingSet {
+    /// Maps all ports and port ranges in `mapping_args`, requesting the same external port as the internal one.
+    ///
+    /// When one of the mappings fails, the ones that were already created are deleted again. So are all of them
+    /// when a mapping was granted a different external port that `--port-policy` doesn't accept.
+    pub async fn map(
+        client: Client,
+        mapping_args: &MappingArgs,
+    ) -> Result<MappingSet, NATPMPError> {
+        let entries = mapping_args
+            .tcp
+            .iter()
+            .map(|&port| MappingEntry::new(MappingProtocol::TCP, port, Some(port)))
+            .chain(
+                mapping_args
+                    .udp
+                    .iter()
+                    .map(|&port| MappingEntry::new(MappingProtocol::UDP, port, Some(port))),
+            )
+            .chain(
+                mapping_args
+                    .tcp_range
+                    .iter()
+                    .flat_map(|range| range.entries(MappingProtocol::TCP)),
+            )
+            .chain(
+                mapping_args
+                    .udp_range
+                    .iter()
+                    .flat_map(|range| range.entries(MappingProtocol::UDP)),
+            )
+            .collect::<Vec<_>>();
+
+        let responses = client
+            .map_ports_bounded(
+                &entries,
+                Some(mapping_args.lifetime),
+                mapping_args.concurrency,
+            )
+            .await?;
+
+        for response in &responses {
+            if response.is_different_port() {
+                event!(Level::WARN, %response, "Mapped port, but to a different external port than requested");
+            } else {
+                event!(Level::INFO, %response, "Mapped port");
+            }
+        }
+
+        let mut mapping_set = MappingSet {
+            client,
+            lifetime: mapping_args.lifetime,
+            concurrency: mapping_args.concurrency,
+            mappings: responses,
+        };
+
+        if let Err(error) = mapping_set
+            .apply_policy(&mapping_args.external_port_policy())
+            .await
+        {
+            mapping_set.unmap().await;
+
+            return Err(error);
+        }
+
+        Ok(mapping_set)
+    }
+
+    /// Replaces the mappings that were granted a different external port by ones that `policy` accepts.
+    async fn apply_policy(&mut self, policy: &ExternalPortPolicy) -> Result<(), NATPMPError> {
+        if matches!(*policy, ExternalPortPolicy::Any) {
+            return Ok(());
+        }
+
+        for mapping in &mut self.mappings {
+            let Some(requested) = mapping
+                .requested_external_port()
+                .filter(|_| mapping.is_different_port())
+            else {
+                continue;
+            };
+
+            if matches!(*policy, ExternalPortPolicy::Exact) {
+                return Err(NATPMPError::ExternalPortMismatch {
+                    requested: requested.get(),
+                    granted: mapping.external_port().map_or(0, NonZeroU16::get),
+                });
+            }
+
+            // the requested port is taken, so only the fallbacks are left to try, and the gateway would hand
+            // the existing mapping back rather than move it
+            self.client
+                .unmap(mapping.protocol(), mapping.internal_port())
+                .await?;
+
+            *mapping = self
+                .client
+                .map_once_accepting(
+                    mapping.protocol(),
+                    mapping.internal_port(),
+                    None,
+                    policy,
+                    self.lifetime,
+                )
+                .await?;
+
+            event!(Level::INFO, response = %mapping, "Mapped port to a fallback external port");
+        }
+
+        Ok(())
+    }
+
+    pub fn mappings(&self) -> impl Iterator<Item = &MappingResponse> {
+        self.mappings.iter()
+    }
+
+    /// The time until the next renewal is due, which is halfway to the first expiry.
+    pub fn renew_in(&self) -> Duration {
+        // Source: https://www.rfc-editor.org/rfc/rfc6886#section-3.3:~:text=The%20client%20SHOULD%20begin%20trying%20to%20renew%20the%20mapping%20halfway%20to%20expiry%20time
+        let first_expiry = self.mappings().map(MappingResponse::expires_at).min();
+
+        let until_expiry = first_expiry.map_or(
+            Duration::from_secs(u64::from(self.lifetime)),
+            |expires_at| expires_at.saturating_duration_since(Instant::now()),
+        );
+
+        (until_expiry / 2).max(Duration::from_secs(1))
+    }
+
+    /// Renews all mappings, requesting the external port we were granted before.
+    ///
+    /// A mapping that fails to renew keeps its previous response, and is tried again at the next renewal.
+    ///
+    /// Returns the external ports that changed.
+    pub async fn renew(&mut self) -> Vec<Change> {
+        let results = match self
+            .client
+            .renew_ports(&self.mappings, Some(self.lifetime), self.concurrency)
+            .await
+        {
+            Ok(results) => results,
+            Err(error) => {
+                event!(Level::ERROR, ?error, "Failed to renew mappings");
+
+                return vec![];
+            },
+        };
+
+        let mut changes = vec![];
+
+        for (current, result) in self.mappings.iter_mut().zip(results) {
+            match result {
+                Ok(response) => {
+                    if response.external_port() != current.external_port() {
+                        changes.push(Change::ExternalPort {
+                            gateway: self.client.gateway_ip(),
+                            protocol: response.protocol(),
+                            internal_port: response.internal_port(),
+                            old: current.external_port().map_or(0, NonZeroU16::get),
+                            new: response.external_port().map_or(0, NonZeroU16::get),
+                        });
+                    }
+
+                    *current = response;
+                },
+                Err(error) => {
+                    event!(Level::ERROR, ?error, mapping = %current, "Failed to renew mapping");
+                },
+            }
+        }
+
+        changes
+    }
+
+    /// Deletes all mappings. Failures are logged, as there is nothing else we can do about them.
+    pub async fn unmap(&mut self) {
+        let mappings = std::mem::take(&mut self.mappings);
+
+        let results = match self.client.unmap_ports(&mappings, self.concurrency).await {
+            Ok(results) => results,
+            Err(error) => {
+                event!(Level::ERROR, ?error, "Failed to unmap ports");
+
+                return;
+            },
+        };
+
+        for (mapping, result) in mappings.iter().zip(results) {
+            match result {
+                Ok(_) => {
+                    event!(Level::INFO, %mapping, "Unmapped port");
+                },
+                Err(error) => {
+                    event!(Level::ERROR, ?error, %mapping, "Failed to unmap port");
+                },
+            }
+        }
+    }
+}
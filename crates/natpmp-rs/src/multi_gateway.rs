@@ -0,0 +1,295 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::num::NonZeroU16;
+use std::sync::Arc;
+
+use tracing::{Level, event};
+
+use crate::binding::Binding;
+use crate::client::Client;
+use crate::errors::NATPMPError;
+use crate::get_gateway_addrs;
+use crate::mapping_guard::MappingGuard;
+use crate::protocol::MappingProtocol;
+use crate::retry::RetryPolicy;
+
+/// Talks to several gateways at once, e.g. one per uplink of a multi-WAN host.
+///
+/// Every request goes to all gateways concurrently, and the outcome is reported per gateway, as one uplink
+/// being down shouldn't take the others with it.
+#[derive(Debug, Clone)]
+pub struct MultiGatewayClient {
+    clients: Vec<Client>,
+}
+
+impl MultiGatewayClient {
+    #[must_use]
+    pub fn new(gateways: &[Ipv4Addr]) -> MultiGatewayClient {
+        MultiGatewayClient {
+            clients: gateways
+                .iter()
+                .map(|&gateway| Client::new(gateway))
+                .collect(),
+        }
+    }
+
+    /// Uses the given clients, e.g. each with the binding of its own uplink, see `Client::with_binding()`.
+    #[must_use]
+    pub fn from_clients(clients: Vec<Client>) -> MultiGatewayClient {
+        MultiGatewayClient { clients }
+    }
+
+    /// Creates a client for the gateway of every default route, see `get_gateway_addrs()`.
+    ///
+    /// The address of a gateway that is behind several interfaces only tells which one it is together with the
+    /// interface, so its clients are bound to their interface, see `Binding::with_interface()`.
+    ///
+    /// # Errors
+    /// When there is no default route
+    pub fn discover() -> Result<MultiGatewayClient, NATPMPError> {
+        let routes = get_gateway_addrs()?;
+
+        let clients = routes
+            .iter()
+            .map(|&(ref interface, gateway)| {
+                let client = Client::new(gateway);
+
+                if routes
+                    .iter()
+                    .filter(|&&(_, other)| other == gateway)
+                    .count()
+                    > 1
+                {
+                    client.with_binding(Binding::new().with_interface(interface.clone()))
+                } else {
+                    client
+                }
+            })
+            .collect();
+
+        Ok(MultiGatewayClient::from_clients(clients))
+    }
+
+    /// Uses the same `retry_policy` for every gateway, see `Client::with_retry_policy()`.
+    #[must_use]
+    pub fn with_retry_policy<P: RetryPolicy + 'static>(
+        self,
+        retry_policy: P,
+    ) -> MultiGatewayClient {
+        let retry_policy: Arc<dyn RetryPolicy> = Arc::new(retry_policy);
+
+        MultiGatewayClient {
+            clients: self
+                .clients
+                .into_iter()
+                .map(|client| client.with_shared_retry_policy(Arc::clone(&retry_policy)))
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    /// Asks every gateway for its public address.
+    ///
+    /// Returns a result per gateway, in the order of `clients()`.
+    pub async fn get_public_addresses(&self) -> Vec<(Ipv4Addr, Result<Ipv4Addr, NATPMPError>)> {
+        self.on_every_gateway(|client| async move { client.get_public_address().await })
+            .await
+    }
+
+    /// Maps `private_port` on every gateway, see `Client::map()`, and asks every gateway that mapped it
+    /// for its public address.
+    ///
+    /// Gateways that fail are reported in the returned `MultiGatewayMapping`, the others keep their mapping.
+    pub async fn map(
+        &self,
+        protocol: MappingProtocol,
+        private_port: NonZeroU16,
+        public_port: Option<NonZeroU16>,
+        lifetime: Option<u32>,
+    ) -> MultiGatewayMapping {
+        let results = self
+            .on_every_gateway(move |client| async move {
+                let guard = client
+                    .map(protocol, private_port, public_port, lifetime)
+                    .await?;
+
+                // the mapping is still useful when we can't tell where it is, e.g. to a hook that asks again later
+                let public_ip = client
+                    .get_public_address()
+                    .await
+                    .inspect_err(|error| {
+                        event!(Level::WARN, ?error, gateway = %client.gateway_ip(), "Failed to get public address");
+                    })
+                    .ok();
+
+                Ok((guard, public_ip))
+            })
+            .await;
+
+        MultiGatewayMapping {
+            gateways: results
+                .into_iter()
+                .map(|(gateway, result)| match result {
+                    Ok((guard, public_ip)) => GatewayMapping {
+                        gateway,
+                        public_ip,
+                        guard: Ok(guard),
+                    },
+                    Err(error) => GatewayMapping {
+                        gateway,
+                        public_ip: None,
+                        guard: Err(error),
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    /// Runs `request` against every gateway concurrently, and returns the results in the order of `clients()`.
+    async fn on_every_gateway<T, F, Fut>(
+        &self,
+        request: F,
+    ) -> Vec<(Ipv4Addr, Result<T, NATPMPError>)>
+    where
+        T: Send + 'static,
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = Result<T, NATPMPError>> + Send + 'static,
+    {
+        let tasks = self
+            .clients
+            .iter()
+            .map(|client| {
+                (
+                    client.gateway_ip(),
+                    tokio::task::spawn(request(client.clone())),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::with_capacity(tasks.len());
+
+        for (gateway, task) in tasks {
+            // we never abort the tasks, so they can only fail by panicking
+            let result = task
+                .await
+                .unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()));
+
+            results.push((gateway, result));
+        }
+
+        results
+    }
+}
+
+/// The same internal port mapped on several gateways.
+pub struct MultiGatewayMapping {
+    gateways: Vec<GatewayMapping>,
+}
+
+impl MultiGatewayMapping {
+    /// The status per gateway, in the order of `MultiGatewayClient::clients()`.
+    #[must_use]
+    pub fn gateways(&self) -> &[GatewayMapping] {
+        &self.gateways
+    }
+
+    /// Whether at least one gateway mapped the port.
+    #[must_use]
+    pub fn is_mapped_anywhere(&self) -> bool {
+        self.gateways.iter().any(|gateway| gateway.guard.is_ok())
+    }
+
+    /// The public address of the mapping on every gateway that mapped it and told us its public IP.
+    pub fn public_addresses(&self) -> impl Iterator<Item = SocketAddrV4> {
+        self.gateways
+            .iter()
+            .filter_map(GatewayMapping::public_address)
+    }
+
+    /// Asks every gateway that mapped the port for its public address again, e.g. after an uplink reconnected.
+    pub async fn refresh_public_ips(&mut self) {
+        for gateway in &mut self.gateways {
+            let Ok(ref guard) = gateway.guard else {
+                continue;
+            };
+
+            match guard.client().get_public_address().await {
+                Ok(public_ip) => gateway.public_ip = Some(public_ip),
+                Err(error) => {
+                    event!(Level::WARN, ?error, gateway = %gateway.gateway, "Failed to get public address");
+                },
+            }
+        }
+    }
+
+    /// Deletes the mapping on every gateway that has it.
+    ///
+    /// Returns a result per gateway that had the mapping.
+    pub async fn release(self) -> Vec<(Ipv4Addr, Result<(), NATPMPError>)> {
+        let mut results = vec![];
+
+        for gateway in self.gateways {
+            if let Ok(guard) = gateway.guard {
+                results.push((gateway.gateway, guard.release().await));
+            }
+        }
+
+        results
+    }
+}
+
+/// The mapping on one gateway, or why there is none.
+pub struct GatewayMapping {
+    gateway: Ipv4Addr,
+    public_ip: Option<Ipv4Addr>,
+    guard: Result<MappingGuard, NATPMPError>,
+}
+
+impl GatewayMapping {
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    /// The public IP of this gateway, `None` when the mapping failed or the gateway didn't tell us.
+    #[must_use]
+    pub fn public_ip(&self) -> Option<Ipv4Addr> {
+        self.public_ip
+    }
+
+    /// The public IP and the external port we were granted on this gateway.
+    #[must_use]
+    pub fn public_address(&self) -> Option<SocketAddrV4> {
+        let external_port = self.guard().ok()?.response().external_port()?;
+
+        Some(SocketAddrV4::new(self.public_ip?, external_port.get()))
+    }
+
+    /// The guard of the mapping, or the error mapping it on this gateway.
+    ///
+    /// # Errors
+    /// When this gateway didn't map the port
+    pub fn guard(&self) -> Result<&MappingGuard, &NATPMPError> {
+        self.guard.as_ref()
+    }
+}
+
+impl std::fmt::Display for GatewayMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.public_address(), self.guard()) {
+            (Some(public_address), _) => {
+                write!(f, "{}: mapped to {}", self.gateway, public_address)
+            },
+            (None, Ok(guard)) => write!(
+                f,
+                "{}: mapped to external port {}, public IP unknown",
+                self.gateway,
+                guard.response().external_port().map_or(0, NonZeroU16::get)
+            ),
+            (None, Err(error)) => write!(f, "{}: failed: {}", self.gateway, error),
+        }
+    }
+}
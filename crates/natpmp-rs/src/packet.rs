@@ -0,0 +1,523 @@
+//! Decodes NAT-PMP and PCP packets, requests and responses alike, into something a person can read, and sends
+//! hand-made ones to a gateway, for support tickets and for poking at gateway bugs.
+//!
+//! Decoding only fails on packets that are too short for their layout. Unknown opcodes, result codes and
+//! protocols are shown as numbers, and bytes past the layout as trailing bytes.
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use bytes::Buf as _;
+use tracing::{Level, event};
+
+use crate::binding::Binding;
+use crate::capture::{self, Direction};
+use crate::errors::{DecodeError, NATPMPError, NATPMPResultError, RequestContext};
+use crate::pcp::{PCP_VERSION, PcpResponse, PcpResultCode};
+use crate::protocol::Opcode;
+use crate::requests::announce_request::AnnounceRequest;
+use crate::requests::mapping_request::MappingRequest;
+use crate::responses::{ExternalAddressResponse, MappingResponse, Response as _};
+use crate::retry::{Attempts, RetryPolicy};
+use crate::{NATPMP_PORT, VERSION, build_socket, send_request};
+
+/// The version and opcode, which is all an external address request has.
+const NATPMP_REQUEST_HEADER_SIZE: usize = 2;
+
+/// The version, opcode, result code and epoch, which is all an error response needs.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.5>
+const NATPMP_RESPONSE_HEADER_SIZE: usize = 8;
+
+/// The opcode-specific data of MAP and PEER, which follows the common header.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-11.1>
+const PCP_MAP_SIZE: usize = 36;
+const PCP_PEER_SIZE: usize = 56;
+
+/// The largest PCP message, larger than anything NAT-PMP sends, so that oversized replies aren't cut off.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-7>
+const MAX_PACKET_SIZE: usize = 1100;
+
+/// A decoded packet: what it is, and its fields in the order they are on the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    version: u8,
+    opcode: u8,
+    description: String,
+    length: usize,
+    fields: Vec<(&'static str, String)>,
+    trailing: Vec<u8>,
+}
+
+impl Packet {
+    /// 0 for NAT-PMP, 2 for PCP
+    #[must_use]
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// The opcode as sent, with 128 added for responses
+    #[must_use]
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    #[must_use]
+    pub fn is_response(&self) -> bool {
+        self.opcode & 0x80 != 0
+    }
+
+    /// What the packet is, e.g. `NAT-PMP external address response`
+    #[must_use]
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The name and value of every field
+    #[must_use]
+    pub fn fields(&self) -> &[(&'static str, String)] {
+        &self.fields
+    }
+
+    /// The value of the field called `name`, e.g. `External address`
+    #[must_use]
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|&&(field_name, _)| field_name == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// The bytes after the layout of the packet, e.g. PCP options
+    #[must_use]
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+}
+
+impl std::fmt::Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const TRAILING: &str = "Trailing bytes";
+
+        let width = self
+            .fields
+            .iter()
+            .map(|&(name, _)| name.len())
+            .chain((!self.trailing.is_empty()).then_some(TRAILING.len()))
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        write!(f, "{}, {} bytes", self.description, self.length)?;
+
+        for &(name, ref value) in &self.fields {
+            write!(f, "\n  {:<width$} {}", format!("{}:", name), value)?;
+        }
+
+        if !self.trailing.is_empty() {
+            write!(
+                f,
+                "\n  {:<width$} {}",
+                format!("{}:", TRAILING),
+                to_hex(&self.trailing)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes `datagram` as a NAT-PMP or PCP packet, telling them apart by the version.
+///
+/// # Errors
+/// When the version is neither, or the packet is too short for its layout
+pub fn decode(datagram: &[u8]) -> Result<Packet, DecodeError> {
+    require(datagram, NATPMP_REQUEST_HEADER_SIZE)?;
+
+    match datagram.first().copied() {
+        Some(VERSION) => decode_natpmp(datagram),
+        Some(PCP_VERSION) => decode_pcp(datagram),
+        version => Err(DecodeError::UnknownVersion(version.unwrap_or(0))),
+    }
+}
+
+/// Parses hex like `0080000000001234c0a80101`, ignoring a `0x` prefix, whitespace, and `:` or `-` separators,
+/// so that the output of tcpdump and Wireshark can be pasted as is.
+///
+/// # Errors
+/// When there is anything else, or an odd number of digits
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, DecodeError> {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+
+    let digits = text
+        .chars()
+        .filter(|&c| !c.is_whitespace() && c != ':' && c != '-')
+        .map(|c| {
+            c.to_digit(16)
+                .and_then(|digit| u8::try_from(digit).ok())
+                .ok_or_else(|| DecodeError::InvalidHex(format!("'{}' is not a hex digit", c)))
+        })
+        .collect::<Result<Vec<u8>, DecodeError>>()?;
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(DecodeError::InvalidHex(format!(
+            "{} digits, which is not a whole number of bytes",
+            digits.len()
+        )));
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
+}
+
+/// `bytes` as lowercase hex without separators, the way `parse_hex()` takes it.
+#[must_use]
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|&byte| [byte >> 4, byte & 0x0f])
+        .filter_map(|nibble| char::from_digit(u32::from(nibble), 16))
+        .collect()
+}
+
+fn require(datagram: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if datagram.len() < expected {
+        return Err(DecodeError::Truncated {
+            expected,
+            length: datagram.len(),
+        });
+    }
+
+    Ok(())
+}
+
+fn describe(protocol: &str, operation: Option<&str>, opcode: u8) -> String {
+    let kind = if opcode & 0x80 == 0 {
+        "request"
+    } else {
+        "response"
+    };
+
+    match operation {
+        Some(operation) => format!("{} {} {}", protocol, operation, kind),
+        None => format!(
+            "{} {} with unknown opcode {}",
+            protocol,
+            kind,
+            opcode & 0x7f
+        ),
+    }
+}
+
+fn opcode_field(opcode: u8) -> String {
+    if opcode & 0x80 == 0 {
+        opcode.to_string()
+    } else {
+        format!("{} (response to {})", opcode, opcode & 0x7f)
+    }
+}
+
+fn lifetime_field(lifetime: u32) -> String {
+    if lifetime == 0 {
+        "0 s (delete)".to_owned()
+    } else {
+        format!("{} s", lifetime)
+    }
+}
+
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3>
+fn decode_natpmp(datagram: &[u8]) -> Result<Packet, DecodeError> {
+    let mut buffer = datagram;
+
+    let version = buffer.get_u8();
+    let opcode = buffer.get_u8();
+
+    let operation = match opcode & 0x7f {
+        0 => Some("external address"),
+        1 => Some("UDP mapping"),
+        2 => Some("TCP mapping"),
+        _ => None,
+    };
+
+    let mut fields = vec![
+        ("Version", format!("{} (NAT-PMP)", version)),
+        ("Opcode", opcode_field(opcode)),
+    ];
+
+    if opcode & 0x80 == 0 {
+        if matches!(opcode, 1 | 2) {
+            require(datagram, size_of::<MappingRequest>())?;
+
+            let _reserved = buffer.get_u16();
+            let internal_port = buffer.get_u16();
+            let external_port = buffer.get_u16();
+            let lifetime = buffer.get_u32();
+
+            fields.push((
+                "Internal port",
+                if internal_port == 0 && lifetime == 0 {
+                    "0 (all ports)".to_owned()
+                } else {
+                    internal_port.to_string()
+                },
+            ));
+            fields.push(("Suggested external port", external_port.to_string()));
+            fields.push(("Lifetime", lifetime_field(lifetime)));
+        }
+    } else {
+        require(datagram, NATPMP_RESPONSE_HEADER_SIZE)?;
+
+        let result_code = buffer.get_u16();
+
+        fields.push((
+            "Result code",
+            if result_code == 0 {
+                "0 (Success)".to_owned()
+            } else {
+                format!("{} ({})", result_code, NATPMPResultError::from(result_code))
+            },
+        ));
+        fields.push(("Seconds since epoch", buffer.get_u32().to_string()));
+
+        let size = match opcode {
+            128 => ExternalAddressResponse::SIZE,
+            129 | 130 => MappingResponse::SIZE,
+            _ => NATPMP_RESPONSE_HEADER_SIZE,
+        };
+
+        // error responses may stop after the header
+        if result_code == 0 {
+            require(datagram, size)?;
+        }
+
+        if datagram.len() >= size {
+            match opcode {
+                128 => {
+                    fields.push((
+                        "External address",
+                        Ipv4Addr::from(buffer.get_u32()).to_string(),
+                    ));
+                },
+                129 | 130 => {
+                    fields.push(("Internal port", buffer.get_u16().to_string()));
+                    fields.push(("External port", buffer.get_u16().to_string()));
+                    fields.push(("Lifetime", format!("{} s", buffer.get_u32())));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    Ok(Packet {
+        version,
+        opcode,
+        description: describe("NAT-PMP", operation, opcode),
+        length: datagram.len(),
+        fields,
+        trailing: buffer.to_vec(),
+    })
+}
+
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-7>
+fn decode_pcp(datagram: &[u8]) -> Result<Packet, DecodeError> {
+    // requests and responses have a header of the same size
+    require(
+        datagram,
+        size_of::<AnnounceRequest>().max(PcpResponse::SIZE),
+    )?;
+
+    let mut buffer = datagram;
+
+    let version = buffer.get_u8();
+    let opcode = buffer.get_u8();
+
+    let (operation, data_size) = match opcode & 0x7f {
+        0 => (Some("ANNOUNCE"), 0),
+        1 => (Some("MAP"), PCP_MAP_SIZE),
+        2 => (Some("PEER"), PCP_PEER_SIZE),
+        _ => (None, 0),
+    };
+
+    let mut fields = vec![
+        ("Version", format!("{} (PCP)", version)),
+        ("Opcode", opcode_field(opcode)),
+    ];
+
+    let succeeded = if opcode & 0x80 == 0 {
+        let _reserved = buffer.get_u16();
+
+        fields.push(("Lifetime", lifetime_field(buffer.get_u32())));
+        fields.push(("Client address", get_address(&mut buffer)));
+
+        true
+    } else {
+        let _reserved = buffer.get_u8();
+        let result = PcpResultCode::from(buffer.get_u8());
+
+        fields.push(("Result code", format!("{} ({})", result.code(), result)));
+        fields.push(("Lifetime", format!("{} s", buffer.get_u32())));
+        fields.push(("Seconds since epoch", buffer.get_u32().to_string()));
+
+        buffer.advance(12);
+
+        result == PcpResultCode::Success
+    };
+
+    let size = PcpResponse::SIZE + data_size;
+
+    // error responses may leave out the opcode-specific data
+    if succeeded {
+        require(datagram, size)?;
+    }
+
+    if data_size > 0 && datagram.len() >= size {
+        let nonce = buffer.copy_to_bytes(12);
+
+        fields.push(("Mapping nonce", to_hex(&nonce)));
+        fields.push(("Protocol", protocol_field(buffer.get_u8())));
+
+        buffer.advance(3);
+
+        fields.push(("Internal port", buffer.get_u16().to_string()));
+        fields.push(("External port", buffer.get_u16().to_string()));
+        fields.push(("External address", get_address(&mut buffer)));
+
+        if data_size == PCP_PEER_SIZE {
+            fields.push(("Remote peer port", buffer.get_u16().to_string()));
+
+            buffer.advance(2);
+
+            fields.push(("Remote peer address", get_address(&mut buffer)));
+        }
+    }
+
+    Ok(Packet {
+        version,
+        opcode,
+        description: describe("PCP", operation, opcode),
+        length: datagram.len(),
+        fields,
+        trailing: buffer.to_vec(),
+    })
+}
+
+/// A 128-bit address, shown as IPv4 when it's IPv4-mapped, as PCP sends IPv4 addresses.
+fn get_address(buffer: &mut &[u8]) -> String {
+    let address = Ipv6Addr::from(buffer.get_u128());
+
+    address
+        .to_ipv4_mapped()
+        .map_or_else(|| address.to_string(), |address| address.to_string())
+}
+
+/// Source: <https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml>
+fn protocol_field(protocol: u8) -> String {
+    match protocol {
+        0 => "0 (all)".to_owned(),
+        6 => "6 (TCP)".to_owned(),
+        17 => "17 (UDP)".to_owned(),
+        protocol => protocol.to_string(),
+    }
+}
+
+/// What a gateway sent back to a hand-made packet.
+#[derive(Debug, Clone)]
+pub struct RawReply {
+    datagram: Vec<u8>,
+    round_trip: Duration,
+}
+
+impl RawReply {
+    #[must_use]
+    pub fn datagram(&self) -> &[u8] {
+        &self.datagram
+    }
+
+    /// The time between sending the attempt that was answered and receiving the answer
+    #[must_use]
+    pub fn round_trip(&self) -> Duration {
+        self.round_trip
+    }
+
+    /// # Errors
+    /// When the reply isn't a NAT-PMP or PCP packet
+    pub fn decode(&self) -> Result<Packet, DecodeError> {
+        decode(&self.datagram)
+    }
+}
+
+/// Sends `datagram` to the NAT-PMP port of the gateway as is, and returns the first datagram the gateway sends
+/// back, whatever it is.
+pub(crate) async fn send_raw(
+    gateway_ip: Ipv4Addr,
+    datagram: &[u8],
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<RawReply, NATPMPError> {
+    let opcode = Opcode::from(datagram.get(1).copied().unwrap_or(0));
+
+    let socket = build_socket(binding).await?;
+    let local = capture::local_address(
+        &socket,
+        SocketAddr::from((gateway_ip, NATPMP_PORT)),
+        binding,
+    )
+    .await;
+
+    let mut buffer = [0; MAX_PACKET_SIZE];
+
+    let mut attempts = Attempts::new(retry_policy);
+
+    while let Some((tries, wait_until)) = attempts.next() {
+        let sent_at = Instant::now();
+
+        let _size = send_request(&socket, gateway_ip, datagram, local)
+            .await
+            .map_err(|source| NATPMPError::Network {
+                context: Some(RequestContext::new(gateway_ip, opcode, tries)),
+                source,
+            })?;
+
+        match tokio::time::timeout_at(wait_until, socket.recv_from(&mut buffer)).await {
+            Ok(Ok((size, from))) => {
+                capture::record(
+                    Direction::Received,
+                    local,
+                    from,
+                    &buffer[..size],
+                    (from.ip() != gateway_ip).then_some("not from the gateway"),
+                );
+
+                if from.ip() == gateway_ip {
+                    return Ok(RawReply {
+                        datagram: buffer[..size].to_vec(),
+                        round_trip: sent_at.elapsed(),
+                    });
+                }
+            },
+            Ok(Err(error)) if error.kind() == ErrorKind::WouldBlock => {},
+            // ICMP Port Unreachable, nothing listens on the NAT-PMP port
+            Ok(Err(error)) if error.kind() == ErrorKind::ConnectionRefused => {
+                return Err(NATPMPError::Refused {
+                    context: RequestContext::new(gateway_ip, opcode, tries),
+                });
+            },
+            Ok(Err(error)) => {
+                return Err(NATPMPError::Network {
+                    context: Some(RequestContext::new(gateway_ip, opcode, tries)),
+                    source: error,
+                });
+            },
+            Err(_) => {
+                event!(Level::WARN, "Connection timed out, try {}", tries);
+            },
+        }
+    }
+
+    Err(NATPMPError::TimedOut {
+        context: RequestContext::new(gateway_ip, opcode, attempts.made()),
+    })
+}
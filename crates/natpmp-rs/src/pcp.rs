@@ -0,0 +1,248 @@
+//! Just enough of the Port Control Protocol (RFC 6887), NAT-PMP's successor, to tell whether a gateway speaks it.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use bytes::Buf as _;
+
+use crate::binding::Binding;
+use crate::errors::{InvalidResponse, NATPMPError, RequestContext};
+use crate::requests::announce_request::AnnounceRequest;
+use crate::responses::Response;
+use crate::retry::RetryPolicy;
+use crate::send_request_with_retry;
+
+/// PCP servers listen on the NAT-PMP port, and tell the protocols apart by the version.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-9>
+pub const PCP_VERSION: u8 = 2;
+
+/// The result codes of RFC 6887.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-7.4>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcpResultCode {
+    Success,
+    UnsupportedVersion,
+    NotAuthorized,
+    MalformedRequest,
+    UnsupportedOpcode,
+    UnsupportedOption,
+    MalformedOption,
+    NetworkFailure,
+    NoResources,
+    UnsupportedProtocol,
+    UserExceededQuota,
+    CannotProvideExternal,
+    AddressMismatch,
+    ExcessiveRemotePeers,
+    /// A result code that RFC 6887 doesn't define
+    Unknown(u8),
+}
+
+impl PcpResultCode {
+    #[must_use]
+    pub fn code(&self) -> u8 {
+        match *self {
+            PcpResultCode::Success => 0,
+            PcpResultCode::UnsupportedVersion => 1,
+            PcpResultCode::NotAuthorized => 2,
+            PcpResultCode::MalformedRequest => 3,
+            PcpResultCode::UnsupportedOpcode => 4,
+            PcpResultCode::UnsupportedOption => 5,
+            PcpResultCode::MalformedOption => 6,
+            PcpResultCode::NetworkFailure => 7,
+            PcpResultCode::NoResources => 8,
+            PcpResultCode::UnsupportedProtocol => 9,
+            PcpResultCode::UserExceededQuota => 10,
+            PcpResultCode::CannotProvideExternal => 11,
+            PcpResultCode::AddressMismatch => 12,
+            PcpResultCode::ExcessiveRemotePeers => 13,
+            PcpResultCode::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u8> for PcpResultCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PcpResultCode::Success,
+            1 => PcpResultCode::UnsupportedVersion,
+            2 => PcpResultCode::NotAuthorized,
+            3 => PcpResultCode::MalformedRequest,
+            4 => PcpResultCode::UnsupportedOpcode,
+            5 => PcpResultCode::UnsupportedOption,
+            6 => PcpResultCode::MalformedOption,
+            7 => PcpResultCode::NetworkFailure,
+            8 => PcpResultCode::NoResources,
+            9 => PcpResultCode::UnsupportedProtocol,
+            10 => PcpResultCode::UserExceededQuota,
+            11 => PcpResultCode::CannotProvideExternal,
+            12 => PcpResultCode::AddressMismatch,
+            13 => PcpResultCode::ExcessiveRemotePeers,
+            code => PcpResultCode::Unknown(code),
+        }
+    }
+}
+
+impl std::fmt::Display for PcpResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match *self {
+            PcpResultCode::Success => "SUCCESS",
+            PcpResultCode::UnsupportedVersion => "UNSUPP_VERSION",
+            PcpResultCode::NotAuthorized => "NOT_AUTHORIZED",
+            PcpResultCode::MalformedRequest => "MALFORMED_REQUEST",
+            PcpResultCode::UnsupportedOpcode => "UNSUPP_OPCODE",
+            PcpResultCode::UnsupportedOption => "UNSUPP_OPTION",
+            PcpResultCode::MalformedOption => "MALFORMED_OPTION",
+            PcpResultCode::NetworkFailure => "NETWORK_FAILURE",
+            PcpResultCode::NoResources => "NO_RESOURCES",
+            PcpResultCode::UnsupportedProtocol => "UNSUPP_PROTOCOL",
+            PcpResultCode::UserExceededQuota => "USER_EX_QUOTA",
+            PcpResultCode::CannotProvideExternal => "CANNOT_PROVIDE_EXTERNAL",
+            PcpResultCode::AddressMismatch => "ADDRESS_MISMATCH",
+            PcpResultCode::ExcessiveRemotePeers => "EXCESSIVE_REMOTE_PEERS",
+            PcpResultCode::Unknown(code) => return write!(f, "unknown result code {}", code),
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// What a PCP server answered. Any answer, even an error, means the server speaks PCP.
+#[derive(Debug, Clone)]
+pub struct PcpResponse {
+    gateway: Ipv4Addr,
+    opcode: u8,
+    result: PcpResultCode,
+    lifetime: Duration,
+    received_at: Instant,
+    seconds_since_epoch: u32,
+}
+
+impl PcpResponse {
+    /// The server that answered
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    #[must_use]
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    #[must_use]
+    pub fn result(&self) -> PcpResultCode {
+        self.result
+    }
+
+    /// How long the server will keep the answer's mapping, or how long an error is expected to last
+    #[must_use]
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// When we received the response
+    #[must_use]
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// The time since the server started, or lost its mappings
+    /// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-8.5>
+    #[must_use]
+    pub fn gateway_epoch(&self) -> Duration {
+        Duration::from_secs(u64::from(self.seconds_since_epoch))
+    }
+}
+
+impl std::fmt::Display for PcpResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gateway: {}, opcode: {}, result: {}, lifetime: {}, seconds since epoch: {}",
+            self.gateway,
+            self.opcode,
+            self.result,
+            self.lifetime.as_secs(),
+            self.seconds_since_epoch,
+        )
+    }
+}
+
+impl Response for PcpResponse {
+    /// The common response header, ANNOUNCE has no opcode-specific data
+    const SIZE: usize = 24;
+
+    fn seconds_since_epoch(&self) -> u32 {
+        self.seconds_since_epoch
+    }
+
+    fn try_from_bytes(
+        opcode: u8,
+        gateway: Ipv4Addr,
+        mut buffer: &[u8],
+    ) -> Result<Self, InvalidResponse> {
+        let lifetime = buffer.get_u32();
+        let seconds_since_epoch = buffer.get_u32();
+
+        Ok(PcpResponse {
+            gateway,
+            opcode: opcode & 0x7f,
+            result: PcpResultCode::Success,
+            lifetime: Duration::from_secs(u64::from(lifetime)),
+            received_at: Instant::now(),
+            seconds_since_epoch,
+        })
+    }
+}
+
+/// Like `parse_raw_response()`, but for the PCP response header, whose result code is a single byte.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-7.2>
+pub(crate) fn parse_pcp_response(
+    request_opcode: u8,
+    context: RequestContext,
+    mut buffer: &[u8],
+) -> Result<PcpResponse, NATPMPError> {
+    // a NAT-PMP gateway answers a PCP request with its own version and `UNSUPP_VERSION`
+    // Source: https://www.rfc-editor.org/rfc/rfc6887#section-9
+    if buffer.get_u8() != PCP_VERSION {
+        return Err(NATPMPError::Unsupported { context });
+    }
+
+    let opcode = buffer.get_u8();
+
+    if opcode & 0x80 == 0 || opcode & 0x7f != request_opcode {
+        return Err(NATPMPError::InvalidResponse {
+            context,
+            reason: InvalidResponse::OpcodeMismatch {
+                expected: request_opcode,
+                received: opcode,
+            },
+        });
+    }
+
+    let _reserved = buffer.get_u8();
+    let result = PcpResultCode::from(buffer.get_u8());
+
+    PcpResponse::try_from_bytes(opcode, context.gateway(), buffer)
+        .map(|response| PcpResponse { result, ..response })
+        .map_err(|reason| NATPMPError::InvalidResponse { context, reason })
+}
+
+/// Sends a PCP ANNOUNCE to `server`, with `client_ip` as the address the server should see us as.
+///
+/// `NATPMPError::Unsupported` when the server answers as a NAT-PMP gateway.
+pub(crate) async fn request_announce(
+    server: Ipv4Addr,
+    client_ip: Ipv4Addr,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<PcpResponse, NATPMPError> {
+    send_request_with_retry(
+        server,
+        AnnounceRequest::new(client_ip),
+        retry_policy,
+        binding,
+    )
+    .await
+}
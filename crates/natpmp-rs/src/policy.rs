@@ -0,0 +1,123 @@
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+
+use tracing::{Level, event};
+
+use crate::client::Client;
+use crate::errors::NATPMPError;
+use crate::port_range::PortRange;
+use crate::protocol::MappingProtocol;
+use crate::resolve_gateway;
+use crate::responses::MappingResponse;
+use crate::retry::Rfc6886;
+
+/// What to do when the gateway grants a different external port than the one requested, which it is
+/// allowed to do.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.3>
+#[derive(Debug, Clone, Default)]
+pub enum ExternalPortPolicy {
+    /// Take whatever port the gateway grants
+    #[default]
+    Any,
+    /// Only the requested port will do. Any other port is deleted again, and reported as
+    /// `NATPMPError::ExternalPortMismatch`
+    Exact,
+    /// Like `Exact`, but try these ports, in order, after the requested one
+    Fallback(Vec<NonZeroU16>),
+    /// Like `Exact`, but try the ports of this range, in order, after the requested one
+    FallbackRange(PortRange),
+}
+
+impl ExternalPortPolicy {
+    /// The external ports to try in order, `None` when any port will do.
+    fn candidates(&self, requested: Option<NonZeroU16>) -> Option<Vec<NonZeroU16>> {
+        let fallbacks = match *self {
+            ExternalPortPolicy::Any => return None,
+            ExternalPortPolicy::Exact => vec![],
+            ExternalPortPolicy::Fallback(ref ports) => ports.clone(),
+            ExternalPortPolicy::FallbackRange(range) => range.ports().collect(),
+        };
+
+        let candidates = requested.into_iter().chain(fallbacks).collect::<Vec<_>>();
+
+        // without a port to ask for, whatever we get is what we asked for
+        (!candidates.is_empty()).then_some(candidates)
+    }
+}
+
+/// Like `map_port()`, but applies `policy` when the gateway grants a different external port.
+///
+/// # Arguments
+/// * `protocol` - `Protocol::TCP` or `Protocol::UDP`
+/// * `private_port` - the private port of the mapping requested
+/// * `public_port` - the public port of the mapping requested
+/// * `policy` - what to do with a different external port
+/// * `lifetime` - the duration of the mapping in seconds, defaults to 7200, per specification.
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
+/// * `retry` - the number of times to retry the request if unsuccessful, defaults to 9 as per specification.
+///
+/// # Errors
+///
+/// `NATPMPError::ExternalPortMismatch` when none of the acceptable ports was granted, with the last port
+/// the gateway offered instead
+pub async fn map_port_with_policy(
+    protocol: MappingProtocol,
+    private_port: NonZeroU16,
+    public_port: Option<NonZeroU16>,
+    policy: &ExternalPortPolicy,
+    lifetime: Option<u32>,
+    gateway_ip: Option<Ipv4Addr>,
+    retry: Option<u32>,
+) -> Result<MappingResponse, NATPMPError> {
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+    let client = Client::new(gateway_ip).with_retry_policy(Rfc6886::from_retry(retry));
+
+    map_accepting(
+        &client,
+        protocol,
+        private_port,
+        public_port,
+        policy,
+        lifetime.unwrap_or(7200),
+    )
+    .await
+}
+
+pub(crate) async fn map_accepting(
+    client: &Client,
+    protocol: MappingProtocol,
+    private_port: NonZeroU16,
+    public_port: Option<NonZeroU16>,
+    policy: &ExternalPortPolicy,
+    lifetime: u32,
+) -> Result<MappingResponse, NATPMPError> {
+    let Some(candidates) = policy.candidates(public_port) else {
+        return client
+            .map_once(protocol, private_port, public_port, lifetime)
+            .await;
+    };
+
+    let mut granted = 0;
+
+    for &candidate in &candidates {
+        let response = client
+            .map_once(protocol, private_port, Some(candidate), lifetime)
+            .await?;
+
+        if !response.is_different_port() {
+            return Ok(response);
+        }
+
+        event!(Level::DEBUG, %response, "Gateway granted a different external port, deleting it");
+
+        granted = response.external_port().map_or(0, NonZeroU16::get);
+
+        client.unmap(protocol, private_port).await?;
+    }
+
+    Err(NATPMPError::ExternalPortMismatch {
+        requested: candidates[0].get(),
+        granted,
+    })
+}
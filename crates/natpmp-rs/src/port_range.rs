@@ -0,0 +1,222 @@
+use std::net::Ipv4Addr;
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use tracing::{Level, event};
+
+use crate::batch::{MappingEntry, map_ports_bounded, renew_ports, unmap_ports};
+use crate::errors::NATPMPError;
+use crate::protocol::MappingProtocol;
+use crate::resolve_gateway;
+use crate::responses::MappingResponse;
+use crate::retry::Rfc6886;
+
+/// A contiguous range of ports, e.g. `10000-10100`. Both ends are included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    first: NonZeroU16,
+    last: NonZeroU16,
+}
+
+impl PortRange {
+    /// Returns `None` when `last` comes before `first`.
+    #[must_use]
+    pub fn new(first: NonZeroU16, last: NonZeroU16) -> Option<PortRange> {
+        (first <= last).then_some(PortRange { first, last })
+    }
+
+    #[must_use]
+    pub fn first(&self) -> NonZeroU16 {
+        self.first
+    }
+
+    #[must_use]
+    pub fn last(&self) -> NonZeroU16 {
+        self.last
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        usize::from(self.last.get() - self.first.get()) + 1
+    }
+
+    /// A range always has at least one port.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = NonZeroU16> + use<> {
+        // ports in the range are never 0
+        (self.first.get()..=self.last.get()).filter_map(NonZeroU16::new)
+    }
+
+    /// An entry per port, requesting the same external port as the internal one.
+    pub fn entries(&self, protocol: MappingProtocol) -> impl Iterator<Item = MappingEntry> + use<> {
+        self.ports()
+            .map(move |port| MappingEntry::new(protocol, port, Some(port)))
+    }
+}
+
+impl std::fmt::Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    /// Parses `first-last`, or a single port
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (first, last) = value.split_once('-').unwrap_or((value, value));
+
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<NonZeroU16>()
+                .map_err(|error| format!("Invalid port {:?}: {}", port, error))
+        };
+
+        PortRange::new(parse(first)?, parse(last)?)
+            .ok_or_else(|| format!("Port range {:?} ends before it starts", value))
+    }
+}
+
+/// A port range that is mapped, renewed and deleted as a whole.
+///
+/// NAT-PMP has no range opcode, every port is mapped with its own request. At most `concurrency` of them
+/// are in flight at once.
+pub struct PortRangeMapping {
+    protocol: MappingProtocol,
+    range: PortRange,
+    gateway_ip: Ipv4Addr,
+    lifetime: Option<u32>,
+    retry: Option<u32>,
+    concurrency: NonZeroUsize,
+    mappings: Vec<MappingResponse>,
+}
+
+impl PortRangeMapping {
+    /// Maps every port in `range`, requesting the same external port as the internal one.
+    /// When one of them fails, the others are deleted again.
+    ///
+    /// # Arguments
+    /// * `protocol` - `Protocol::TCP` or `Protocol::UDP`
+    /// * `range` - the private ports to map
+    /// * `lifetime` - the duration of the mappings in seconds, defaults to 7200, per specification.
+    /// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
+    /// * `retry` - the number of times to retry a request if unsuccessful, defaults to 9 as per specification.
+    /// * `concurrency` - the number of requests in flight at once, e.g. `batch::DEFAULT_CONCURRENCY`
+    ///
+    /// # Errors
+    ///
+    /// The first mapping that failed
+    pub async fn map(
+        protocol: MappingProtocol,
+        range: PortRange,
+        lifetime: Option<u32>,
+        gateway_ip: Option<Ipv4Addr>,
+        retry: Option<u32>,
+        concurrency: NonZeroUsize,
+    ) -> Result<PortRangeMapping, NATPMPError> {
+        let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+        let entries = range.entries(protocol).collect::<Vec<_>>();
+
+        let mappings =
+            map_ports_bounded(&entries, lifetime, Some(gateway_ip), retry, concurrency).await?;
+
+        Ok(PortRangeMapping {
+            protocol,
+            range,
+            gateway_ip,
+            lifetime,
+            retry,
+            concurrency,
+            mappings,
+        })
+    }
+
+    #[must_use]
+    pub fn protocol(&self) -> MappingProtocol {
+        self.protocol
+    }
+
+    #[must_use]
+    pub fn range(&self) -> PortRange {
+        self.range
+    }
+
+    /// The current mappings, in port order.
+    #[must_use]
+    pub fn mappings(&self) -> &[MappingResponse] {
+        &self.mappings
+    }
+
+    /// The mappings that were granted a different external port than the one requested.
+    pub fn differing_ports(&self) -> impl Iterator<Item = &MappingResponse> {
+        self.mappings
+            .iter()
+            .filter(|mapping| mapping.is_different_port())
+    }
+
+    /// The time until the next renewal is due, which is halfway to the first expiry.
+    #[must_use]
+    pub fn renew_in(&self) -> Duration {
+        let first_expiry = self.mappings.iter().map(MappingResponse::expires_at).min();
+
+        first_expiry
+            .map_or(Duration::ZERO, |expires_at| {
+                expires_at.saturating_duration_since(Instant::now()) / 2
+            })
+            .max(Duration::from_secs(1))
+    }
+
+    /// Renews every mapping, requesting the external port it was granted before.
+    ///
+    /// A mapping that fails to renew keeps its previous response, and is tried again at the next renewal.
+    ///
+    /// # Errors
+    ///
+    /// When the requests could not be sent at all
+    pub async fn renew(&mut self) -> Result<(), NATPMPError> {
+        let results = renew_ports(
+            &self.mappings,
+            self.lifetime,
+            Some(self.gateway_ip),
+            self.retry,
+            self.concurrency,
+        )
+        .await?;
+
+        for (mapping, result) in self.mappings.iter_mut().zip(results) {
+            match result {
+                Ok(response) => *mapping = response,
+                Err(error) => {
+                    event!(Level::ERROR, ?error, %mapping, "Failed to renew mapping");
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes every mapping.
+    ///
+    /// # Errors
+    ///
+    /// The first mapping that could not be deleted, the others are still deleted
+    pub async fn unmap(self) -> Result<(), NATPMPError> {
+        unmap_ports(
+            &self.mappings,
+            Some(self.gateway_ip),
+            self.retry,
+            self.concurrency,
+        )
+        .await?
+        .into_iter()
+        .find_map(Result::err)
+        .map_or(Ok(()), Err)
+    }
+}
@@ -0,0 +1,65 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use metrics::gauge;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use natpmp_rs::responses::MappingResponse;
+use natpmp_rs::telemetry::REQUEST_DURATION;
+
+pub const ACTIVE_MAPPINGS: &str = "natpmp_active_mappings";
+pub const NEXT_EXPIRY: &str = "natpmp_next_expiry_seconds";
+pub const EXTERNAL_ADDRESS_INFO: &str = "natpmp_external_address_info";
+
+/// The time-based metrics are kept up to date by refreshing them at this interval.
+pub const METRICS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Round trips to a gateway on the LAN take milliseconds, the upper buckets catch the retries.
+const REQUEST_DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Installs the Prometheus recorder, and serves the metrics over HTTP on `listen`, e.g. `http://<listen>/metrics`.
+pub fn install(listen: SocketAddr) -> Result<(), eyre::Report> {
+    PrometheusBuilder::new()
+        .with_http_listener(listen)
+        .set_buckets_for_metric(
+            Matcher::Full(String::from(REQUEST_DURATION)),
+            &REQUEST_DURATION_BUCKETS,
+        )?
+        .install()?;
+
+    Ok(())
+}
+
+/// Sets the mapping metrics from the current responses of everything we keep mapped. Mappings that have expired
+/// don't count as active.
+pub fn record_mappings<'mapping, I>(mappings: I)
+where
+    I: IntoIterator<Item = &'mapping MappingResponse>,
+{
+    let now = Instant::now();
+
+    let expiries = mappings
+        .into_iter()
+        .map(MappingResponse::expires_at)
+        .filter(|&expires_at| expires_at > now)
+        .collect::<Vec<_>>();
+
+    gauge!(ACTIVE_MAPPINGS).set(u32::try_from(expiries.len()).unwrap_or(u32::MAX));
+
+    if let Some(next_expiry) = expiries.iter().min() {
+        gauge!(NEXT_EXPIRY).set(next_expiry.saturating_duration_since(now).as_secs_f64());
+    }
+}
+
+/// Sets the external address of `gateway`, clearing the `previous` one, as an info metric only has one current value.
+pub fn record_external_address(gateway: Ipv4Addr, previous: Option<Ipv4Addr>, address: Ipv4Addr) {
+    if let Some(previous) = previous
+        && previous != address
+    {
+        gauge!(EXTERNAL_ADDRESS_INFO, "gateway" => gateway.to_string(), "address" => previous.to_string()).set(0);
+    }
+
+    gauge!(EXTERNAL_ADDRESS_INFO, "gateway" => gateway.to_string(), "address" => address.to_string()).set(1);
+}
@@ -1,6 +1,8 @@
+use std::str::FromStr;
+
 use zerocopy::{Immutable, IntoBytes};
 
-#[derive(Debug, Copy, Clone, IntoBytes, Immutable)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoBytes, Immutable)]
 #[repr(u8)]
 pub enum MappingProtocol {
     UDP = 1,
@@ -20,6 +22,21 @@ impl std::fmt::Display for MappingProtocol {
     }
 }
 
+impl FromStr for MappingProtocol {
+    type Err = String;
+
+    /// Parses `tcp` or `udp`, in any case
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("tcp") {
+            Ok(MappingProtocol::TCP)
+        } else if value.eq_ignore_ascii_case("udp") {
+            Ok(MappingProtocol::UDP)
+        } else {
+            Err(format!("Invalid protocol {:?}, expected tcp or udp", value))
+        }
+    }
+}
+
 impl TryFrom<u8> for MappingProtocol {
     type Error = String;
 
@@ -35,8 +52,47 @@ impl TryFrom<u8> for MappingProtocol {
 impl From<MappingProtocol> for u8 {
     fn from(value: MappingProtocol) -> Self {
         match value {
-            MappingProtocol::TCP => 1,
-            MappingProtocol::UDP => 2,
+            MappingProtocol::UDP => 1,
+            MappingProtocol::TCP => 2,
+        }
+    }
+}
+
+/// What a request asks the gateway for.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.2>
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Opcode {
+    /// 0, the external address, which PCP uses for its announcements
+    ExternalAddress,
+    /// 1 for UDP and 2 for TCP, creating, renewing or deleting a mapping
+    Mapping(MappingProtocol),
+    /// An opcode that NAT-PMP doesn't define, e.g. in a packet made by hand
+    Other(u8),
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", u8::from(*self))
+    }
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Opcode::ExternalAddress,
+            1 => Opcode::Mapping(MappingProtocol::UDP),
+            2 => Opcode::Mapping(MappingProtocol::TCP),
+            other => Opcode::Other(other),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(value: Opcode) -> Self {
+        match value {
+            Opcode::ExternalAddress => 0,
+            Opcode::Mapping(protocol) => protocol.into(),
+            Opcode::Other(other) => other,
         }
     }
 }
@@ -0,0 +1,47 @@
+use std::net::SocketAddrV4;
+
+use crate::mapping_guard::MappingGuard;
+
+/// A socket that is reachable from outside the NAT, together with its public endpoint and the guard
+/// that keeps its mapping alive.
+///
+/// The public endpoint is the one at the time of binding. When a renewal grants a different external port,
+/// `MappingGuard::external_port()` is notified.
+pub struct PublicSocket<S> {
+    socket: S,
+    public_address: SocketAddrV4,
+    guard: MappingGuard,
+}
+
+impl<S> PublicSocket<S> {
+    pub(crate) fn new(
+        socket: S,
+        public_address: SocketAddrV4,
+        guard: MappingGuard,
+    ) -> PublicSocket<S> {
+        PublicSocket {
+            socket,
+            public_address,
+            guard,
+        }
+    }
+
+    #[must_use]
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
+    #[must_use]
+    pub fn public_address(&self) -> SocketAddrV4 {
+        self.public_address
+    }
+
+    pub fn guard(&self) -> &MappingGuard {
+        &self.guard
+    }
+
+    /// The mapping is deleted when the guard is dropped, so keep it around for as long as the socket is used.
+    pub fn into_parts(self) -> (S, SocketAddrV4, MappingGuard) {
+        (self.socket, self.public_address, self.guard)
+    }
+}
@@ -0,0 +1,191 @@
+//! Whether mappings can be reached from the internet, judging by the external address of the gateway.
+//!
+//! A gateway behind another NAT, e.g. the carrier-grade NAT of an ISP or the router of a landlord, happily maps
+//! ports, but only on its own external address, which the internet can't reach.
+
+use std::net::Ipv4Addr;
+
+use tracing::{Level, event};
+
+use crate::client::Client;
+use crate::errors::NATPMPError;
+use crate::pcp::{PcpResponse, request_announce};
+use crate::request_external_address;
+use crate::responses::ExternalAddressResponse;
+
+/// Shared address space, for carrier-grade NAT.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6598#section-7>
+const SHARED_ADDRESS_SPACE: (Ipv4Addr, u32) = (Ipv4Addr::new(100, 64, 0, 0), 10);
+
+/// What kind of address a gateway's external address is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    /// A public address, mappings are reachable from the internet
+    Public,
+    /// In `100.64.0.0/10`: the ISP runs carrier-grade NAT in front of the gateway
+    CarrierGradeNat,
+    /// In RFC 1918 space: another NAT, e.g. the ISP's router, sits in front of the gateway
+    Private,
+    /// `0.0.0.0`, link-local, loopback and other addresses no traffic can arrive on, e.g. while the gateway
+    /// has no uplink
+    Unroutable,
+}
+
+impl Reachability {
+    #[must_use]
+    pub fn of(address: Ipv4Addr) -> Reachability {
+        let (shared, prefix_length) = SHARED_ADDRESS_SPACE;
+        let shared_mask = u32::MAX << (32 - prefix_length);
+
+        if u32::from(address) & shared_mask == u32::from(shared) {
+            Reachability::CarrierGradeNat
+        } else if address.is_private() {
+            Reachability::Private
+        } else if address.is_unspecified()
+            || address.is_loopback()
+            || address.is_link_local()
+            || address.is_multicast()
+            || address.is_broadcast()
+            // 0.0.0.0/8 and the reserved 240.0.0.0/4
+            || address.octets()[0] == 0
+            || address.octets()[0] >= 240
+        {
+            Reachability::Unroutable
+        } else {
+            Reachability::Public
+        }
+    }
+
+    /// Whether mappings on the gateway can be reached from the internet.
+    #[must_use]
+    pub fn is_public(&self) -> bool {
+        *self == Reachability::Public
+    }
+
+    /// Whether another NAT sits between the gateway and the internet, which our mappings don't reach through.
+    #[must_use]
+    pub fn is_double_nat(&self) -> bool {
+        matches!(*self, Reachability::CarrierGradeNat | Reachability::Private)
+    }
+}
+
+impl std::fmt::Display for Reachability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Reachability::Public => "public",
+                Reachability::CarrierGradeNat => "carrier-grade NAT",
+                Reachability::Private => "private, double NAT",
+                Reachability::Unroutable => "unroutable",
+            }
+        )
+    }
+}
+
+/// Whether the gateway in front of our gateway speaks PCP, in which case it could be asked for a mapping too.
+#[derive(Debug)]
+pub struct UpstreamProbe {
+    gateway: Ipv4Addr,
+    result: Result<PcpResponse, NATPMPError>,
+}
+
+impl UpstreamProbe {
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    /// The answer to a PCP ANNOUNCE, `NATPMPError::Unsupported` when the gateway only speaks NAT-PMP.
+    ///
+    /// # Errors
+    /// When the gateway didn't answer as a PCP server
+    pub fn result(&self) -> Result<&PcpResponse, &NATPMPError> {
+        self.result.as_ref()
+    }
+
+    /// Whether the gateway answered as a PCP server, even with an error.
+    #[must_use]
+    pub fn speaks_pcp(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+impl std::fmt::Display for UpstreamProbe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.result {
+            Ok(ref response) => write!(
+                f,
+                "{} speaks PCP, ANNOUNCE answered with {}",
+                self.gateway,
+                response.result()
+            ),
+            Err(NATPMPError::Unsupported { .. }) => {
+                write!(f, "{} speaks NAT-PMP, but not PCP", self.gateway)
+            },
+            Err(ref error) => write!(f, "{} didn't answer PCP: {}", self.gateway, error),
+        }
+    }
+}
+
+/// The external address of a gateway, and what it means for mappings on it.
+#[derive(Debug)]
+pub struct ReachabilityReport {
+    external_address: ExternalAddressResponse,
+    upstream: Option<UpstreamProbe>,
+}
+
+impl ReachabilityReport {
+    #[must_use]
+    pub fn external_address(&self) -> &ExternalAddressResponse {
+        &self.external_address
+    }
+
+    #[must_use]
+    pub fn reachability(&self) -> Reachability {
+        self.external_address.reachability()
+    }
+
+    /// The PCP follow-up at the upstream gateway, when one was asked for.
+    #[must_use]
+    pub fn upstream(&self) -> Option<&UpstreamProbe> {
+        self.upstream.as_ref()
+    }
+}
+
+pub(crate) async fn probe(
+    client: &Client,
+    upstream: Option<Ipv4Addr>,
+) -> Result<ReachabilityReport, NATPMPError> {
+    let external_address =
+        request_external_address(client.gateway_ip(), client.retry_policy(), client.binding())
+            .await?;
+
+    let reachability = external_address.reachability();
+
+    if !reachability.is_public() {
+        event!(Level::WARN, %reachability, external_address = %external_address.ipv4_address(), "Mappings are not reachable from the internet");
+    }
+
+    let upstream = match upstream {
+        Some(gateway) => {
+            // to the upstream gateway, we are our gateway's external address
+            let result = request_announce(
+                gateway,
+                external_address.ipv4_address(),
+                client.retry_policy(),
+                client.binding(),
+            )
+            .await;
+
+            Some(UpstreamProbe { gateway, result })
+        },
+        None => None,
+    };
+
+    Ok(ReachabilityReport {
+        external_address,
+        upstream,
+    })
+}
@@ -1,14 +1,43 @@
-use crate::responses::Response;
+use crate::VERSION;
+use crate::errors::{NATPMPError, RequestContext};
+use crate::protocol::Opcode;
+use crate::responses::{MappingResponse, Response, parse_raw_response};
 
+pub(crate) mod announce_request;
 pub(crate) mod external_address_request;
 pub(crate) mod mapping_request;
 pub(crate) mod unmap_all_request;
 pub(crate) mod unmap_request;
 
-type Opcode = u8;
-
 pub(crate) trait Request {
     type Response: Response;
 
+    /// 0 for NAT-PMP, 2 for PCP
+    fn version(&self) -> u8 {
+        VERSION
+    }
+
     fn opcode(&self) -> Opcode;
+
+    fn parse_response(
+        &self,
+        context: RequestContext,
+        buffer: &[u8],
+    ) -> Result<Self::Response, NATPMPError>
+    where
+        Self: Sized,
+    {
+        parse_raw_response(self, context, buffer)
+    }
+
+    /// Adds what the response itself doesn't tell, but the request does.
+    fn complete(&self, response: Self::Response) -> Self::Response {
+        response
+    }
+}
+
+/// A request about a single internal port. Its response carries the same opcode and internal port,
+/// which is how responses to requests sent over a shared socket are told apart.
+pub(crate) trait PortRequest: Request<Response = MappingResponse> {
+    fn internal_port(&self) -> u16;
 }
@@ -0,0 +1,54 @@
+use std::net::Ipv4Addr;
+
+use zerocopy::network_endian::{U16, U32};
+use zerocopy::{Immutable, IntoBytes};
+
+use super::Request;
+use crate::errors::{NATPMPError, RequestContext};
+use crate::pcp::{PCP_VERSION, PcpResponse, parse_pcp_response};
+use crate::protocol::Opcode;
+
+/// The PCP ANNOUNCE request, which asks a PCP server nothing but whether it is there.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6887#section-14.1>
+#[derive(IntoBytes, Immutable)]
+#[repr(C)]
+pub(crate) struct AnnounceRequest {
+    version: u8,
+    opcode: u8,
+    _reserved: U16,
+    lifetime: U32,
+    client_ip: [u8; 16],
+}
+
+impl AnnounceRequest {
+    /// `client_ip` is the address the server will see us as, it answers with `ADDRESS_MISMATCH` otherwise.
+    pub(crate) fn new(client_ip: Ipv4Addr) -> Self {
+        Self {
+            version: PCP_VERSION,
+            opcode: 0,
+            _reserved: U16::ZERO,
+            lifetime: U32::ZERO,
+            client_ip: client_ip.to_ipv6_mapped().octets(),
+        }
+    }
+}
+
+impl Request for AnnounceRequest {
+    type Response = PcpResponse;
+
+    fn version(&self) -> u8 {
+        self.version
+    }
+
+    fn opcode(&self) -> Opcode {
+        self.opcode.into()
+    }
+
+    fn parse_response(
+        &self,
+        context: RequestContext,
+        buffer: &[u8],
+    ) -> Result<PcpResponse, NATPMPError> {
+        parse_pcp_response(self.opcode, context, buffer)
+    }
+}
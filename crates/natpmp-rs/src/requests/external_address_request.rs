@@ -2,14 +2,15 @@ use zerocopy::{Immutable, IntoBytes};
 
 use super::Request;
 use crate::VERSION;
+use crate::protocol::Opcode;
 use crate::responses::ExternalAddressResponse;
 
 impl Request for ExternalAddressRequest {
     type Response = ExternalAddressResponse;
 
-    fn opcode(&self) -> u8 {
+    fn opcode(&self) -> Opcode {
         // or self.opcode
-        0
+        Opcode::ExternalAddress
     }
 }
 
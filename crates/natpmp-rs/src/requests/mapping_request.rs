@@ -1,10 +1,11 @@
 use std::num::NonZeroU16;
 
+use zerocopy::network_endian::{U16, U32};
 use zerocopy::{Immutable, IntoBytes};
 
-use super::Request;
+use super::{PortRequest, Request};
 use crate::VERSION;
-use crate::protocol::MappingProtocol;
+use crate::protocol::{MappingProtocol, Opcode};
 use crate::responses::MappingResponse;
 
 #[derive(IntoBytes, Immutable)]
@@ -12,17 +13,21 @@ use crate::responses::MappingResponse;
 pub(crate) struct MappingRequest {
     version: u8,
     protocol: MappingProtocol,
-    _spacer: u16,
-    internal_port: NonZeroU16,
-    external_port: u16,
-    lifetime: u32,
+    _spacer: U16,
+    internal_port: U16,
+    external_port: U16,
+    lifetime: U32,
 }
 
 impl Request for MappingRequest {
     type Response = MappingResponse;
 
-    fn opcode(&self) -> u8 {
-        self.protocol.into()
+    fn opcode(&self) -> Opcode {
+        Opcode::Mapping(self.protocol)
+    }
+
+    fn complete(&self, response: MappingResponse) -> MappingResponse {
+        response.with_requested_external_port(self.external_port.get())
     }
 }
 
@@ -36,10 +41,16 @@ impl MappingRequest {
         Self {
             version: VERSION,
             protocol,
-            _spacer: 0,
-            internal_port: private_port,
-            external_port: public_port,
-            lifetime,
+            _spacer: U16::ZERO,
+            internal_port: private_port.get().into(),
+            external_port: public_port.into(),
+            lifetime: lifetime.into(),
         }
     }
 }
+
+impl PortRequest for MappingRequest {
+    fn internal_port(&self) -> u16 {
+        self.internal_port.get()
+    }
+}
@@ -1,19 +1,20 @@
+use zerocopy::network_endian::{U16, U32};
 use zerocopy::{Immutable, IntoBytes};
 
 use super::Request;
 use crate::VERSION;
-use crate::protocol::MappingProtocol;
-use crate::responses::MappingResponse;
+use crate::protocol::{MappingProtocol, Opcode};
+use crate::responses::UnmapAllResponse;
 
 #[derive(IntoBytes, Immutable)]
 #[repr(C)]
 pub(crate) struct UnmapAllPortsRequest {
     version: u8,
     protocol: MappingProtocol,
-    _spacer: u16,
-    internal_port: u16,
-    external_port: u16,
-    lifetime: u32,
+    _spacer: U16,
+    internal_port: U16,
+    external_port: U16,
+    lifetime: U32,
 }
 
 impl UnmapAllPortsRequest {
@@ -21,21 +22,21 @@ impl UnmapAllPortsRequest {
         Self {
             version: VERSION,
             protocol,
-            _spacer: 0,
+            _spacer: U16::ZERO,
             // internal port, set to zero to remove all from this protocol
-            internal_port: 0,
+            internal_port: U16::ZERO,
             // external port, set to zero as per spec
-            external_port: 0,
+            external_port: U16::ZERO,
             // lifetime, set to zero as per spec
-            lifetime: 0,
+            lifetime: U32::ZERO,
         }
     }
 }
 
 impl Request for UnmapAllPortsRequest {
-    type Response = MappingResponse;
+    type Response = UnmapAllResponse;
 
-    fn opcode(&self) -> u8 {
-        self.protocol.into()
+    fn opcode(&self) -> Opcode {
+        Opcode::Mapping(self.protocol)
     }
 }
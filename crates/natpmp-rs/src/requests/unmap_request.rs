@@ -1,10 +1,11 @@
 use std::num::NonZeroU16;
 
+use zerocopy::network_endian::{U16, U32};
 use zerocopy::{Immutable, IntoBytes};
 
-use super::Request;
+use super::{PortRequest, Request};
 use crate::VERSION;
-use crate::protocol::MappingProtocol;
+use crate::protocol::{MappingProtocol, Opcode};
 use crate::responses::MappingResponse;
 
 #[derive(IntoBytes, Immutable)]
@@ -12,10 +13,10 @@ use crate::responses::MappingResponse;
 pub(crate) struct UnmapPortRequest {
     version: u8,
     protocol: MappingProtocol,
-    _spacer: u16,
-    internal_port: NonZeroU16,
-    external_port: u16,
-    lifetime: u32,
+    _spacer: U16,
+    internal_port: U16,
+    external_port: U16,
+    lifetime: U32,
 }
 
 impl UnmapPortRequest {
@@ -23,12 +24,12 @@ impl UnmapPortRequest {
         Self {
             version: VERSION,
             protocol,
-            _spacer: 0,
-            internal_port: private_port,
+            _spacer: U16::ZERO,
+            internal_port: private_port.get().into(),
             // external port, set to zero as per spec
-            external_port: 0,
+            external_port: U16::ZERO,
             // lifetime, set to zero as per spec
-            lifetime: 0,
+            lifetime: U32::ZERO,
         }
     }
 }
@@ -36,7 +37,13 @@ impl UnmapPortRequest {
 impl Request for UnmapPortRequest {
     type Response = MappingResponse;
 
-    fn opcode(&self) -> u8 {
-        self.protocol.into()
+    fn opcode(&self) -> Opcode {
+        Opcode::Mapping(self.protocol)
+    }
+}
+
+impl PortRequest for UnmapPortRequest {
+    fn internal_port(&self) -> u16 {
+        self.internal_port.get()
     }
 }
@@ -1,11 +1,13 @@
 use std::net::Ipv4Addr;
 use std::num::NonZeroU16;
+use std::time::{Duration, Instant, SystemTime};
 
 use bytes::{Buf as _, BytesMut};
 
 use crate::VERSION;
-use crate::errors::{NATPMPError, NATPMPResultError};
+use crate::errors::{InvalidResponse, NATPMPError, NATPMPResultError, RequestContext};
 use crate::protocol::MappingProtocol;
+use crate::reachability::Reachability;
 use crate::requests::Request;
 
 pub(crate) trait Response {
@@ -17,21 +19,25 @@ pub(crate) trait Response {
 
     fn try_from_bytes(
         opcode: u8,
+        gateway: Ipv4Addr,
         buffer: &[u8],
         // buffer: &[u8],
-    ) -> Result<Self, NATPMPError>
+    ) -> Result<Self, InvalidResponse>
     where
         Self: std::marker::Sized;
+
+    fn seconds_since_epoch(&self) -> u32;
 }
 
 pub(crate) fn parse_raw_response<R: Request>(
     request: &R,
+    context: RequestContext,
     mut buffer: &[u8],
 ) -> Result<R::Response, NATPMPError> {
     let version = buffer.get_u8();
 
     if version != VERSION {
-        return Err(NATPMPError::Response(NATPMPResultError::UnsupportedVersion));
+        return Err(NATPMPError::Unsupported { context });
     }
 
     let opcode = buffer.get_u8();
@@ -48,9 +54,15 @@ pub(crate) fn parse_raw_response<R: Request>(
     //     return Err(NATPMPError::Response(NATPMPResultError::UnsupportedOpcode));
     // }
 
-    if opcode & 0x7f != request.opcode() {
-        // if we hit this the response received did match the request sent
-        todo!()
+    if opcode & 0x7f != u8::from(request.opcode()) {
+        // if we hit this the response received did not match the request sent
+        return Err(NATPMPError::InvalidResponse {
+            context,
+            reason: InvalidResponse::OpcodeMismatch {
+                expected: request.opcode().into(),
+                received: opcode,
+            },
+        });
     }
 
     let result_code = buffer.get_u16();
@@ -58,31 +70,116 @@ pub(crate) fn parse_raw_response<R: Request>(
     if result_code != 0 {
         // this is bad, we need to still check the internal port to see if it matches the request
         // we can't just assume the response we got here is tied to the request we sent
-        return Err(NATPMPResultError::try_from(result_code)
-            .map_or_else(NATPMPError::Deserialize, NATPMPError::Response));
+        return Err(NATPMPError::Response {
+            context,
+            result: NATPMPResultError::from(result_code),
+        });
     }
 
-    R::Response::try_from_bytes(opcode, buffer)
+    R::Response::try_from_bytes(opcode, context.gateway(), buffer)
+        .map(|response| request.complete(response))
+        .map_err(|reason| NATPMPError::InvalidResponse { context, reason })
 }
 
-#[derive(Debug)]
+/// What the gateway answered when it was asked about the mapping of an internal port: the mapping it created,
+/// renewed, or deleted.
+#[derive(Debug, Clone)]
 pub struct MappingResponse {
+    gateway: Ipv4Addr,
     protocol: MappingProtocol,
     internal_port: NonZeroU16,
-    external_port: u16,
-    lifetime: u32,
+    external_port: Option<NonZeroU16>,
+    requested_external_port: Option<NonZeroU16>,
+    lifetime: Duration,
+    received_at: Instant,
     seconds_since_epoch: u32,
+    gateway_started_at: SystemTime,
+}
+
+impl MappingResponse {
+    /// The gateway that answered
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    #[must_use]
+    pub fn protocol(&self) -> MappingProtocol {
+        self.protocol
+    }
+
+    #[must_use]
+    pub fn internal_port(&self) -> NonZeroU16 {
+        self.internal_port
+    }
+
+    /// The external port the gateway granted, `None` when the mapping was deleted
+    #[must_use]
+    pub fn external_port(&self) -> Option<NonZeroU16> {
+        self.external_port
+    }
+
+    /// The external port we asked for, `None` when we let the gateway pick one, or when deleting a mapping
+    #[must_use]
+    pub fn requested_external_port(&self) -> Option<NonZeroU16> {
+        self.requested_external_port
+    }
+
+    /// Whether we got a different external port than the one we asked for
+    #[must_use]
+    pub fn is_different_port(&self) -> bool {
+        self.requested_external_port
+            .is_some_and(|requested| Some(requested) != self.external_port)
+    }
+
+    pub(crate) fn with_requested_external_port(mut self, requested_external_port: u16) -> Self {
+        self.requested_external_port = NonZeroU16::new(requested_external_port);
+        self
+    }
+
+    /// The lifetime the gateway granted, which can be shorter than the one requested
+    #[must_use]
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// When we received the response
+    #[must_use]
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// When the mapping expires, unless it's renewed before then, as measured by the local clock
+    #[must_use]
+    pub fn expires_at(&self) -> Instant {
+        self.received_at + self.lifetime
+    }
+
+    /// The time since the gateway started, or lost its mappings
+    /// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.6>
+    #[must_use]
+    pub fn gateway_epoch(&self) -> Duration {
+        Duration::from_secs(u64::from(self.seconds_since_epoch))
+    }
+
+    /// When the gateway started, or lost its mappings, as estimated from the gateway epoch and the local clock
+    #[must_use]
+    pub fn gateway_started_at(&self) -> SystemTime {
+        self.gateway_started_at
+    }
 }
 
 impl std::fmt::Display for MappingResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Protocol: {}, internal port: {}, external port {}, lifetime: {}, seconds since epoch: {}",
+            "Gateway: {}, protocol: {}, internal port: {}, external port {}, requested external port: {}, lifetime: {}, seconds since epoch: {}",
+            self.gateway,
             self.protocol,
             self.internal_port,
-            self.external_port,
-            self.lifetime,
+            self.external_port.map_or(0, NonZeroU16::get),
+            self.requested_external_port.map_or(0, NonZeroU16::get),
+            self.lifetime.as_secs(),
             self.seconds_since_epoch,
         )
     }
@@ -91,51 +188,210 @@ impl std::fmt::Display for MappingResponse {
 impl Response for MappingResponse {
     const SIZE: usize = 16;
 
-    fn try_from_bytes(opcode: u8, mut buffer: &[u8]) -> Result<Self, NATPMPError> {
+    fn seconds_since_epoch(&self) -> u32 {
+        self.seconds_since_epoch
+    }
+
+    fn try_from_bytes(
+        opcode: u8,
+        gateway: Ipv4Addr,
+        mut buffer: &[u8],
+    ) -> Result<Self, InvalidResponse> {
         // parse protocol
         let protocol: MappingProtocol = (opcode & 0x7f)
             .try_into()
-            .map_err(NATPMPError::Deserialize)?;
+            .map_err(|_| InvalidResponse::UnknownProtocol(opcode & 0x7f))?;
 
         let seconds_since_epoch = buffer.get_u32();
         let internal_port = buffer.get_u16();
         let external_port = buffer.get_u16();
         let lifetime = buffer.get_u32();
 
+        let internal_port =
+            NonZeroU16::new(internal_port).ok_or(InvalidResponse::ZeroInternalPort)?;
+
         Ok(MappingResponse {
+            gateway,
             protocol,
+            internal_port,
+            external_port: NonZeroU16::new(external_port),
+            requested_external_port: None,
+            lifetime: Duration::from_secs(u64::from(lifetime)),
+            received_at: Instant::now(),
             seconds_since_epoch,
-            internal_port: internal_port.try_into().unwrap(),
-            external_port,
-            lifetime,
+            gateway_started_at: started_at(seconds_since_epoch),
         })
     }
 }
 
-#[derive(Debug)]
+/// The gateway's address on the outside, and the gateway that told us.
+#[derive(Debug, Clone)]
 pub struct ExternalAddressResponse {
-    #[expect(unused, reason = "Not implemented")]
-    seconds_since_epoch: u32,
+    gateway: Ipv4Addr,
     ipv4_address: Ipv4Addr,
+    received_at: Instant,
+    seconds_since_epoch: u32,
+    gateway_started_at: SystemTime,
 }
 
 impl ExternalAddressResponse {
+    /// The gateway that answered, or announced
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
     #[must_use]
     pub fn ipv4_address(&self) -> Ipv4Addr {
         self.ipv4_address
     }
+
+    /// Whether mappings on this gateway can be reached from the internet, judging by its external address
+    #[must_use]
+    pub fn reachability(&self) -> Reachability {
+        Reachability::of(self.ipv4_address)
+    }
+
+    /// When we received the response
+    #[must_use]
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// The time since the gateway started, or lost its mappings
+    /// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.6>
+    #[must_use]
+    pub fn gateway_epoch(&self) -> Duration {
+        Duration::from_secs(u64::from(self.seconds_since_epoch))
+    }
+
+    /// When the gateway started, or lost its mappings, as estimated from the gateway epoch and the local clock
+    #[must_use]
+    pub fn gateway_started_at(&self) -> SystemTime {
+        self.gateway_started_at
+    }
 }
 
 impl Response for ExternalAddressResponse {
     const SIZE: usize = 12;
 
-    fn try_from_bytes(_opcode: u8, mut buffer: &[u8]) -> Result<Self, NATPMPError> {
+    fn seconds_since_epoch(&self) -> u32 {
+        self.seconds_since_epoch
+    }
+
+    fn try_from_bytes(
+        _opcode: u8,
+        gateway: Ipv4Addr,
+        mut buffer: &[u8],
+    ) -> Result<Self, InvalidResponse> {
         let seconds_since_epoch = buffer.get_u32();
         let ip_address = buffer.get_u32();
 
         Ok(ExternalAddressResponse {
-            seconds_since_epoch,
+            gateway,
             ipv4_address: Ipv4Addr::from(ip_address),
+            received_at: Instant::now(),
+            seconds_since_epoch,
+            gateway_started_at: started_at(seconds_since_epoch),
+        })
+    }
+}
+
+/// Whether the gateway restarted, and might have lost its mappings, between telling us `previous_epoch` and, `elapsed`
+/// later on our clock, `epoch`: the epoch went back, or fell more than 2 seconds behind 7/8 of the time that
+/// passed, which leaves room for clocks that run at different speeds.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.6>
+#[must_use]
+pub fn epoch_regressed(previous_epoch: Duration, epoch: Duration, elapsed: Duration) -> bool {
+    epoch < previous_epoch || epoch + Duration::from_secs(2) < previous_epoch + elapsed * 7 / 8
+}
+
+/// What the gateway answered when it was asked to delete all mappings of a protocol. Unlike a `MappingResponse`
+/// it is about no internal port in particular, its internal port is 0.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.4>
+#[derive(Debug, Clone)]
+pub struct UnmapAllResponse {
+    gateway: Ipv4Addr,
+    protocol: MappingProtocol,
+    received_at: Instant,
+    seconds_since_epoch: u32,
+    gateway_started_at: SystemTime,
+}
+
+impl UnmapAllResponse {
+    /// The gateway that answered
+    #[must_use]
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    #[must_use]
+    pub fn protocol(&self) -> MappingProtocol {
+        self.protocol
+    }
+
+    /// When we received the response
+    #[must_use]
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// The time since the gateway started, or lost its mappings
+    /// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.6>
+    #[must_use]
+    pub fn gateway_epoch(&self) -> Duration {
+        Duration::from_secs(u64::from(self.seconds_since_epoch))
+    }
+
+    /// When the gateway started, or lost its mappings, as estimated from the gateway epoch and the local clock
+    #[must_use]
+    pub fn gateway_started_at(&self) -> SystemTime {
+        self.gateway_started_at
+    }
+}
+
+impl std::fmt::Display for UnmapAllResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gateway: {}, protocol: {}, all mappings deleted, seconds since epoch: {}",
+            self.gateway, self.protocol, self.seconds_since_epoch,
+        )
+    }
+}
+
+impl Response for UnmapAllResponse {
+    const SIZE: usize = 16;
+
+    fn seconds_since_epoch(&self) -> u32 {
+        self.seconds_since_epoch
+    }
+
+    fn try_from_bytes(
+        opcode: u8,
+        gateway: Ipv4Addr,
+        mut buffer: &[u8],
+    ) -> Result<Self, InvalidResponse> {
+        let protocol: MappingProtocol = (opcode & 0x7f)
+            .try_into()
+            .map_err(|_| InvalidResponse::UnknownProtocol(opcode & 0x7f))?;
+
+        let seconds_since_epoch = buffer.get_u32();
+
+        // the internal port, external port and lifetime are all 0, and tell nothing
+
+        Ok(UnmapAllResponse {
+            gateway,
+            protocol,
+            received_at: Instant::now(),
+            seconds_since_epoch,
+            gateway_started_at: started_at(seconds_since_epoch),
         })
     }
 }
+
+fn started_at(seconds_since_epoch: u32) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(Duration::from_secs(u64::from(seconds_since_epoch)))
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
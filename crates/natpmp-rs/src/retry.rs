@@ -0,0 +1,220 @@
+//! How long to wait for a response, and how often to ask again.
+
+use std::hash::{BuildHasher as _, RandomState};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Decides how long to wait for each attempt of a request, and when to give up.
+pub trait RetryPolicy: std::fmt::Debug + Send + Sync {
+    /// How long to wait for a response to `attempt`, starting at 1, or `None` to give up before sending it.
+    /// There is no attempt 0, it gets `None`.
+    fn timeout(&self, attempt: u32) -> Option<Duration>;
+
+    /// Gives up once this much time has passed since the first attempt, however many attempts are left.
+    fn deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Gives up after `deadline`, or earlier when this policy runs out of attempts.
+    fn with_deadline(self, deadline: Duration) -> WithDeadline<Self>
+    where
+        Self: Sized,
+    {
+        WithDeadline {
+            policy: self,
+            deadline,
+        }
+    }
+}
+
+/// The schedule of RFC 6886: wait 250 ms for the first attempt, doubling every attempt, for 9 attempts.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.1>
+#[derive(Debug, Clone, Copy)]
+pub struct Rfc6886 {
+    attempts: u32,
+}
+
+impl Rfc6886 {
+    // Source: https://www.rfc-editor.org/rfc/rfc6886#page-6:~:text=and%20waits%20250%20ms%20for%20a%20response.%20%20If%20no%0A%20%20%20NAT%2DPMP%20response%20is%20received%20from%20the%20gateway%20after%20250%20ms%2C%20the%0A%20%20%20client%20retransmits%20its%20request%20and%20waits%20500%20ms
+    const FIRST_TIMEOUT: Duration = Duration::from_millis(250);
+
+    /// The same doubling schedule, but for `attempts` attempts.
+    #[must_use]
+    pub fn with_attempts(attempts: u32) -> Rfc6886 {
+        Rfc6886 { attempts }
+    }
+
+    pub(crate) fn from_retry(retry: Option<u32>) -> Rfc6886 {
+        retry.map_or_else(Rfc6886::default, Rfc6886::with_attempts)
+    }
+}
+
+impl Default for Rfc6886 {
+    fn default() -> Self {
+        Rfc6886 { attempts: 9 }
+    }
+}
+
+impl RetryPolicy for Rfc6886 {
+    fn timeout(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt > self.attempts {
+            return None;
+        }
+
+        Some(
+            2_u32
+                .checked_pow(attempt - 1)
+                .and_then(|factor| Rfc6886::FIRST_TIMEOUT.checked_mul(factor))
+                .unwrap_or(Duration::MAX),
+        )
+    }
+}
+
+/// Doubles the wait every attempt, up to `max`, and takes a random part off every wait so that clients
+/// that started together don't keep asking together.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+    jitter: f64,
+}
+
+impl ExponentialBackoff {
+    #[must_use]
+    pub fn new(initial: Duration, max: Duration, attempts: u32) -> ExponentialBackoff {
+        ExponentialBackoff {
+            initial,
+            max,
+            attempts,
+            jitter: 0.0,
+        }
+    }
+
+    /// Takes up to `jitter` (between 0 and 1) of each wait off, at random. A jitter that isn't a finite number means no jitter.
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: f64) -> ExponentialBackoff {
+        // NaN survives `clamp()`, and would make `Duration::mul_f64()` panic
+        self.jitter = if jitter.is_finite() {
+            jitter.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn timeout(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt > self.attempts {
+            return None;
+        }
+
+        let timeout = 2_u32
+            .checked_pow(attempt - 1)
+            .and_then(|factor| self.initial.checked_mul(factor))
+            .map_or(self.max, |timeout| timeout.min(self.max));
+
+        Some(timeout.mul_f64(1.0 - self.jitter * random_fraction()))
+    }
+}
+
+/// Waits the same `interval` for every attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedInterval {
+    interval: Duration,
+    attempts: u32,
+}
+
+impl FixedInterval {
+    #[must_use]
+    pub fn new(interval: Duration, attempts: u32) -> FixedInterval {
+        FixedInterval { interval, attempts }
+    }
+}
+
+impl RetryPolicy for FixedInterval {
+    fn timeout(&self, attempt: u32) -> Option<Duration> {
+        (1..=self.attempts)
+            .contains(&attempt)
+            .then_some(self.interval)
+    }
+}
+
+/// A policy with an overall deadline, see `RetryPolicy::with_deadline()`.
+#[derive(Debug, Clone, Copy)]
+pub struct WithDeadline<P> {
+    policy: P,
+    deadline: Duration,
+}
+
+impl<P: RetryPolicy> RetryPolicy for WithDeadline<P> {
+    fn timeout(&self, attempt: u32) -> Option<Duration> {
+        self.policy.timeout(attempt)
+    }
+
+    fn deadline(&self) -> Option<Duration> {
+        Some(
+            self.policy
+                .deadline()
+                .map_or(self.deadline, |deadline| deadline.min(self.deadline)),
+        )
+    }
+}
+
+/// Counts the attempts of a request under a `RetryPolicy`.
+pub(crate) struct Attempts<'policy> {
+    policy: &'policy dyn RetryPolicy,
+    give_up_at: Option<Instant>,
+    made: u32,
+}
+
+impl Attempts<'_> {
+    pub(crate) fn new(policy: &dyn RetryPolicy) -> Attempts<'_> {
+        Attempts {
+            policy,
+            give_up_at: policy
+                .deadline()
+                .and_then(|deadline| Instant::now().checked_add(deadline)),
+            made: 0,
+        }
+    }
+
+    /// The next attempt, and until when to wait for its response, or `None` when it's time to give up.
+    pub(crate) fn next(&mut self) -> Option<(u32, Instant)> {
+        let now = Instant::now();
+
+        if self.give_up_at.is_some_and(|give_up_at| give_up_at <= now) {
+            return None;
+        }
+
+        let timeout = self.policy.timeout(self.made + 1)?;
+
+        self.made += 1;
+
+        // a timeout too far out to represent is as good as a day
+        let wait_until = now
+            .checked_add(timeout)
+            .unwrap_or_else(|| now + Duration::from_hours(24));
+
+        Some((
+            self.made,
+            self.give_up_at
+                .map_or(wait_until, |give_up_at| wait_until.min(give_up_at)),
+        ))
+    }
+
+    /// The number of attempts made so far.
+    pub(crate) fn made(&self) -> u32 {
+        self.made
+    }
+}
+
+/// Between 0 and 1. Jitter doesn't need a good random number generator, just a different number every time,
+/// which a randomly seeded hasher gives us.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().hash_one(std::time::SystemTime::now()) >> 32;
+
+    f64::from(u32::try_from(random).unwrap_or(u32::MAX)) / f64::from(u32::MAX)
+}
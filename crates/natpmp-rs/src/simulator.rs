@@ -0,0 +1,248 @@
+//! A NAT-PMP gateway that behaves the way RFC 6886 requires, to run clients and the conformance runner against.
+//!
+//! It keeps a table of mappings and answers requests for it, but forwards no traffic.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf as _, BufMut as _};
+use tokio::net::UdpSocket;
+use tracing::{Level, event};
+
+use crate::VERSION;
+use crate::errors::NATPMPResultError;
+
+/// Mapping requests are 12 bytes, anything shorter is truncated.
+const MAPPING_REQUEST_SIZE: usize = 12;
+
+/// Mappings are per protocol (the opcode), client and internal port.
+/// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.3>
+type MappingKey = (u8, Ipv4Addr, u16);
+
+struct Mapping {
+    external_port: u16,
+    expires_at: Instant,
+}
+
+/// An RFC 6886 gateway on a UDP socket.
+pub struct Simulator {
+    socket: UdpSocket,
+    external_address: Ipv4Addr,
+    started_at: Instant,
+    mappings: BTreeMap<MappingKey, Mapping>,
+}
+
+impl Simulator {
+    /// Listens on `address`, usually port 5351 of a loopback address, and reports `external_address` as its
+    /// external address.
+    ///
+    /// # Errors
+    /// When the socket can't be bound
+    pub async fn bind(
+        address: SocketAddrV4,
+        external_address: Ipv4Addr,
+    ) -> Result<Simulator, io::Error> {
+        let socket = UdpSocket::bind(address).await?;
+
+        Ok(Simulator {
+            socket,
+            external_address,
+            started_at: Instant::now(),
+            mappings: BTreeMap::new(),
+        })
+    }
+
+    /// # Errors
+    /// When the socket can't tell
+    pub fn local_address(&self) -> Result<SocketAddr, io::Error> {
+        self.socket.local_addr()
+    }
+
+    /// Answers requests until the socket fails.
+    ///
+    /// # Errors
+    /// When receiving or sending fails
+    pub async fn run(mut self) -> Result<(), io::Error> {
+        // larger than any request, so that oversized ones aren't mistaken for well-formed ones
+        let mut buffer = [0; 1100];
+
+        loop {
+            let (size, from) = self.socket.recv_from(&mut buffer).await?;
+
+            let SocketAddr::V4(from) = from else {
+                continue;
+            };
+
+            match self.answer(&buffer[..size], *from.ip()) {
+                Some(response) => {
+                    self.socket.send_to(&response, from).await?;
+                },
+                None => {
+                    event!(Level::DEBUG, %from, size, "Ignoring request");
+                },
+            }
+        }
+    }
+
+    /// The response to `request` from `client`, `None` for requests that must be ignored.
+    fn answer(&mut self, mut request: &[u8], client: Ipv4Addr) -> Option<Vec<u8>> {
+        // too short to even tell what it is
+        if request.len() < 2 {
+            return None;
+        }
+
+        let version = request.get_u8();
+        let opcode = request.get_u8();
+
+        // Source: https://www.rfc-editor.org/rfc/rfc6886#section-3.5
+        if opcode >= 128 {
+            // a response, not a request
+            return None;
+        }
+
+        if version != VERSION {
+            return Some(self.header(opcode, NATPMPResultError::UnsupportedVersion.code()));
+        }
+
+        match opcode {
+            0 => {
+                let mut response = self.header(opcode, 0);
+                response.put_u32(self.external_address.to_bits());
+
+                Some(response)
+            },
+            1 | 2 => {
+                // the opcode is already read
+                if request.len() < MAPPING_REQUEST_SIZE - 2 {
+                    return None;
+                }
+
+                let _reserved = request.get_u16();
+                let internal_port = request.get_u16();
+                let requested_external_port = request.get_u16();
+                let lifetime = request.get_u32();
+
+                Some(self.map(
+                    opcode,
+                    client,
+                    internal_port,
+                    requested_external_port,
+                    lifetime,
+                ))
+            },
+            _ => Some(self.header(opcode, NATPMPResultError::UnsupportedOpcode.code())),
+        }
+    }
+
+    /// Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.3>
+    fn map(
+        &mut self,
+        opcode: u8,
+        client: Ipv4Addr,
+        internal_port: u16,
+        requested_external_port: u16,
+        lifetime: u32,
+    ) -> Vec<u8> {
+        let now = Instant::now();
+
+        self.mappings.retain(|_, mapping| mapping.expires_at > now);
+
+        let (external_port, lifetime) = if lifetime == 0 {
+            if internal_port == 0 {
+                // delete all of the client's mappings of this protocol
+                // Source: https://www.rfc-editor.org/rfc/rfc6886#section-3.4
+                self.mappings
+                    .retain(|&(mapping_opcode, mapping_client, _), _| {
+                        mapping_opcode != opcode || mapping_client != client
+                    });
+            } else {
+                self.mappings.remove(&(opcode, client, internal_port));
+            }
+
+            (0, 0)
+        } else if internal_port == 0 {
+            return self.port_response(
+                opcode,
+                NATPMPResultError::NotAuthorizedRefused.code(),
+                internal_port,
+                0,
+                0,
+            );
+        } else {
+            let external_port = match self.mappings.get(&(opcode, client, internal_port)) {
+                // a renewal keeps the port
+                Some(mapping) => mapping.external_port,
+                None => self.free_port(
+                    opcode,
+                    if requested_external_port == 0 {
+                        internal_port
+                    } else {
+                        requested_external_port
+                    },
+                ),
+            };
+
+            self.mappings.insert(
+                (opcode, client, internal_port),
+                Mapping {
+                    external_port,
+                    expires_at: now + Duration::from_secs(u64::from(lifetime)),
+                },
+            );
+
+            (external_port, lifetime)
+        };
+
+        self.port_response(opcode, 0, internal_port, external_port, lifetime)
+    }
+
+    /// `preferred`, or the next port after it that no other mapping of this protocol has.
+    fn free_port(&self, opcode: u8, preferred: u16) -> u16 {
+        let mut port = preferred;
+
+        while self
+            .mappings
+            .iter()
+            .any(|(&(mapping_opcode, ..), mapping)| {
+                mapping_opcode == opcode && mapping.external_port == port
+            })
+        {
+            port = port.checked_add(1).unwrap_or(1);
+        }
+
+        port
+    }
+
+    /// The common header, which is all an error response needs.
+    fn header(&self, opcode: u8, result_code: u16) -> Vec<u8> {
+        let mut response = vec![];
+        response.put_u8(VERSION);
+        response.put_u8(opcode + 128);
+        response.put_u16(result_code);
+        response.put_u32(self.seconds_since_epoch());
+
+        response
+    }
+
+    fn port_response(
+        &self,
+        opcode: u8,
+        result_code: u16,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Vec<u8> {
+        let mut response = self.header(opcode, result_code);
+        response.put_u16(internal_port);
+        response.put_u16(external_port);
+        response.put_u32(lifetime);
+
+        response
+    }
+
+    fn seconds_since_epoch(&self) -> u32 {
+        u32::try_from(self.started_at.elapsed().as_secs()).unwrap_or(u32::MAX)
+    }
+}
@@ -0,0 +1,328 @@
+//! A STUN Binding client (RFC 5389), to check the external address a gateway reports against the address
+//! the internet actually sees us as.
+
+use std::hash::{BuildHasher as _, RandomState};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use bytes::Buf as _;
+use tokio::net::UdpSocket;
+use tracing::{Level, event};
+use zerocopy::network_endian::{U16, U32};
+use zerocopy::{Immutable, IntoBytes};
+
+use crate::binding::Binding;
+use crate::errors::StunError;
+use crate::reachability::Reachability;
+use crate::responses::ExternalAddressResponse;
+use crate::retry::{Attempts, RetryPolicy};
+
+/// Source: <https://www.rfc-editor.org/rfc/rfc5389#section-6>
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+/// The port of `XOR-MAPPED-ADDRESS` is masked with the most significant half of the magic cookie
+const MAGIC_COOKIE_HIGH: u16 = 0x2112;
+const HEADER_SIZE: usize = 20;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+
+/// Source: <https://www.rfc-editor.org/rfc/rfc5389#section-18.2>
+const MAPPED_ADDRESS: u16 = 0x0001;
+const ERROR_CODE: u16 = 0x0009;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+const FAMILY_IPV4: u8 = 0x01;
+
+/// The port STUN servers listen on, unless told otherwise.
+pub const DEFAULT_STUN_PORT: u16 = 3478;
+
+#[derive(IntoBytes, Immutable)]
+#[repr(C)]
+struct BindingRequest {
+    message_type: U16,
+    message_length: U16,
+    magic_cookie: U32,
+    transaction_id: [u8; 12],
+}
+
+impl BindingRequest {
+    fn new() -> BindingRequest {
+        // the transaction ID only has to be unpredictable enough to not match someone else's
+        let random = RandomState::new();
+        let mut transaction_id = [0; 12];
+
+        transaction_id[..8].copy_from_slice(random.hash_one(1).as_bytes());
+        transaction_id[8..].copy_from_slice(&random.hash_one(2).as_bytes()[..4]);
+
+        BindingRequest {
+            message_type: BINDING_REQUEST.into(),
+            message_length: U16::ZERO,
+            magic_cookie: MAGIC_COOKIE.into(),
+            transaction_id,
+        }
+    }
+}
+
+/// How the address a STUN server sees us as compares to the external address the gateway reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StunVerdict {
+    /// Both agree, mappings on the gateway are on the address the internet sees
+    Match,
+    /// They differ, and the gateway's external address isn't public: another NAT sits in front of the gateway
+    DoubleNat,
+    /// They differ, but the gateway claims a public address: it reports a stale or wrong address
+    Mismatch,
+}
+
+impl std::fmt::Display for StunVerdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                StunVerdict::Match => "match",
+                StunVerdict::DoubleNat => "mismatch, double NAT",
+                StunVerdict::Mismatch => "mismatch, the gateway reports a wrong external address",
+            }
+        )
+    }
+}
+
+/// The external address a gateway reports, next to the one a STUN server sees.
+#[derive(Debug)]
+pub struct StunCrossCheck {
+    external_address: ExternalAddressResponse,
+    stun_server: SocketAddrV4,
+    reflexive_address: SocketAddrV4,
+}
+
+impl StunCrossCheck {
+    #[must_use]
+    pub fn new(
+        external_address: ExternalAddressResponse,
+        stun_server: SocketAddrV4,
+        reflexive_address: SocketAddrV4,
+    ) -> StunCrossCheck {
+        StunCrossCheck {
+            external_address,
+            stun_server,
+            reflexive_address,
+        }
+    }
+
+    /// What the gateway reported
+    #[must_use]
+    pub fn external_address(&self) -> &ExternalAddressResponse {
+        &self.external_address
+    }
+
+    #[must_use]
+    pub fn stun_server(&self) -> SocketAddrV4 {
+        self.stun_server
+    }
+
+    /// The address and port the STUN server saw our request come from
+    #[must_use]
+    pub fn reflexive_address(&self) -> SocketAddrV4 {
+        self.reflexive_address
+    }
+
+    #[must_use]
+    pub fn verdict(&self) -> StunVerdict {
+        if *self.reflexive_address.ip() == self.external_address.ipv4_address() {
+            StunVerdict::Match
+        } else if self.external_address.reachability() == Reachability::Public {
+            StunVerdict::Mismatch
+        } else {
+            StunVerdict::DoubleNat
+        }
+    }
+
+    /// Whether the addresses differ
+    #[must_use]
+    pub fn is_mismatch(&self) -> bool {
+        self.verdict() != StunVerdict::Match
+    }
+}
+
+impl std::fmt::Display for StunCrossCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gateway reports {}, {} sees {}: {}",
+            self.external_address.ipv4_address(),
+            self.stun_server,
+            self.reflexive_address.ip(),
+            self.verdict()
+        )
+    }
+}
+
+/// Asks `server` which address and port our request came from, with a socket set up as described by `binding`.
+///
+/// # Errors
+/// When the server can't be reached, doesn't answer, or answers with an error
+pub async fn binding_request(
+    server: SocketAddrV4,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<SocketAddrV4, StunError> {
+    let socket = UdpSocket::from_std(binding.socket().await?)?;
+    let request = BindingRequest::new();
+
+    let mut attempts = Attempts::new(retry_policy);
+
+    while let Some((tries, wait_until)) = attempts.next() {
+        socket.send_to(request.as_bytes(), server).await?;
+
+        loop {
+            // zeroed every time, so that a short datagram doesn't leave parts of the previous one behind
+            let mut buffer = [0; 576];
+
+            match tokio::time::timeout_at(wait_until, socket.recv_from(&mut buffer)).await {
+                Ok(Ok((size, from))) => {
+                    if from != SocketAddr::V4(server) {
+                        continue;
+                    }
+
+                    if let Some(result) = parse_response(server, &request, &buffer[..size]) {
+                        return result;
+                    }
+
+                    event!(
+                        Level::DEBUG,
+                        "Ignoring STUN message that doesn't answer our request"
+                    );
+                },
+                Ok(Err(error)) if error.kind() == ErrorKind::WouldBlock => {},
+                Ok(Err(error)) => return Err(error.into()),
+                Err(_) => {
+                    event!(Level::WARN, "STUN request timed out, try {}", tries);
+
+                    break;
+                },
+            }
+        }
+    }
+
+    Err(StunError::TimedOut {
+        server,
+        attempts: attempts.made(),
+    })
+}
+
+/// Compares the external address the gateway reported with the address `server` sees us as.
+///
+/// Uses the same `binding` the gateway was asked with, so that both look at the same path out.
+///
+/// # Errors
+/// When the STUN server can't tell
+pub async fn cross_check(
+    external_address: ExternalAddressResponse,
+    server: SocketAddrV4,
+    retry_policy: &dyn RetryPolicy,
+    binding: &Binding,
+) -> Result<StunCrossCheck, StunError> {
+    let reflexive_address = binding_request(server, retry_policy, binding).await?;
+
+    let check = StunCrossCheck::new(external_address, server, reflexive_address);
+
+    if check.is_mismatch() {
+        event!(Level::WARN, %check, "External address reported by the gateway differs from the one the STUN server sees");
+    }
+
+    Ok(check)
+}
+
+/// `None` when the message isn't a response to `request`, so that we keep waiting for one that is.
+fn parse_response(
+    server: SocketAddrV4,
+    request: &BindingRequest,
+    message: &[u8],
+) -> Option<Result<SocketAddrV4, StunError>> {
+    let invalid = |reason| Some(Err(StunError::InvalidResponse { server, reason }));
+
+    if message.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let mut header = &message[..HEADER_SIZE];
+
+    let message_type = header.get_u16();
+    let message_length = usize::from(header.get_u16());
+    let magic_cookie = header.get_u32();
+
+    if magic_cookie != MAGIC_COOKIE || header != request.transaction_id {
+        return None;
+    }
+
+    let Some(mut attributes) = message.get(HEADER_SIZE..HEADER_SIZE + message_length) else {
+        return invalid("Message is shorter than its length");
+    };
+
+    let mut mapped_address = None;
+    let mut xor_mapped_address = None;
+    let mut error = None;
+
+    // Source: https://www.rfc-editor.org/rfc/rfc5389#section-15
+    while attributes.remaining() >= 4 {
+        let attribute_type = attributes.get_u16();
+        let length = usize::from(attributes.get_u16());
+        // values are padded to a multiple of 4 bytes
+        let padded_length = length.next_multiple_of(4);
+
+        if attributes.remaining() < length {
+            return invalid("Attribute is longer than the message");
+        }
+
+        let value = &attributes[..length];
+
+        match attribute_type {
+            MAPPED_ADDRESS => mapped_address = parse_address(value, 0, 0),
+            XOR_MAPPED_ADDRESS => {
+                xor_mapped_address = parse_address(value, MAGIC_COOKIE_HIGH, MAGIC_COOKIE);
+            },
+            ERROR_CODE if value.len() >= 4 => {
+                let code = u16::from(value[2] & 0x07) * 100 + u16::from(value[3]);
+                let reason = String::from_utf8_lossy(&value[4..]).into_owned();
+
+                error = Some((code, reason));
+            },
+            _ => {},
+        }
+
+        attributes.advance(padded_length.min(attributes.remaining()));
+    }
+
+    match message_type {
+        BINDING_SUCCESS_RESPONSE => match xor_mapped_address.or(mapped_address) {
+            Some(address) => Some(Ok(address)),
+            None => invalid("Response has no IPv4 mapped address"),
+        },
+        BINDING_ERROR_RESPONSE => {
+            let (code, reason) = error.unwrap_or((0, String::new()));
+
+            Some(Err(StunError::ErrorResponse {
+                server,
+                code,
+                reason,
+            }))
+        },
+        _ => None,
+    }
+}
+
+/// Parses a `MAPPED-ADDRESS` or `XOR-MAPPED-ADDRESS`, `None` when it's an IPv6 address.
+fn parse_address(mut value: &[u8], port_mask: u16, address_mask: u32) -> Option<SocketAddrV4> {
+    if value.len() < 8 {
+        return None;
+    }
+
+    let _reserved = value.get_u8();
+    let family = value.get_u8();
+    let port = value.get_u16() ^ port_mask;
+    let address = value.get_u32() ^ address_mask;
+
+    (family == FAMILY_IPV4).then(|| SocketAddrV4::new(Ipv4Addr::from(address), port))
+}
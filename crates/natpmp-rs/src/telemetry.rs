@@ -0,0 +1,82 @@
+//! Metrics about our conversations with gateways, recorded through the `metrics` facade.
+//!
+//! Nothing is recorded unless the application installs a recorder, e.g. a Prometheus exporter.
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use metrics::{counter, gauge, histogram};
+
+use crate::VERSION;
+use crate::errors::{NATPMPError, NATPMPResultError};
+use crate::pcp::PCP_VERSION;
+use crate::protocol::{MappingProtocol, Opcode};
+use crate::responses::Response;
+
+pub const REQUESTS_SENT: &str = "natpmp_requests_sent_total";
+pub const RETRIES: &str = "natpmp_retries_total";
+pub const TIMEOUTS: &str = "natpmp_timeouts_total";
+pub const RESULTS: &str = "natpmp_results_total";
+pub const REQUEST_DURATION: &str = "natpmp_request_duration_seconds";
+pub const GATEWAY_EPOCH: &str = "natpmp_gateway_epoch_seconds";
+
+fn opcode_label(version: u8, opcode: Opcode) -> &'static str {
+    match (version, opcode) {
+        (VERSION, Opcode::ExternalAddress) => "external_address",
+        (VERSION, Opcode::Mapping(MappingProtocol::UDP)) => "map_udp",
+        (VERSION, Opcode::Mapping(MappingProtocol::TCP)) => "map_tcp",
+        (PCP_VERSION, Opcode::ExternalAddress) => "pcp_announce",
+        _ => "unknown",
+    }
+}
+
+fn result_label(result_error: NATPMPResultError) -> &'static str {
+    match result_error {
+        NATPMPResultError::UnsupportedVersion => "unsupported_version",
+        NATPMPResultError::NotAuthorizedRefused => "not_authorized_refused",
+        NATPMPResultError::NetworkFailure => "network_failure",
+        NATPMPResultError::OutOfResources => "out_of_resources",
+        NATPMPResultError::UnsupportedOpcode => "unsupported_opcode",
+        NATPMPResultError::Unknown(_) => "unknown",
+    }
+}
+
+pub(crate) fn record_sent(version: u8, opcode: Opcode, attempt: u32) {
+    let opcode = opcode_label(version, opcode);
+
+    counter!(REQUESTS_SENT, "opcode" => opcode).increment(1);
+
+    if attempt > 1 {
+        counter!(RETRIES, "opcode" => opcode).increment(1);
+    }
+}
+
+pub(crate) fn record_timeout(version: u8, opcode: Opcode) {
+    counter!(TIMEOUTS, "opcode" => opcode_label(version, opcode)).increment(1);
+}
+
+pub(crate) fn record_response<R: Response>(
+    gateway_ip: Ipv4Addr,
+    version: u8,
+    opcode: Opcode,
+    round_trip: Duration,
+    result: &Result<R, NATPMPError>,
+) {
+    let opcode = opcode_label(version, opcode);
+
+    histogram!(REQUEST_DURATION, "opcode" => opcode).record(round_trip);
+
+    let result = match *result {
+        Ok(ref response) => {
+            gauge!(GATEWAY_EPOCH, "gateway" => gateway_ip.to_string())
+                .set(response.seconds_since_epoch());
+
+            "success"
+        },
+        Err(NATPMPError::Response { result, .. }) => result_label(result),
+        Err(NATPMPError::Unsupported { .. }) => "unsupported",
+        Err(_) => "invalid",
+    };
+
+    counter!(RESULTS, "opcode" => opcode, "result" => result).increment(1);
+}
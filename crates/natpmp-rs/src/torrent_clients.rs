@@ -0,0 +1,160 @@
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+use natpmp_rs::errors::IntegrationError;
+use natpmp_rs::integrations::qbittorrent::QBittorrent;
+use natpmp_rs::integrations::transmission::Transmission;
+use natpmp_rs::protocol::MappingProtocol;
+use tracing::{Level, event};
+
+use crate::cli::HookArgs;
+use crate::hooks::Change;
+
+/// Torrent clients that listen on the external port, so that they advertise a port that actually reaches them.
+///
+/// A torrent client listens on one port, so only the mapping of that port is followed, the others are left alone.
+pub struct TorrentClients {
+    qbittorrent: Option<QBittorrent>,
+    transmission: Option<Transmission>,
+    protocol: MappingProtocol,
+    /// The internal port of the followed mapping, the first one of `protocol` when not configured
+    internal_port: Option<NonZeroU16>,
+    /// The last port all clients were successfully updated to
+    port: Option<u16>,
+}
+
+impl TorrentClients {
+    pub fn new(hook_args: &HookArgs) -> Result<TorrentClients, IntegrationError> {
+        let qbittorrent = hook_args
+            .qbittorrent_url
+            .as_deref()
+            .map(|url| {
+                QBittorrent::new(
+                    url,
+                    hook_args
+                        .qbittorrent_username
+                        .clone()
+                        .zip(hook_args.qbittorrent_password.clone()),
+                )
+            })
+            .transpose()?;
+
+        let transmission = hook_args
+            .transmission_url
+            .as_deref()
+            .map(|url| {
+                Transmission::new(
+                    url,
+                    hook_args
+                        .transmission_username
+                        .clone()
+                        .zip(hook_args.transmission_password.clone()),
+                )
+            })
+            .transpose()?;
+
+        Ok(TorrentClients {
+            qbittorrent,
+            transmission,
+            protocol: hook_args.torrent_protocol,
+            internal_port: hook_args.torrent_port,
+            port: None,
+        })
+    }
+
+    /// Sets the clients' port when `change` is about the followed mapping.
+    pub async fn update(&mut self, change: &Change, timeout: Duration) {
+        if self.qbittorrent.is_none() && self.transmission.is_none() {
+            return;
+        }
+
+        let &Change::ExternalPort {
+            protocol,
+            internal_port,
+            new,
+            ..
+        } = change
+        else {
+            return;
+        };
+
+        if protocol != self.protocol {
+            return;
+        }
+
+        match self.internal_port {
+            Some(followed) if followed != internal_port => return,
+            Some(_) => {},
+            None => {
+                event!(Level::INFO, %protocol, %internal_port, "Following mapping for the torrent clients");
+
+                self.internal_port = Some(internal_port);
+            },
+        }
+
+        self.set_port(new, timeout).await;
+    }
+
+    async fn set_port(&mut self, port: u16, timeout: Duration) {
+        if self.port == Some(port) {
+            return;
+        }
+
+        let mut succeeded = true;
+
+        if let Some(qbittorrent) = self.qbittorrent.as_ref() {
+            succeeded &= report(
+                "qBittorrent",
+                port,
+                tokio::time::timeout(timeout, qbittorrent.set_listen_port(port)).await,
+            );
+        }
+
+        if let Some(transmission) = self.transmission.as_mut() {
+            succeeded &= report(
+                "Transmission",
+                port,
+                tokio::time::timeout(timeout, transmission.set_peer_port(port)).await,
+            );
+        }
+
+        if succeeded {
+            self.port = Some(port);
+        }
+    }
+}
+
+fn report(
+    client: &str,
+    port: u16,
+    result: Result<Result<(), IntegrationError>, tokio::time::error::Elapsed>,
+) -> bool {
+    match result {
+        Ok(Ok(())) => {
+            event!(Level::INFO, client, port, "Updated torrent client port");
+
+            true
+        },
+        Ok(Err(error)) => {
+            event!(
+                Level::ERROR,
+                ?error,
+                client,
+                port,
+                "Failed to update torrent client port"
+            );
+
+            false
+        },
+        Err(_) => {
+            event!(
+                Level::ERROR,
+                client,
+                port,
+                "Timed out updating torrent client port"
+            );
+
+            false
+        },
+    }
+}
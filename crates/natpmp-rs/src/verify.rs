@@ -0,0 +1,317 @@
+//! Checks that a mapping actually forwards traffic to the local socket, rather than trusting the gateway's word.
+//!
+//! Traffic has to arrive at the public address from somewhere. Either we send it ourselves, which only works
+//! when the gateway hairpins traffic from the inside back in, or a reflector outside the NAT, see
+//! `serve_reflector()`, sends it for us.
+//!
+//! The reflector speaks a line-based protocol over TCP: the client sends `tcp <port> <nonce>` or
+//! `udp <port> <nonce>`, and the reflector sends `<nonce>` to that port of the address the request came from.
+//! It never connects anywhere else, so it can't be used to send traffic to third parties.
+
+use std::hash::{BuildHasher as _, RandomState};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, SystemTime};
+
+use tokio::io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::Instant;
+use tracing::{Level, event};
+
+use crate::protocol::MappingProtocol;
+use crate::public_socket::PublicSocket;
+
+/// How long the reflector takes to connect back before it gives up.
+const REFLECTOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// UDP gets lost, so we, and the reflector, send the nonce a couple of times.
+const UDP_SENDS: u32 = 3;
+const UDP_SEND_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Where the traffic to the public address comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verifier {
+    /// Send it ourselves, through the gateway, which only works when the gateway supports hairpinning
+    Hairpin,
+    /// Ask the reflector at this address to send it from outside the NAT
+    Reflector(SocketAddr),
+}
+
+/// Whether traffic sent to the public address of a mapping arrived at the local socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    /// It arrived, the mapping forwards traffic
+    Reachable,
+    /// Our own traffic didn't come back, which many gateways don't do. That says nothing about traffic from
+    /// outside, use a reflector to tell
+    HairpinUnsupported,
+    /// The reflector's traffic didn't arrive, the mapping doesn't forward traffic from outside
+    Unreachable,
+}
+
+impl std::fmt::Display for VerifyOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                VerifyOutcome::Reachable => "reachable",
+                VerifyOutcome::HairpinUnsupported => "hairpin unsupported, inconclusive",
+                VerifyOutcome::Unreachable => "unreachable",
+            }
+        )
+    }
+}
+
+/// Checks that connections to the public address of `public_socket` arrive at its listener.
+///
+/// Connections that don't carry our nonce are accepted and dropped, so don't run this on a listener that is
+/// already in use.
+///
+/// # Errors
+/// When the reflector can't be reached, or the listener fails
+pub async fn verify_tcp(
+    public_socket: &PublicSocket<TcpListener>,
+    verifier: Verifier,
+    timeout: Duration,
+) -> Result<VerifyOutcome, io::Error> {
+    let nonce = nonce();
+    let public_address = public_socket.public_address();
+    let give_up_at = Instant::now() + timeout;
+
+    let arrived = async {
+        loop {
+            let (stream, from) = public_socket.socket().accept().await?;
+
+            if read_nonce(stream).await.as_deref() == Some(nonce.as_str()) {
+                return Ok::<_, io::Error>(());
+            }
+
+            event!(Level::DEBUG, %from, "Ignoring connection without our nonce");
+        }
+    };
+
+    let send = async {
+        match verifier {
+            Verifier::Hairpin => {
+                // refused or unanswered both mean the gateway didn't hairpin, we wait for the listener either way
+                if let Err(error) = connect_and_send(SocketAddr::V4(public_address), &nonce).await {
+                    event!(Level::DEBUG, ?error, %public_address, "Hairpin connection failed");
+                }
+            },
+            Verifier::Reflector(reflector) => {
+                ask_reflector(reflector, MappingProtocol::TCP, public_address, &nonce).await?;
+            },
+        }
+
+        // keep waiting for the listener
+        std::future::pending::<Result<(), io::Error>>().await
+    };
+
+    let result = tokio::time::timeout_at(give_up_at, async {
+        tokio::select! {
+            result = arrived => result,
+            result = send => result,
+        }
+    })
+    .await;
+
+    outcome(result, verifier)
+}
+
+/// Checks that datagrams to the public address of `public_socket` arrive at its socket.
+///
+/// Datagrams that don't carry our nonce are read and dropped, so don't run this on a socket that is already
+/// in use.
+///
+/// # Errors
+/// When the reflector can't be reached, or the socket fails
+pub async fn verify_udp(
+    public_socket: &PublicSocket<UdpSocket>,
+    verifier: Verifier,
+    timeout: Duration,
+) -> Result<VerifyOutcome, io::Error> {
+    let nonce = nonce();
+    let public_address = public_socket.public_address();
+    let give_up_at = Instant::now() + timeout;
+
+    let arrived = async {
+        let mut buffer = [0; 64];
+
+        loop {
+            let (size, from) = public_socket.socket().recv_from(&mut buffer).await?;
+
+            if buffer[..size] == *nonce.as_bytes() {
+                return Ok::<_, io::Error>(());
+            }
+
+            event!(Level::DEBUG, %from, "Ignoring datagram without our nonce");
+        }
+    };
+
+    let send = async {
+        match verifier {
+            Verifier::Hairpin => {
+                // from another socket, as a datagram from the mapped port to itself isn't what we're after
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+
+                send_datagrams(&socket, SocketAddr::V4(public_address), &nonce).await?;
+            },
+            Verifier::Reflector(reflector) => {
+                ask_reflector(reflector, MappingProtocol::UDP, public_address, &nonce).await?;
+            },
+        }
+
+        std::future::pending::<Result<(), io::Error>>().await
+    };
+
+    let result = tokio::time::timeout_at(give_up_at, async {
+        tokio::select! {
+            result = arrived => result,
+            result = send => result,
+        }
+    })
+    .await;
+
+    outcome(result, verifier)
+}
+
+/// Answers the requests of `verify_tcp()` and `verify_udp()` made with `Verifier::Reflector`, until the listener
+/// fails. Run it outside the NAT.
+///
+/// # Errors
+/// When accepting connections fails
+pub async fn serve_reflector(listener: TcpListener) -> Result<(), io::Error> {
+    loop {
+        let (stream, from) = listener.accept().await?;
+
+        tokio::task::spawn(async move {
+            if let Err(error) = reflect(stream, from).await {
+                event!(Level::WARN, ?error, %from, "Failed to reflect");
+            }
+        });
+    }
+}
+
+async fn reflect(stream: TcpStream, from: SocketAddr) -> Result<(), io::Error> {
+    let mut line = String::new();
+
+    // a request is short, anything longer isn't one
+    BufReader::new(stream.take(64)).read_line(&mut line).await?;
+
+    let mut parts = line.split_whitespace();
+
+    let (Some(protocol), Some(Ok(port)), Some(nonce), None) = (
+        parts.next(),
+        parts.next().map(str::parse::<u16>),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid request {:?}", line.trim_end()),
+        ));
+    };
+
+    // only ever back to where the request came from
+    let target = SocketAddr::new(from.ip(), port);
+
+    event!(Level::INFO, %target, protocol, "Reflecting");
+
+    match protocol {
+        "tcp" => {
+            tokio::time::timeout(REFLECTOR_TIMEOUT, connect_and_send(target, nonce))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        },
+        "udp" => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+
+            send_datagrams(&socket, target, nonce).await?;
+        },
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown protocol {:?}", protocol),
+            ));
+        },
+    }
+
+    Ok(())
+}
+
+async fn ask_reflector(
+    reflector: SocketAddr,
+    protocol: MappingProtocol,
+    public_address: SocketAddrV4,
+    nonce: &str,
+) -> Result<(), io::Error> {
+    let protocol = match protocol {
+        MappingProtocol::TCP => "tcp",
+        MappingProtocol::UDP => "udp",
+    };
+
+    let mut stream = TcpStream::connect(reflector).await?;
+
+    stream
+        .write_all(format!("{} {} {}\n", protocol, public_address.port(), nonce).as_bytes())
+        .await?;
+
+    stream.shutdown().await
+}
+
+async fn connect_and_send(target: SocketAddr, nonce: &str) -> Result<(), io::Error> {
+    let mut stream = TcpStream::connect(target).await?;
+
+    stream.write_all(nonce.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn send_datagrams(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    nonce: &str,
+) -> Result<(), io::Error> {
+    for _ in 0..UDP_SENDS {
+        socket.send_to(nonce.as_bytes(), target).await?;
+
+        tokio::time::sleep(UDP_SEND_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Reads what a connection sends, up to the length of a nonce, `None` when it fails or takes too long.
+async fn read_nonce(stream: TcpStream) -> Option<String> {
+    let mut nonce = String::new();
+
+    tokio::time::timeout(
+        REFLECTOR_TIMEOUT,
+        stream.take(64).read_to_string(&mut nonce),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    Some(nonce)
+}
+
+fn outcome(
+    result: Result<Result<(), io::Error>, tokio::time::error::Elapsed>,
+    verifier: Verifier,
+) -> Result<VerifyOutcome, io::Error> {
+    match (result, verifier) {
+        (Ok(Ok(())), _) => Ok(VerifyOutcome::Reachable),
+        (Ok(Err(error)), _) => Err(error),
+        (Err(_), Verifier::Hairpin) => Ok(VerifyOutcome::HairpinUnsupported),
+        (Err(_), Verifier::Reflector(_)) => Ok(VerifyOutcome::Unreachable),
+    }
+}
+
+/// Tells our traffic apart from whatever else arrives on the public address.
+fn nonce() -> String {
+    format!(
+        "natpmp-{:016x}",
+        RandomState::new().hash_one(SystemTime::now())
+    )
+}
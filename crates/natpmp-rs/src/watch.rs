@@ -0,0 +1,199 @@
+//! Watches the public address of a gateway, for dynamic DNS, firewall allowlists and the like.
+//!
+//! Gateways announce address changes, so we listen for those and only ask the gateway when it has been quiet for
+//! a while, in case announcements don't reach us.
+//! Source: <https://www.rfc-editor.org/rfc/rfc6886#section-3.2.1>
+
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::Stream;
+use futures_util::stream::{StreamExt as _, once, unfold};
+use tokio::time::Instant;
+use tracing::{Level, event};
+
+use crate::announcements::{
+    Announcement, AnnouncementListener, ExternalAddressChange, ExternalAddressHistory,
+};
+use crate::client::Client;
+use crate::errors::NATPMPError;
+use crate::resolve_gateway;
+use crate::retry::Rfc6886;
+
+/// The shortest poll interval, shorter ones are raised to it. As long as the gateway is given to answer a request
+/// before we ask again, see `Rfc6886`.
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A stream of the public addresses of a gateway, with the gateway's epoch. The first item is the current address,
+/// after that an item is only yielded when the address changed, or when the epoch regressed, i.e. the gateway
+/// restarted and might have lost the mappings, see `ExternalAddressChange::epoch_regressed()`.
+///
+/// Errors are yielded too, e.g. when the gateway stopped answering, and the stream carries on after them. It never
+/// ends, drop it to stop watching.
+pub struct PublicAddressWatch {
+    changes: Pin<Box<dyn Stream<Item = Result<ExternalAddressChange, NATPMPError>> + Send>>,
+}
+
+impl std::fmt::Debug for PublicAddressWatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PublicAddressWatch").finish_non_exhaustive()
+    }
+}
+
+impl Stream for PublicAddressWatch {
+    type Item = Result<ExternalAddressChange, NATPMPError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.changes.as_mut().poll_next(cx)
+    }
+}
+
+struct Watcher {
+    client: Client,
+    poll_interval: Duration,
+    /// `None` when we can't receive announcements, and only poll
+    listener: Option<AnnouncementListener>,
+    history: ExternalAddressHistory,
+    /// `None` when the poll interval is too long to represent, and we never poll again
+    next_poll: Option<Instant>,
+}
+
+impl Watcher {
+    async fn start(client: Client, poll_interval: Duration) -> Watcher {
+        // announcements are a nice-to-have, we still poll when we can't receive them
+        let listener =
+            AnnouncementListener::bind_with_binding(Ipv4Addr::UNSPECIFIED, client.binding())
+                .await
+                .inspect_err(|error| {
+                    event!(
+                        Level::WARN,
+                        ?error,
+                        "Failed to listen for gateway announcements"
+                    );
+                })
+                .ok();
+
+        Watcher {
+            client,
+            poll_interval,
+            listener,
+            history: ExternalAddressHistory::new(),
+            // the current address right away
+            next_poll: Some(Instant::now()),
+        }
+    }
+
+    async fn next_change(&mut self) -> Result<ExternalAddressChange, NATPMPError> {
+        let gateway_ip = self.client.gateway_ip();
+
+        loop {
+            let response = tokio::select! {
+                announcement = next_announcement(self.listener.as_ref()) => match announcement {
+                    Ok(announcement) if announcement.source() == gateway_ip => {
+                        announcement.response().clone()
+                    },
+                    Ok(announcement) => {
+                        event!(Level::DEBUG, source = %announcement.source(), "Ignoring announcement from other gateway");
+
+                        continue;
+                    },
+                    // a datagram that isn't a valid announcement
+                    Err(error) if error.context().is_some() => {
+                        event!(Level::DEBUG, ?error, "Ignoring invalid announcement");
+
+                        continue;
+                    },
+                    Err(error) => {
+                        event!(Level::WARN, ?error, "Failed to receive gateway announcements, polling only");
+
+                        self.listener = None;
+
+                        return Err(error);
+                    },
+                },
+                () = sleep_until(self.next_poll) => {
+                    match self.client.get_external_address().await {
+                        Ok(response) => response,
+                        Err(error) => {
+                            self.next_poll = Instant::now().checked_add(self.poll_interval);
+
+                            return Err(error);
+                        },
+                    }
+                },
+            };
+
+            // an announcement tells us as much as polling would
+            self.next_poll = Instant::now().checked_add(self.poll_interval);
+
+            let change = self.history.record(gateway_ip, &response);
+
+            if change.previous_address().is_none()
+                || change.address_changed()
+                || change.epoch_regressed()
+            {
+                return Ok(change);
+            }
+        }
+    }
+}
+
+async fn next_announcement(
+    listener: Option<&AnnouncementListener>,
+) -> Result<Announcement, NATPMPError> {
+    match listener {
+        Some(listener) => listener.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Watches the public address of `client`'s gateway, listening for announcements where the client sends its
+/// requests from and polling every `poll_interval`, at least `MIN_POLL_INTERVAL`, when none arrive.
+pub(crate) fn watch(client: Client, poll_interval: Duration) -> PublicAddressWatch {
+    let watcher = Watcher::start(client, poll_interval.max(MIN_POLL_INTERVAL));
+
+    PublicAddressWatch {
+        changes: Box::pin(once(watcher).flat_map(|watcher| {
+            unfold(watcher, |mut watcher| async move {
+                let change = watcher.next_change().await;
+
+                Some((change, watcher))
+            })
+        })),
+    }
+}
+
+/// Watches the public address of the gateway, see `PublicAddressWatch`, instead of calling `get_public_address()`
+/// in a loop.
+///
+/// # Arguments
+/// * `gateway_ip` - the IP to the NAT-PMP compatible gateway, or discover it via `discover_gateway()`
+/// * `poll_interval` - how long to wait for an announcement before asking the gateway, at least `MIN_POLL_INTERVAL`
+/// * `retry` - the number of times to retry a request if unsuccessful, defaults to 9 as per specification.
+///
+/// # Errors
+/// When there is no gateway
+pub async fn watch_public_address(
+    gateway_ip: Option<Ipv4Addr>,
+    poll_interval: Duration,
+    retry: Option<u32>,
+) -> Result<PublicAddressWatch, NATPMPError> {
+    let gateway_ip = resolve_gateway(gateway_ip, Rfc6886::from_retry(retry)).await?;
+
+    let mut client = Client::new(gateway_ip);
+
+    if let Some(retry) = retry {
+        client = client.with_retry(retry);
+    }
+
+    Ok(watch(client, poll_interval))
+}
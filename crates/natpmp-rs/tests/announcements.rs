@@ -0,0 +1,204 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use bytes::BufMut as _;
+use futures_util::StreamExt as _;
+use natpmp_rs::announcements::{
+    ANNOUNCEMENT_MULTICAST_ADDRESS, ANNOUNCEMENT_PORT, ExternalAddressHistory,
+};
+use natpmp_rs::client::Client;
+use natpmp_rs::responses::epoch_regressed;
+use natpmp_rs::retry::Rfc6886;
+use natpmp_rs::watch::watch_public_address;
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
+use nix::sys::socket::SockaddrIn;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+/// An external address response, or announcement, with `epoch` and `external_address`.
+fn external_address_response(epoch: u32, external_address: Ipv4Addr) -> Vec<u8> {
+    let mut response = vec![];
+    response.put_u8(0);
+    response.put_u8(128);
+    response.put_u16(0);
+    response.put_u32(epoch);
+    response.put_u32(external_address.to_bits());
+
+    response
+}
+
+/// The address of an interface that is up and does multicast, which the kernel joins the announcement group on
+/// when it picks one. Loopback doesn't do multicast.
+fn multicast_address() -> Option<Ipv4Addr> {
+    getifaddrs()
+        .unwrap()
+        .filter(|interface| {
+            interface
+                .flags
+                .contains(InterfaceFlags::IFF_UP | InterfaceFlags::IFF_MULTICAST)
+                && !interface.flags.contains(InterfaceFlags::IFF_LOOPBACK)
+        })
+        .find_map(|interface| interface.address?.as_sockaddr_in().map(SockaddrIn::ip))
+}
+
+/// A gateway that answers external address requests with the given epochs and addresses, in order.
+async fn scripted_gateway(address: Ipv4Addr, answers: Vec<(u32, Ipv4Addr)>) {
+    let socket = UdpSocket::bind((address, 5351)).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 12];
+
+        for (epoch, external_address) in answers {
+            let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+            socket
+                .send_to(&external_address_response(epoch, external_address), from)
+                .await
+                .unwrap();
+        }
+    });
+}
+
+#[tokio::test]
+async fn tells_address_changes_and_epoch_regressions() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 80);
+    let first = Ipv4Addr::new(198, 51, 100, 7);
+    let second = Ipv4Addr::new(198, 51, 100, 9);
+
+    scripted_gateway(
+        gateway_ip,
+        vec![(100, first), (160, first), (5, first), (20, second)],
+    )
+    .await;
+
+    let client = Client::new(gateway_ip).with_retry_policy(Rfc6886::with_attempts(2));
+    let mut history = ExternalAddressHistory::new();
+
+    let mut changes = vec![];
+
+    for _ in 0..4 {
+        let response = client.get_external_address().await.unwrap();
+
+        changes.push(history.record(gateway_ip, &response));
+    }
+
+    assert_eq!(changes[0].previous_address(), None);
+    assert!(!changes[0].address_changed());
+    assert!(!changes[0].epoch_regressed());
+
+    assert_eq!(changes[1].previous_epoch(), Some(Duration::from_secs(100)));
+    assert!(!changes[1].address_changed());
+    assert!(!changes[1].epoch_regressed());
+
+    assert!(changes[2].epoch_regressed());
+    assert!(!changes[2].address_changed());
+
+    assert_eq!(changes[3].previous_address(), Some(first));
+    assert_eq!(changes[3].address(), second);
+    assert!(changes[3].address_changed());
+    assert!(!changes[3].epoch_regressed());
+}
+
+#[test]
+fn an_epoch_that_falls_behind_our_clock_regressed() {
+    let secs = Duration::from_secs;
+
+    // went back
+    assert!(epoch_regressed(secs(100), secs(99), secs(0)));
+    // kept up with 7/8 of the time that passed, give or take 2 seconds
+    assert!(!epoch_regressed(secs(100), secs(100), secs(0)));
+    assert!(!epoch_regressed(secs(100), secs(100), secs(2)));
+    assert!(!epoch_regressed(secs(100), secs(107), secs(10)));
+    assert!(!epoch_regressed(secs(100), secs(160), secs(60)));
+    // a restart that took longer than the epoch the gateway had before
+    assert!(epoch_regressed(secs(100), secs(101), secs(10)));
+    assert!(epoch_regressed(secs(100), secs(150), secs(60)));
+}
+
+#[tokio::test]
+async fn watching_yields_only_changes() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 81);
+    let first = Ipv4Addr::new(198, 51, 100, 7);
+    let second = Ipv4Addr::new(198, 51, 100, 9);
+
+    scripted_gateway(
+        gateway_ip,
+        vec![(100, first), (110, first), (120, first), (130, second)],
+    )
+    .await;
+
+    // raised to the minimum, rather than polling again right after every answer
+    let mut watch = watch_public_address(Some(gateway_ip), Duration::ZERO, Some(2))
+        .await
+        .unwrap();
+
+    let change = watch.next().await.unwrap().unwrap();
+
+    assert_eq!(change.address(), first);
+    assert_eq!(change.epoch(), Duration::from_secs(100));
+    assert_eq!(change.previous_address(), None);
+
+    // the unchanged answers in between aren't yielded
+    let change = watch.next().await.unwrap().unwrap();
+
+    assert_eq!(change.address(), second);
+    assert_eq!(change.epoch(), Duration::from_secs(130));
+    assert_eq!(change.previous_address(), Some(first));
+}
+
+#[tokio::test]
+async fn watching_hears_announcements() {
+    let Some(gateway_ip) = multicast_address() else {
+        eprintln!("No interface does multicast, skipping");
+
+        return;
+    };
+
+    let first = Ipv4Addr::new(198, 51, 100, 7);
+    let second = Ipv4Addr::new(198, 51, 100, 9);
+
+    // answers the first poll, after which only announcements arrive in time
+    scripted_gateway(gateway_ip, vec![(100, first)]).await;
+
+    let mut watch = watch_public_address(Some(gateway_ip), Duration::from_hours(1), Some(2))
+        .await
+        .unwrap();
+
+    assert_eq!(watch.next().await.unwrap().unwrap().address(), first);
+
+    // from the gateway's address, as announcements from other gateways are ignored
+    let announcer = UdpSocket::bind((gateway_ip, 0)).await.unwrap();
+    let group = (ANNOUNCEMENT_MULTICAST_ADDRESS, ANNOUNCEMENT_PORT);
+
+    announcer
+        .send_to(&external_address_response(101, second), group)
+        .await
+        .unwrap();
+
+    let change = tokio::time::timeout(Duration::from_secs(5), watch.next())
+        .await
+        .expect("the announcement is heard")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(change.address(), second);
+    assert_eq!(change.previous_address(), Some(first));
+
+    // a restart without a new address is worth telling too
+    announcer
+        .send_to(&external_address_response(5, second), group)
+        .await
+        .unwrap();
+
+    let change = tokio::time::timeout(Duration::from_secs(5), watch.next())
+        .await
+        .expect("the announcement is heard")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(change.address(), second);
+    assert!(!change.address_changed());
+    assert!(change.epoch_regressed());
+}
@@ -0,0 +1,209 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::{NonZeroU16, NonZeroUsize};
+
+mod common;
+
+use natpmp_rs::batch::{MappingEntry, map_ports, map_ports_bounded};
+use natpmp_rs::errors::{NATPMPError, NATPMPResultError};
+use natpmp_rs::protocol::MappingProtocol;
+use pretty_assertions::assert_eq;
+
+use crate::common::{PUBLIC_ADDRESS, Received, gateway, misbehaving_gateway, next};
+
+fn entry(protocol: MappingProtocol, port: u16) -> MappingEntry {
+    let port = NonZeroU16::new(port).unwrap();
+
+    MappingEntry::new(protocol, port, Some(port))
+}
+
+#[tokio::test]
+async fn map_ports_returns_responses_in_order() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 35);
+    let _requests = gateway(gateway_ip, vec![42000, 42001, 42002]).await;
+
+    let responses = map_ports(
+        &[
+            entry(MappingProtocol::TCP, 6000),
+            entry(MappingProtocol::UDP, 6000),
+            entry(MappingProtocol::UDP, 6001),
+        ],
+        None,
+        Some(gateway_ip),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        responses
+            .iter()
+            .map(|response| (
+                response.protocol().to_string(),
+                response.internal_port().get(),
+                response.external_port().map_or(0, NonZeroU16::get)
+            ))
+            .collect::<Vec<_>>(),
+        [
+            (String::from("TCP"), 6000, 42000),
+            (String::from("UDP"), 6000, 42001),
+            (String::from("UDP"), 6001, 42002)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn map_ports_rolls_back_when_one_fails() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 36);
+    let mut requests =
+        misbehaving_gateway(gateway_ip, PUBLIC_ADDRESS, vec![6100], &[6101], &[]).await;
+
+    let result = map_ports(
+        &[
+            entry(MappingProtocol::TCP, 6100),
+            entry(MappingProtocol::TCP, 6101),
+            entry(MappingProtocol::UDP, 6100),
+        ],
+        None,
+        Some(gateway_ip),
+        None,
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(NATPMPError::Response {
+            result: NATPMPResultError::NotAuthorizedRefused,
+            ..
+        })
+    ));
+
+    let mut received = vec![];
+
+    for _ in 0..5 {
+        received.push(next(&mut requests).await);
+    }
+
+    // the two that succeeded are deleted again
+    assert_eq!(
+        received.split_off(3),
+        [
+            Received {
+                opcode: 2,
+                internal_port: 6100,
+                external_port: 0,
+                lifetime: 0
+            },
+            Received {
+                opcode: 1,
+                internal_port: 6100,
+                external_port: 0,
+                lifetime: 0
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn map_ports_rolls_back_mappings_that_timed_out() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 92);
+    let mut requests =
+        misbehaving_gateway(gateway_ip, PUBLIC_ADDRESS, vec![6200], &[6202], &[6201]).await;
+
+    let result = map_ports(
+        &[
+            entry(MappingProtocol::TCP, 6200),
+            entry(MappingProtocol::TCP, 6201),
+            entry(MappingProtocol::TCP, 6202),
+        ],
+        None,
+        Some(gateway_ip),
+        Some(2),
+    )
+    .await;
+
+    // the first failure in the order of the entries
+    assert!(matches!(result, Err(NATPMPError::TimedOut { .. })));
+
+    let mut deleted = vec![];
+
+    while deleted.len() < 2 {
+        let received = next(&mut requests).await;
+
+        if received.lifetime == 0 {
+            deleted.push(received.internal_port);
+        }
+    }
+
+    // the one whose reply got lost might exist on the gateway
+    assert_eq!(deleted, [6200, 6201]);
+}
+
+#[tokio::test]
+async fn map_ports_bounded_keeps_the_window_full() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 93);
+    let mut requests =
+        misbehaving_gateway(gateway_ip, PUBLIC_ADDRESS, vec![6300], &[], &[6300]).await;
+
+    let result = map_ports_bounded(
+        &[
+            entry(MappingProtocol::TCP, 6300),
+            entry(MappingProtocol::TCP, 6301),
+            entry(MappingProtocol::TCP, 6302),
+            entry(MappingProtocol::TCP, 6303),
+        ],
+        None,
+        Some(gateway_ip),
+        Some(1),
+        NonZeroUsize::new(2).unwrap(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(NATPMPError::TimedOut { .. })));
+
+    let mut sent = vec![];
+
+    for _ in 0..4 {
+        sent.push(next(&mut requests).await.internal_port);
+    }
+
+    // the unanswered request doesn't hold back the ones after it
+    assert_eq!(sent, [6300, 6301, 6302, 6303]);
+}
+
+#[tokio::test]
+async fn map_ports_rejects_mapping_a_port_twice() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 101);
+    let mut requests = gateway(gateway_ip, vec![6400]).await;
+
+    let result = map_ports(
+        &[
+            entry(MappingProtocol::TCP, 6400),
+            entry(MappingProtocol::UDP, 6400),
+            MappingEntry::new(
+                MappingProtocol::TCP,
+                NonZeroU16::new(6400).unwrap(),
+                NonZeroU16::new(6401),
+            ),
+        ],
+        None,
+        Some(gateway_ip),
+        None,
+    )
+    .await;
+
+    let Err(
+        error @ NATPMPError::DuplicateEntry {
+            protocol: MappingProtocol::TCP,
+            internal_port,
+        },
+    ) = result
+    else {
+        panic!("Unexpected result {result:?}");
+    };
+
+    assert_eq!(internal_port.get(), 6400);
+    assert!(error.is_permanent());
+    // before anything is sent
+    assert!(requests.try_recv().is_err(), "nothing was sent");
+}
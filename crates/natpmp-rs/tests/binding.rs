@@ -0,0 +1,146 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::num::NonZeroU16;
+use std::path::Path;
+
+mod common;
+
+use bytes::BufMut as _;
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::NATPMPError;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+use crate::common::{PUBLIC_ADDRESS, Received, gateway, next};
+
+#[tokio::test]
+async fn requests_leave_from_the_source_address() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 50);
+    let source = Ipv4Addr::new(127, 0, 0, 51);
+    let socket = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    let gateway = tokio::spawn(async move {
+        let mut buffer = [0; 12];
+        let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+        let mut response = vec![];
+        response.put_u8(0);
+        response.put_u8(128);
+        response.put_u16(0);
+        response.put_u32(1);
+        response.put_slice(&Ipv4Addr::new(198, 51, 100, 7).octets());
+
+        socket.send_to(&response, from).await.unwrap();
+
+        from
+    });
+
+    let client = Client::new(gateway_ip).with_binding(Binding::new().with_source(source));
+
+    assert_eq!(
+        client.get_public_address().await.unwrap(),
+        Ipv4Addr::new(198, 51, 100, 7)
+    );
+
+    let SocketAddr::V4(from) = gateway.await.unwrap() else {
+        panic!("Request from an IPv6 address");
+    };
+
+    assert_eq!(*from.ip(), source);
+}
+
+#[test]
+fn network_namespace_names_are_in_run_netns() {
+    let binding = Binding::new().with_network_namespace(Path::new("vpn"));
+
+    assert_eq!(
+        binding.network_namespace(),
+        Some(Path::new("/run/netns/vpn"))
+    );
+
+    let binding = Binding::new()
+        .with_source(Ipv4Addr::new(10, 0, 0, 2))
+        .with_network_namespace(Path::new("/proc/1/ns/net"));
+
+    assert_eq!(
+        binding.network_namespace(),
+        Some(Path::new("/proc/1/ns/net"))
+    );
+    assert_eq!(
+        binding.to_string(),
+        "source 10.0.0.2, network namespace /proc/1/ns/net"
+    );
+    assert_eq!(Binding::new().to_string(), "default");
+}
+
+#[tokio::test]
+async fn sockets_of_a_network_namespace_share_one_thread() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 96);
+    let source = Ipv4Addr::new(127, 0, 0, 97);
+    let mut requests = gateway(gateway_ip, vec![4096]).await;
+
+    // our own namespace, entering it needs `CAP_SYS_ADMIN` all the same
+    let binding = Binding::new()
+        .with_source(source)
+        .with_network_namespace(Path::new("/proc/self/ns/net"));
+    let client = Client::new(gateway_ip).with_binding(binding);
+
+    for _ in 0..3 {
+        assert_eq!(client.get_public_address().await.unwrap(), PUBLIC_ADDRESS);
+        assert_eq!(next(&mut requests).await.opcode, 0);
+    }
+
+    let public_socket = client
+        .bind_public_udp(NonZeroU16::new(4096), Some(60))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 1,
+            internal_port: 4096,
+            external_port: 4096,
+            lifetime: 60,
+        }
+    );
+    assert_eq!(
+        public_socket.socket().local_addr().unwrap(),
+        SocketAddr::V4(SocketAddrV4::new(source, 4096)),
+        "the mapped socket is bound like the requests"
+    );
+
+    let namespace_threads = std::fs::read_dir("/proc/self/task")
+        .unwrap()
+        .filter(|task| {
+            std::fs::read_to_string(task.as_ref().unwrap().path().join("comm"))
+                .is_ok_and(|name| name.trim() == "natpmp-netns")
+        })
+        .count();
+
+    assert_eq!(namespace_threads, 1, "one thread creates all sockets");
+
+    // after the one thread, as the threads of this namespace would be counted too
+    let client = Client::new(gateway_ip)
+        .with_binding(Binding::new().with_network_namespace(Path::new("/nonexistent/ns/net")));
+
+    let requests = std::iter::repeat_with(|| {
+        let client = client.clone();
+
+        tokio::spawn(async move { client.get_public_address().await })
+    })
+    .take(8)
+    .collect::<Vec<_>>();
+
+    // every socket queued for the thread hears why it failed, not just the first
+    for request in requests {
+        let error = request.await.unwrap().unwrap_err();
+
+        assert!(
+            matches!(error, NATPMPError::Network { ref source, .. } if source.kind() == ErrorKind::NotFound),
+            "{error:?}"
+        );
+    }
+}
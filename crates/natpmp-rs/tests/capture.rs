@@ -0,0 +1,153 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+
+use bytes::{Buf as _, BufMut as _};
+use natpmp_rs::capture::{Capture, set_capture};
+use natpmp_rs::get_public_address;
+use pretty_assertions::{assert_eq, assert_ne};
+use tokio::net::UdpSocket;
+
+/// An enhanced packet block: the direction flags, the IPv4 packet, and the comment, if any.
+struct Packet {
+    flags: u32,
+    bytes: Vec<u8>,
+    comment: Option<String>,
+}
+
+/// Splits a pcapng file into its block types, and the enhanced packet blocks.
+fn parse(mut file: &[u8]) -> (Vec<u32>, Vec<Packet>) {
+    let mut block_types = vec![];
+    let mut packets = vec![];
+
+    while file.has_remaining() {
+        let block_type = file.get_u32_le();
+        let length = usize::try_from(file.get_u32_le()).unwrap();
+        let mut body = &file[..length - 12];
+
+        file.advance(length - 8);
+        block_types.push(block_type);
+
+        if block_type != 6 {
+            continue;
+        }
+
+        let _interface = body.get_u32_le();
+        let _timestamp = body.get_u64_le();
+        let captured = usize::try_from(body.get_u32_le()).unwrap();
+        let _original = body.get_u32_le();
+        let ip_packet = body[..captured].to_vec();
+
+        body.advance(captured.next_multiple_of(4));
+
+        let mut packet = Packet {
+            flags: 0,
+            bytes: ip_packet,
+            comment: None,
+        };
+
+        while body.has_remaining() {
+            let code = body.get_u16_le();
+            let length = usize::from(body.get_u16_le());
+
+            match code {
+                1 => packet.comment = Some(String::from_utf8(body[..length].to_vec()).unwrap()),
+                2 => packet.flags = (&body[..length]).get_u32_le(),
+                _ => {},
+            }
+
+            body.advance(length.next_multiple_of(4));
+        }
+
+        packets.push(packet);
+    }
+
+    (block_types, packets)
+}
+
+#[tokio::test]
+async fn captures_sent_received_and_discarded_datagrams() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 77);
+    let impostor_ip = Ipv4Addr::new(127, 0, 0, 78);
+    let path =
+        std::env::temp_dir().join(format!("natpmp-rs-capture-{}.pcapng", std::process::id()));
+
+    set_capture(Some(Capture::create(&path).unwrap()));
+
+    let gateway = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+    let impostor = UdpSocket::bind((impostor_ip, 5351)).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 12];
+        let (_size, from) = gateway.recv_from(&mut buffer).await.unwrap();
+
+        let mut response = vec![];
+        response.put_u8(0);
+        response.put_u8(128);
+        response.put_u16(0);
+        response.put_u32(1);
+        response.put_u32(Ipv4Addr::new(198, 51, 100, 7).to_bits());
+
+        // first from an address we didn't ask, which the client must discard
+        impostor.send_to(&response, from).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        gateway.send_to(&response, from).await.unwrap();
+    });
+
+    let public_address = get_public_address(Some(gateway_ip), Some(2)).await.unwrap();
+
+    set_capture(None);
+
+    assert_eq!(public_address, Ipv4Addr::new(198, 51, 100, 7));
+
+    let file = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let (block_types, packets) = parse(&file);
+
+    // section header, interface description, and a block per datagram: the discarded response costs an attempt
+    assert_eq!(block_types, [0x0A0D_0D0A, 1, 6, 6, 6, 6]);
+
+    let sources = packets
+        .iter()
+        .map(|packet| Ipv4Addr::from(<[u8; 4]>::try_from(&packet.bytes[12..16]).unwrap()))
+        .collect::<Vec<_>>();
+    let destinations = packets
+        .iter()
+        .map(|packet| Ipv4Addr::from(<[u8; 4]>::try_from(&packet.bytes[16..20]).unwrap()))
+        .collect::<Vec<_>>();
+
+    assert_eq!([sources[1], sources[3]], [impostor_ip, gateway_ip]);
+    assert_eq!([destinations[0], destinations[2]], [gateway_ip, gateway_ip]);
+
+    // the socket isn't bound to an address, the one the kernel sends from is looked up
+    assert_eq!(
+        [sources[0], destinations[1], destinations[3]],
+        [Ipv4Addr::LOCALHOST; 3]
+    );
+    assert_ne!(
+        (&packets[0].bytes[20..22]).get_u16(),
+        0,
+        "the request is captured with its source port"
+    );
+
+    // outbound and inbound, in turns
+    assert_eq!(
+        packets
+            .iter()
+            .map(|packet| packet.flags)
+            .collect::<Vec<_>>(),
+        [2, 1, 2, 1]
+    );
+    assert_eq!(
+        packets
+            .iter()
+            .map(|packet| packet.comment.as_deref())
+            .collect::<Vec<_>>(),
+        [None, Some("Discarded: not from the gateway"), None, None]
+    );
+
+    // IPv4 and UDP headers around the request
+    assert_eq!(packets[0].bytes.len(), 20 + 8 + 2);
+    assert_eq!(&packets[0].bytes[28..], &[0, 0]);
+    assert_eq!((&packets[3].bytes[20..22]).get_u16(), 5351);
+}
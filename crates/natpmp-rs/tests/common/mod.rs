@@ -0,0 +1,135 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use bytes::{Buf as _, BufMut as _};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// The address the stand-in gateway reports as its public address
+pub const PUBLIC_ADDRESS: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Received {
+    pub opcode: u8,
+    pub internal_port: u16,
+    pub external_port: u16,
+    pub lifetime: u32,
+}
+
+/// A stand-in gateway that grants the given external ports, in order, repeating the last one, or the requested one
+/// when there are none, and hands back every request it received. External address requests are handed back with only their opcode.
+pub async fn gateway(address: Ipv4Addr, granted: Vec<u16>) -> UnboundedReceiver<Received> {
+    gateway_reporting(address, PUBLIC_ADDRESS, granted).await
+}
+
+/// Like `gateway()`, but reports `public_address` as its public address.
+pub async fn gateway_reporting(
+    address: Ipv4Addr,
+    public_address: Ipv4Addr,
+    granted: Vec<u16>,
+) -> UnboundedReceiver<Received> {
+    misbehaving_gateway(address, public_address, granted, &[], &[]).await
+}
+
+/// Like `gateway_reporting()`, but refuses to map the `refused` internal ports, and loses the replies to mapping
+/// requests of the `unanswered` ones, after creating the mapping. Deleting mappings is always answered.
+pub async fn misbehaving_gateway(
+    address: Ipv4Addr,
+    public_address: Ipv4Addr,
+    granted: Vec<u16>,
+    refused: &'static [u16],
+    unanswered: &'static [u16],
+) -> UnboundedReceiver<Received> {
+    let socket = UdpSocket::bind((address, 5351)).await.unwrap();
+    let (sender, requests) = mpsc::unbounded_channel();
+
+    tokio::task::spawn(async move {
+        let mut mapped = 0;
+        let mut buffer = [0; 12];
+
+        loop {
+            let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+            let mut request = &buffer[1..];
+
+            let opcode = request.get_u8();
+
+            if opcode == 0 {
+                let mut response = vec![];
+                response.put_u8(0);
+                response.put_u8(128);
+                response.put_u16(0);
+                response.put_u32(1);
+                response.put_u32(public_address.to_bits());
+
+                socket.send_to(&response, from).await.unwrap();
+
+                if sender
+                    .send(Received {
+                        opcode,
+                        internal_port: 0,
+                        external_port: 0,
+                        lifetime: 0,
+                    })
+                    .is_err()
+                {
+                    break;
+                }
+
+                continue;
+            }
+
+            let _spacer = request.get_u16();
+
+            let received = Received {
+                opcode,
+                internal_port: request.get_u16(),
+                external_port: request.get_u16(),
+                lifetime: request.get_u32(),
+            };
+
+            let refuse = refused.contains(&received.internal_port) && received.lifetime != 0;
+            let answer = !unanswered.contains(&received.internal_port) || received.lifetime == 0;
+
+            let (external_port, lifetime) = if received.lifetime == 0 || refuse {
+                (0, 0)
+            } else {
+                let port = granted
+                    .get(mapped.min(granted.len().saturating_sub(1)))
+                    .copied()
+                    .unwrap_or(received.external_port);
+
+                mapped += 1;
+
+                (port, received.lifetime)
+            };
+
+            let mut response = vec![];
+            response.put_u8(0);
+            response.put_u8(128 + received.opcode);
+            // result code, 2 is not authorized, and seconds since epoch
+            response.put_u16(if refuse { 2 } else { 0 });
+            response.put_u32(1);
+            response.put_u16(received.internal_port);
+            response.put_u16(external_port);
+            response.put_u32(lifetime);
+
+            if answer {
+                socket.send_to(&response, from).await.unwrap();
+            }
+
+            if sender.send(received).is_err() {
+                break;
+            }
+        }
+    });
+
+    requests
+}
+
+pub async fn next(requests: &mut UnboundedReceiver<Received>) -> Received {
+    tokio::time::timeout(Duration::from_secs(5), requests.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
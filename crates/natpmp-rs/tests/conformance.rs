@@ -0,0 +1,102 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use bytes::BufMut as _;
+use natpmp_rs::client::Client;
+use natpmp_rs::conformance::Outcome;
+use natpmp_rs::retry::Rfc6886;
+use natpmp_rs::simulator::Simulator;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn the_simulator_conforms() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 74);
+    let external_address = Ipv4Addr::new(203, 0, 113, 1);
+
+    let simulator = Simulator::bind(SocketAddrV4::new(gateway_ip, 5351), external_address)
+        .await
+        .unwrap();
+
+    tokio::spawn(simulator.run());
+
+    let client = Client::new(gateway_ip).with_retry_policy(Rfc6886::with_attempts(2));
+
+    let report = client.check_conformance_destructively().await;
+
+    assert!(report.is_conformant(), "{report}");
+    assert_eq!(report.checks().len(), 10);
+    assert_eq!(
+        client.get_external_address().await.unwrap().ipv4_address(),
+        external_address
+    );
+}
+
+#[tokio::test]
+async fn a_gateway_that_answers_everything_the_same_fails() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 75);
+    let socket = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    // answers every datagram with its external address, whatever the version or opcode
+    tokio::spawn(async move {
+        let mut buffer = [0; 64];
+
+        loop {
+            let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+            let mut response = vec![];
+            response.put_u8(0);
+            response.put_u8(128);
+            response.put_u16(0);
+            response.put_u32(1);
+            response.put_u32(Ipv4Addr::new(203, 0, 113, 2).to_bits());
+
+            socket.send_to(&response, from).await.unwrap();
+        }
+    });
+
+    let report = Client::new(gateway_ip)
+        .with_retry_policy(Rfc6886::with_attempts(2))
+        .check_conformance()
+        .await;
+
+    assert!(!report.is_conformant());
+    // it would delete every TCP mapping of this host
+    assert!(report.check("unmap all").is_none(), "{report}");
+    assert!(report.check("external address").unwrap().passed());
+    assert!(report.check("epoch monotonicity").unwrap().passed());
+
+    for name in [
+        "unsupported version",
+        "unknown opcode",
+        "responses are ignored",
+        "truncated requests",
+        "mapping and lifetime 0 deletion",
+        "response opcode is request opcode + 128",
+    ] {
+        assert!(!report.check(name).unwrap().passed(), "{name}");
+    }
+
+    assert_eq!(
+        report.check("unknown opcode").unwrap().outcome(),
+        &Outcome::Fail(String::from("Response opcode 128 instead of 131"))
+    );
+}
+
+#[tokio::test]
+async fn a_silent_gateway_fails_the_first_check_only() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 76);
+
+    let _silent = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    let report = Client::new(gateway_ip)
+        .with_retry_policy(Rfc6886::with_attempts(1))
+        .check_conformance()
+        .await;
+
+    assert_eq!(report.checks().len(), 1);
+    assert_eq!(
+        report.checks()[0].outcome(),
+        &Outcome::Fail(String::from("No response"))
+    );
+}
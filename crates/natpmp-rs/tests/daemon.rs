@@ -0,0 +1,85 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::process::Stdio;
+use std::time::Duration;
+
+mod common;
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+use pretty_assertions::assert_eq;
+use tokio::process::Command;
+
+use crate::common::{Received, gateway, next};
+
+#[tokio::test]
+async fn keeps_mappings_until_terminated() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 88);
+    let mut requests = gateway(gateway_ip, vec![40080]).await;
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+        .args([
+            "daemon",
+            "--gateway",
+            &gateway_ip.to_string(),
+            "--retry",
+            "2",
+        ])
+        .args(["--udp", "5000", "--lifetime", "2"])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mapped = Received {
+        opcode: 1,
+        internal_port: 5000,
+        external_port: 5000,
+        lifetime: 2,
+    };
+
+    assert_eq!(next(&mut requests).await, mapped);
+    assert_eq!(next(&mut requests).await.opcode, 0);
+
+    // renewed after half of the lifetime, asking for the port we got
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            external_port: 40080,
+            ..mapped
+        }
+    );
+
+    let pid = Pid::from_raw(i32::try_from(daemon.id().unwrap()).unwrap());
+
+    kill(pid, Signal::SIGTERM).unwrap();
+
+    let status = tokio::time::timeout(Duration::from_secs(10), daemon.wait())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(status.success());
+
+    let unmapped = next(&mut requests).await;
+
+    assert_eq!(unmapped.internal_port, 5000);
+    assert_eq!(unmapped.lifetime, 0);
+}
+
+#[tokio::test]
+async fn rejects_a_poll_interval_out_of_range() {
+    for poll_interval in ["0", "18446744073709551615"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+            .args(["daemon", "--gateway", "127.0.0.1", "--tcp", "8080"])
+            .args(["--poll-interval", poll_interval])
+            .output()
+            .await
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(2), "{poll_interval}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("--poll-interval"),
+            "the error names the argument"
+        );
+    }
+}
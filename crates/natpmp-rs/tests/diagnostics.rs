@@ -0,0 +1,91 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+
+use natpmp_rs::client::Client;
+use natpmp_rs::diagnostics::PortCheck;
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::retry::Rfc6886;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+mod common;
+
+#[tokio::test]
+async fn diagnoses_a_natpmp_gateway() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 71);
+
+    // answers PCP as a NAT-PMP gateway, and grants a port we didn't ask for
+    let mut requests = common::gateway(gateway_ip, vec![40000]).await;
+
+    let diagnosis = Client::new(gateway_ip)
+        .with_retry_policy(Rfc6886::with_attempts(2))
+        .diagnose()
+        .await;
+
+    assert!(diagnosis.is_healthy());
+    assert_eq!(diagnosis.gateway(), gateway_ip);
+    assert_eq!(diagnosis.local_address(), Some(Ipv4Addr::LOCALHOST));
+    assert!(matches!(diagnosis.port(), PortCheck::Answered(_)));
+
+    let natpmp = diagnosis.natpmp().unwrap().result().unwrap();
+
+    assert_eq!(natpmp.ipv4_address(), common::PUBLIC_ADDRESS);
+    assert_eq!(natpmp.gateway_epoch().as_secs(), 1);
+
+    assert!(matches!(
+        diagnosis.pcp().unwrap().result(),
+        Err(&NATPMPError::Unsupported { .. })
+    ));
+
+    let protocols = diagnosis
+        .mappings()
+        .iter()
+        .map(|mapping| mapping.protocol().to_string())
+        .collect::<Vec<_>>();
+
+    assert_eq!(protocols, ["TCP", "UDP"]);
+
+    for mapping in diagnosis.mappings() {
+        assert!(mapping.is_different_port());
+        assert_eq!(
+            mapping.mapping().result().unwrap().external_port(),
+            std::num::NonZeroU16::new(40000)
+        );
+        mapping.unmapping().unwrap().result().unwrap();
+    }
+
+    // the port check and the external address request, then mapping and deleting, per protocol
+    let opcodes = [
+        common::next(&mut requests).await.opcode,
+        common::next(&mut requests).await.opcode,
+    ];
+
+    assert_eq!(opcodes, [0, 0]);
+}
+
+#[tokio::test]
+async fn tells_a_silent_gateway_from_a_refusing_one() {
+    let silent_ip = Ipv4Addr::new(127, 0, 0, 72);
+    let refusing_ip = Ipv4Addr::new(127, 0, 0, 73);
+
+    // bound, so no ICMP error, but never answers
+    let _silent = UdpSocket::bind((silent_ip, 5351)).await.unwrap();
+
+    let silent = Client::new(silent_ip)
+        .with_retry_policy(Rfc6886::with_attempts(1))
+        .diagnose()
+        .await;
+
+    assert!(matches!(silent.port(), PortCheck::Silent));
+    assert!(silent.natpmp().is_none());
+    assert!(silent.mappings().is_empty());
+    assert!(!silent.is_healthy());
+
+    let refusing = Client::new(refusing_ip)
+        .with_retry_policy(Rfc6886::with_attempts(1))
+        .diagnose()
+        .await;
+
+    assert!(matches!(refusing.port(), PortCheck::Refused));
+    assert!(!refusing.is_healthy());
+}
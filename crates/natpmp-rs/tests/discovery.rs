@@ -0,0 +1,96 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+mod common;
+
+use natpmp_rs::binding::Binding;
+use natpmp_rs::discovery::{
+    Candidates, discover_gateway_with, discovered_gateway, forget_discovered_gateway, race,
+};
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::map_udp_port;
+use natpmp_rs::retry::FixedInterval;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+use crate::common::{Received, gateway, next};
+
+#[tokio::test]
+async fn the_first_candidate_to_answer_wins_and_becomes_the_default() {
+    let silent = Ipv4Addr::new(127, 0, 0, 48);
+    let answering = Ipv4Addr::new(127, 0, 0, 49);
+
+    let _silent_socket = UdpSocket::bind((silent, 5351)).await.unwrap();
+    let mut requests = gateway(answering, vec![49000]).await;
+
+    let candidates = Candidates::new().with_addresses([silent, answering]);
+    let retry_policy = FixedInterval::new(Duration::from_millis(100), 3);
+
+    assert_eq!(
+        race(&candidates, retry_policy, &Binding::new())
+            .await
+            .unwrap(),
+        answering
+    );
+    assert_eq!(next(&mut requests).await.opcode, 0);
+
+    // a silent candidate alone loses
+    assert!(matches!(
+        race(
+            &Candidates::new().with_address(silent),
+            retry_policy,
+            &Binding::new()
+        )
+        .await,
+        Err(NATPMPError::TimedOut { .. })
+    ));
+
+    discover_gateway_with(
+        &candidates,
+        retry_policy,
+        &Binding::new(),
+        Duration::from_mins(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(next(&mut requests).await.opcode, 0);
+
+    assert_eq!(discovered_gateway(), Some(answering));
+
+    // without a gateway, the discovered one is used
+    let response = map_udp_port(
+        NonZeroU16::new(4900),
+        NonZeroU16::new(4900).unwrap(),
+        Some(60),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.gateway(), answering);
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 1,
+            internal_port: 4900,
+            external_port: 4900,
+            lifetime: 60
+        }
+    );
+
+    forget_discovered_gateway();
+
+    assert_eq!(discovered_gateway(), None);
+
+    // a TTL too long to represent never expires
+    discover_gateway_with(&candidates, retry_policy, &Binding::new(), Duration::MAX)
+        .await
+        .unwrap();
+
+    assert_eq!(discovered_gateway(), Some(answering));
+
+    forget_discovered_gateway();
+}
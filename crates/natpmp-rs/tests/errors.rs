@@ -0,0 +1,79 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+
+use bytes::BufMut as _;
+use natpmp_rs::errors::{NATPMPError, NATPMPResultError};
+use natpmp_rs::map_tcp_port;
+use natpmp_rs::protocol::{MappingProtocol, Opcode};
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn unknown_result_codes_are_kept() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 42);
+    let socket = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 12];
+        let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+        let mut response = vec![];
+        response.put_u8(0);
+        response.put_u8(128 + buffer[1]);
+        response.put_u16(99);
+        response.put_u32(1);
+        response.put_bytes(0, 8);
+
+        socket.send_to(&response, from).await.unwrap();
+    });
+
+    let error = map_tcp_port(
+        NonZeroU16::new(7000),
+        NonZeroU16::new(7000).unwrap(),
+        Some(60),
+        Some(gateway_ip),
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    let NATPMPError::Response {
+        context,
+        result: NATPMPResultError::Unknown(99),
+    } = error
+    else {
+        panic!("Unexpected error {error:?}");
+    };
+
+    assert_eq!(context.gateway(), gateway_ip);
+    assert_eq!(context.opcode(), Opcode::Mapping(MappingProtocol::TCP));
+    assert_eq!(context.attempt(), 1);
+    assert!(!error.is_retryable());
+    assert!(!error.is_permanent());
+}
+
+#[tokio::test]
+async fn silence_is_a_timeout() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 43);
+    // bound, so the requests don't bounce, but never answered
+    let _socket = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    let error = map_tcp_port(
+        NonZeroU16::new(7000),
+        NonZeroU16::new(7000).unwrap(),
+        Some(60),
+        Some(gateway_ip),
+        Some(2),
+    )
+    .await
+    .unwrap_err();
+
+    let NATPMPError::TimedOut { context } = error else {
+        panic!("Unexpected error {error:?}");
+    };
+
+    assert_eq!(context.attempt(), 2);
+    assert!(error.is_retryable());
+    assert!(!error.is_permanent());
+}
@@ -0,0 +1,91 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+mod common;
+
+use pretty_assertions::assert_eq;
+use tokio::process::Command;
+
+use crate::common::{gateway, next};
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("natpmp-rs-{}-{}", name, std::process::id()))
+}
+
+/// Runs `true` with a TCP mapping of port 8080 and `hook`, and waits for us to exit.
+async fn run_with_hook(gateway_ip: Ipv4Addr, hook: &str, hook_timeout: u64) {
+    let status = tokio::time::timeout(
+        Duration::from_secs(20),
+        Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+            .args(["run", "--gateway", &gateway_ip.to_string(), "--retry", "2"])
+            .args(["--hook", hook, "--hook-timeout", &hook_timeout.to_string()])
+            .args(["--tcp", "8080", "--", "true"])
+            .stdout(Stdio::null())
+            .status(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert!(status.success(), "run failed with {}", status);
+}
+
+fn is_running(pid: &str) -> bool {
+    // a zombie is as good as gone, it only waits to be reaped by whoever adopted it
+    std::fs::read_to_string(Path::new("/proc").join(pid).join("stat"))
+        .is_ok_and(|stat| !stat.contains(") Z "))
+}
+
+#[tokio::test]
+async fn runs_hooks_for_the_initial_address_and_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 86);
+    let mut requests = gateway(gateway_ip, vec![40080]).await;
+
+    let output = temp_file("hook-output");
+
+    run_with_hook(
+        gateway_ip,
+        &format!(
+            "echo $NATPMP_EVENT $NATPMP_NEW_PUBLIC_IP$NATPMP_NEW_EXTERNAL_PORT >> {}",
+            output.display()
+        ),
+        30,
+    )
+    .await;
+
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    assert_eq!(written, "public_ip 198.51.100.7\nexternal_port 40080\n");
+    assert_eq!(next(&mut requests).await.external_port, 8080);
+}
+
+#[tokio::test]
+async fn kills_hooks_that_time_out_with_everything_they_started() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 87);
+    let _requests = gateway(gateway_ip, vec![40080]).await;
+
+    let pids = temp_file("hook-pids");
+
+    run_with_hook(
+        gateway_ip,
+        &format!("sleep 60 & echo $! >> {}; wait", pids.display()),
+        1,
+    )
+    .await;
+
+    let written = std::fs::read_to_string(&pids).unwrap();
+    std::fs::remove_file(&pids).unwrap();
+
+    let sleeps = written.lines().collect::<Vec<_>>();
+
+    // one per change
+    assert_eq!(sleeps.len(), 2);
+
+    for sleep in sleeps {
+        assert!(!is_running(sleep), "sleep {} outlived its hook", sleep);
+    }
+}
@@ -0,0 +1,155 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+mod common;
+
+use bytes::BufMut as _;
+use natpmp_rs::client::Client;
+use natpmp_rs::protocol::MappingProtocol;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+use crate::common::{Received, gateway, next};
+
+#[tokio::test]
+async fn mapping_guard_renews_and_reports_port_changes() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 31);
+    let mut requests = gateway(gateway_ip, vec![40000, 40001]).await;
+
+    let port = NonZeroU16::new(4000).unwrap();
+
+    let guard = Client::new(gateway_ip)
+        .map(MappingProtocol::TCP, port, Some(port), Some(2))
+        .await
+        .unwrap();
+
+    let mut external_port = guard.external_port();
+
+    assert_eq!(*external_port.borrow_and_update(), NonZeroU16::new(40000));
+
+    // renewed after half of the lifetime, asking for the port we got
+    tokio::time::timeout(Duration::from_secs(5), external_port.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(*external_port.borrow(), NonZeroU16::new(40001));
+    assert_eq!(guard.response().external_port(), NonZeroU16::new(40001));
+
+    guard.release().await.unwrap();
+
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 2,
+            internal_port: 4000,
+            external_port: 4000,
+            lifetime: 2
+        }
+    );
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 2,
+            internal_port: 4000,
+            external_port: 40000,
+            lifetime: 2
+        }
+    );
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 2,
+            internal_port: 4000,
+            external_port: 0,
+            lifetime: 0
+        }
+    );
+}
+
+#[tokio::test]
+async fn dropping_mapping_guard_unmaps() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 32);
+    let mut requests = gateway(gateway_ip, vec![5000]).await;
+
+    let port = NonZeroU16::new(5000).unwrap();
+
+    let guard = Client::new(gateway_ip)
+        .map(MappingProtocol::UDP, port, None, None)
+        .await
+        .unwrap();
+
+    assert_eq!(next(&mut requests).await.lifetime, 7200);
+
+    drop(guard);
+
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 1,
+            internal_port: 5000,
+            external_port: 0,
+            lifetime: 0
+        }
+    );
+}
+
+#[tokio::test]
+async fn mapping_guard_backs_off_once_the_mapping_expired() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 100);
+    let socket = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+    let (sender, mut renewals) = tokio::sync::mpsc::unbounded_channel();
+
+    // grants the first mapping for 2 s, and fails every renewal with Network Failure, which is worth retrying
+    tokio::spawn(async move {
+        let mut buffer = [0; 12];
+
+        for answered in 0_u32.. {
+            let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+            let mut response = vec![];
+            response.put_u8(0);
+            response.put_u8(128 + buffer[1]);
+            response.put_u16(if answered == 0 { 0 } else { 3 });
+            response.put_u32(1);
+            response.put_slice(&buffer[4..8]);
+            response.put_u32(if answered == 0 { 2 } else { 0 });
+
+            socket.send_to(&response, from).await.unwrap();
+
+            if answered > 0 && sender.send(Instant::now()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let port = NonZeroU16::new(5100).unwrap();
+
+    let _guard = Client::new(gateway_ip)
+        .map(MappingProtocol::TCP, port, Some(port), Some(2))
+        .await
+        .unwrap();
+
+    let mut renewed_at = vec![];
+
+    while renewed_at.len() < 4 {
+        let at = tokio::time::timeout(Duration::from_secs(5), renewals.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        renewed_at.push(at);
+    }
+
+    // halfway to expiry, at expiry, 1 s after it, then 2 s later, the lifetime, rather than every second
+    let gaps = renewed_at
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .collect::<Vec<_>>();
+
+    assert!(gaps[1] < Duration::from_millis(1500), "{gaps:?}");
+    assert!(gaps[2] > Duration::from_millis(1500), "{gaps:?}");
+}
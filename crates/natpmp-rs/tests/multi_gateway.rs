@@ -0,0 +1,81 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+mod common;
+
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::multi_gateway::{GatewayMapping, MultiGatewayClient};
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::retry::FixedInterval;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+use crate::common::{PUBLIC_ADDRESS, Received, gateway, next};
+
+#[tokio::test]
+async fn maps_on_every_gateway_and_reports_per_gateway() {
+    let first = Ipv4Addr::new(127, 0, 0, 45);
+    let second = Ipv4Addr::new(127, 0, 0, 46);
+    let silent = Ipv4Addr::new(127, 0, 0, 47);
+
+    let mut first_requests = gateway(first, vec![45000]).await;
+    let _second_requests = gateway(second, vec![46000]).await;
+    let _silent_socket = UdpSocket::bind((silent, 5351)).await.unwrap();
+
+    let client = MultiGatewayClient::new(&[first, second, silent])
+        .with_retry_policy(FixedInterval::new(Duration::from_millis(100), 2));
+
+    let mapping = client
+        .map(
+            MappingProtocol::TCP,
+            NonZeroU16::new(4500).unwrap(),
+            None,
+            None,
+        )
+        .await;
+
+    // the gateway picks the external port
+    assert_eq!(
+        next(&mut first_requests).await,
+        Received {
+            opcode: 2,
+            internal_port: 4500,
+            external_port: 0,
+            lifetime: 7200
+        }
+    );
+
+    let gateways = mapping.gateways();
+
+    assert_eq!(
+        gateways
+            .iter()
+            .map(GatewayMapping::gateway)
+            .collect::<Vec<_>>(),
+        [first, second, silent]
+    );
+    assert_eq!(
+        mapping.public_addresses().collect::<Vec<_>>(),
+        [
+            SocketAddrV4::new(PUBLIC_ADDRESS, 45000),
+            SocketAddrV4::new(PUBLIC_ADDRESS, 46000)
+        ]
+    );
+    assert!(matches!(
+        gateways[2].guard(),
+        Err(&NATPMPError::TimedOut { .. })
+    ));
+    assert!(mapping.is_mapped_anywhere());
+
+    let released = mapping.release().await;
+
+    assert_eq!(
+        released
+            .iter()
+            .map(|&(gateway, _)| gateway)
+            .collect::<Vec<_>>(),
+        [first, second]
+    );
+}
@@ -0,0 +1,140 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::num::NonZeroU16;
+
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::DecodeError;
+use natpmp_rs::map_tcp_port;
+use natpmp_rs::packet::{decode, parse_hex, to_hex};
+use natpmp_rs::retry::Rfc6886;
+use natpmp_rs::simulator::Simulator;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+#[test]
+fn decodes_an_external_address_response() {
+    let packet = decode(&parse_hex("0080000000001234c0a80101").unwrap()).unwrap();
+
+    assert_eq!(packet.description(), "NAT-PMP external address response");
+    assert!(packet.is_response());
+    assert_eq!(packet.field("Result code"), Some("0 (Success)"));
+    assert_eq!(packet.field("Seconds since epoch"), Some("4660"));
+    assert_eq!(packet.field("External address"), Some("192.168.1.1"));
+    assert!(packet.trailing().is_empty());
+}
+
+#[test]
+fn decodes_a_mapping_request_pasted_from_wireshark() {
+    let packet = decode(&parse_hex("00:02:00:00:1f:90:00:00:00:00:00:00").unwrap()).unwrap();
+
+    assert_eq!(packet.description(), "NAT-PMP TCP mapping request");
+    assert_eq!(packet.field("Internal port"), Some("8080"));
+    assert_eq!(packet.field("Lifetime"), Some("0 s (delete)"));
+}
+
+#[test]
+fn decodes_an_error_response_without_a_body() {
+    let packet = decode(&parse_hex("0081 0002 0000 0010").unwrap()).unwrap();
+
+    assert_eq!(packet.description(), "NAT-PMP UDP mapping response");
+    assert_eq!(
+        packet.field("Result code"),
+        Some("2 (Not Authorized/Refused)")
+    );
+    assert_eq!(packet.field("External port"), None);
+}
+
+#[test]
+fn decodes_a_pcp_map_request() {
+    let mut datagram = parse_hex("0201000000001c20").unwrap();
+    datagram.extend(Ipv4Addr::new(192, 168, 1, 10).to_ipv6_mapped().octets());
+    datagram.extend([7; 12]);
+    datagram.extend(parse_hex("06000000 1f90 1f90").unwrap());
+    datagram.extend(Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+
+    let packet = decode(&datagram).unwrap();
+
+    assert_eq!(packet.description(), "PCP MAP request");
+    assert_eq!(packet.field("Client address"), Some("192.168.1.10"));
+    assert_eq!(packet.field("Protocol"), Some("6 (TCP)"));
+    assert_eq!(packet.field("External address"), Some("0.0.0.0"));
+}
+
+#[test]
+fn keeps_what_follows_the_layout() {
+    let packet = decode(&parse_hex("0000cafe").unwrap()).unwrap();
+
+    assert_eq!(packet.description(), "NAT-PMP external address request");
+    assert_eq!(to_hex(packet.trailing()), "cafe");
+}
+
+#[test]
+fn rejects_what_it_cannot_decode() {
+    assert_eq!(
+        decode(&parse_hex("0080000000001234").unwrap()),
+        Err(DecodeError::Truncated {
+            expected: 12,
+            length: 8
+        })
+    );
+    assert_eq!(decode(&[1, 0]), Err(DecodeError::UnknownVersion(1)));
+    assert!(matches!(parse_hex("008"), Err(DecodeError::InvalidHex(_))));
+    assert!(matches!(parse_hex("00zz"), Err(DecodeError::InvalidHex(_))));
+}
+
+#[tokio::test]
+async fn sends_a_raw_packet_and_decodes_the_reply() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 79);
+
+    let simulator = Simulator::bind(
+        SocketAddrV4::new(gateway_ip, 5351),
+        Ipv4Addr::new(203, 0, 113, 1),
+    )
+    .await
+    .unwrap();
+
+    tokio::spawn(simulator.run());
+
+    let client = Client::new(gateway_ip).with_retry_policy(Rfc6886::with_attempts(2));
+
+    // version 1 doesn't exist
+    let reply = client.send_raw(&[1, 0]).await.unwrap();
+    let packet = reply.decode().unwrap();
+
+    assert_eq!(reply.datagram().len(), 8);
+    assert_eq!(packet.field("Result code"), Some("1 (Unsupported Version)"));
+
+    let reply = client.send_raw(&[0, 0]).await.unwrap();
+
+    assert_eq!(
+        reply.decode().unwrap().field("External address"),
+        Some("203.0.113.1")
+    );
+}
+
+#[tokio::test]
+async fn sends_mapping_requests_in_network_byte_order() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 85);
+    let gateway = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    let request = tokio::spawn(async move {
+        let mut buffer = [0; 12];
+
+        let (size, _from) = gateway.recv_from(&mut buffer).await.unwrap();
+
+        to_hex(&buffer[..size])
+    });
+
+    // nobody answers
+    let _unanswered = map_tcp_port(
+        NonZeroU16::new(9000),
+        NonZeroU16::new(8080).unwrap(),
+        Some(300),
+        Some(gateway_ip),
+        Some(1),
+    )
+    .await;
+
+    // opcode 2 is TCP, then the ports and the lifetime, big-endian
+    assert_eq!(request.await.unwrap(), "000200001f9023280000012c");
+}
@@ -0,0 +1,97 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+
+mod common;
+
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::policy::{ExternalPortPolicy, map_port_with_policy};
+use natpmp_rs::protocol::MappingProtocol;
+use pretty_assertions::assert_eq;
+
+use crate::common::{gateway, next};
+
+fn port(port: u16) -> NonZeroU16 {
+    NonZeroU16::new(port).unwrap()
+}
+
+#[tokio::test]
+async fn any_policy_accepts_a_different_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 38);
+    let _requests = gateway(gateway_ip, vec![43000]).await;
+
+    let response = map_port_with_policy(
+        MappingProtocol::TCP,
+        port(8000),
+        Some(port(8000)),
+        &ExternalPortPolicy::Any,
+        None,
+        Some(gateway_ip),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.requested_external_port(), Some(port(8000)));
+    assert_eq!(response.external_port(), Some(port(43000)));
+    assert!(response.is_different_port());
+}
+
+#[tokio::test]
+async fn exact_policy_deletes_a_different_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 39);
+    let mut requests = gateway(gateway_ip, vec![43000]).await;
+
+    let result = map_port_with_policy(
+        MappingProtocol::TCP,
+        port(8000),
+        Some(port(8000)),
+        &ExternalPortPolicy::Exact,
+        None,
+        Some(gateway_ip),
+        None,
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(NATPMPError::ExternalPortMismatch {
+            requested: 8000,
+            granted: 43000
+        })
+    ));
+
+    assert_eq!(next(&mut requests).await.external_port, 8000);
+    assert_eq!(next(&mut requests).await.lifetime, 0);
+}
+
+#[tokio::test]
+async fn fallback_policy_tries_the_next_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 40);
+    let mut requests = gateway(gateway_ip, vec![43000, 8001]).await;
+
+    let response = map_port_with_policy(
+        MappingProtocol::UDP,
+        port(8000),
+        Some(port(8000)),
+        &ExternalPortPolicy::FallbackRange("8001-8010".parse().unwrap()),
+        None,
+        Some(gateway_ip),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.requested_external_port(), Some(port(8001)));
+    assert_eq!(response.external_port(), Some(port(8001)));
+
+    let mut received = vec![];
+
+    for _ in 0..3 {
+        let request = next(&mut requests).await;
+
+        received.push((request.external_port, request.lifetime));
+    }
+
+    assert_eq!(received, [(8000, 7200), (0, 0), (8001, 7200)]);
+}
@@ -0,0 +1,74 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::{NonZeroU16, NonZeroUsize};
+
+mod common;
+
+use natpmp_rs::port_range::{PortRange, PortRangeMapping};
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::responses::MappingResponse;
+use pretty_assertions::assert_eq;
+
+use crate::common::{gateway, next};
+
+#[test]
+fn parses_port_ranges() {
+    let range = "10000-10100".parse::<PortRange>().unwrap();
+
+    assert_eq!(range.first().get(), 10000);
+    assert_eq!(range.last().get(), 10100);
+    assert_eq!(range.len(), 101);
+    assert_eq!(range.to_string(), "10000-10100");
+
+    assert_eq!("5060".parse::<PortRange>().unwrap().len(), 1);
+    "10100-10000".parse::<PortRange>().unwrap_err();
+    "0-10".parse::<PortRange>().unwrap_err();
+}
+
+#[tokio::test]
+async fn maps_a_range_and_reports_differing_ports() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 37);
+    let mut requests = gateway(gateway_ip, vec![7000, 7001, 9999]).await;
+
+    let mapping = PortRangeMapping::map(
+        MappingProtocol::UDP,
+        "7000-7002".parse().unwrap(),
+        None,
+        Some(gateway_ip),
+        None,
+        NonZeroUsize::new(2).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        mapping
+            .mappings()
+            .iter()
+            .filter_map(MappingResponse::external_port)
+            .map(NonZeroU16::get)
+            .collect::<Vec<_>>(),
+        [7000, 7001, 9999]
+    );
+    assert_eq!(
+        mapping
+            .differing_ports()
+            .map(|mapping| mapping.internal_port().get())
+            .collect::<Vec<_>>(),
+        [7002]
+    );
+
+    mapping.unmap().await.unwrap();
+
+    let mut deleted = vec![];
+
+    for _ in 0..6 {
+        let received = next(&mut requests).await;
+
+        if received.lifetime == 0 {
+            deleted.push(received.internal_port);
+        }
+    }
+
+    assert_eq!(deleted, [7000, 7001, 7002]);
+}
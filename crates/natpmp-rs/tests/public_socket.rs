@@ -0,0 +1,75 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+mod common;
+
+use natpmp_rs::client::Client;
+use pretty_assertions::assert_eq;
+
+use crate::common::{PUBLIC_ADDRESS, Received, gateway, next};
+
+#[tokio::test]
+async fn bind_public_udp_maps_the_bound_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 33);
+    let mut requests = gateway(gateway_ip, vec![41000]).await;
+
+    let public_socket = Client::new(gateway_ip)
+        .bind_public_udp(None, Some(60))
+        .await
+        .unwrap();
+
+    let local_port = public_socket.socket().local_addr().unwrap().port();
+
+    assert_eq!(
+        public_socket.public_address(),
+        SocketAddrV4::new(PUBLIC_ADDRESS, 41000)
+    );
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 1,
+            internal_port: local_port,
+            external_port: local_port,
+            lifetime: 60
+        }
+    );
+    assert_eq!(next(&mut requests).await.opcode, 0);
+
+    let (_socket, _public_address, guard) = public_socket.into_parts();
+
+    guard.release().await.unwrap();
+
+    assert_eq!(next(&mut requests).await.lifetime, 0);
+}
+
+#[tokio::test]
+async fn bind_public_tcp_maps_the_given_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 34);
+    let mut requests = gateway(gateway_ip, vec![41001]).await;
+
+    let port = {
+        // find a free port
+        let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+
+    let public_socket = Client::new(gateway_ip)
+        .bind_public_tcp(port.try_into().ok(), None)
+        .await
+        .unwrap();
+
+    assert_eq!(public_socket.socket().local_addr().unwrap().port(), port);
+    assert_eq!(
+        public_socket.public_address(),
+        SocketAddrV4::new(PUBLIC_ADDRESS, 41001)
+    );
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 2,
+            internal_port: port,
+            external_port: port,
+            lifetime: 7200
+        }
+    );
+}
@@ -0,0 +1,138 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[expect(
+    dead_code,
+    reason = "Only the gateway reporting another public address is used"
+)]
+mod common;
+
+use bytes::BufMut as _;
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::pcp::PcpResultCode;
+use natpmp_rs::reachability::Reachability;
+use natpmp_rs::retry::Rfc6886;
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+use crate::common::gateway_reporting;
+
+#[test]
+fn classifies_external_addresses() {
+    for (address, expected) in [
+        (Ipv4Addr::new(203, 0, 113, 9), Reachability::Public),
+        (Ipv4Addr::new(100, 64, 0, 1), Reachability::CarrierGradeNat),
+        (
+            Ipv4Addr::new(100, 127, 255, 254),
+            Reachability::CarrierGradeNat,
+        ),
+        (Ipv4Addr::new(100, 128, 0, 1), Reachability::Public),
+        (Ipv4Addr::new(10, 1, 2, 3), Reachability::Private),
+        (Ipv4Addr::new(172, 16, 0, 1), Reachability::Private),
+        (Ipv4Addr::new(192, 168, 1, 1), Reachability::Private),
+        (Ipv4Addr::UNSPECIFIED, Reachability::Unroutable),
+        (Ipv4Addr::new(169, 254, 0, 1), Reachability::Unroutable),
+        (Ipv4Addr::new(240, 0, 0, 1), Reachability::Unroutable),
+    ] {
+        assert_eq!(Reachability::of(address), expected, "{address}");
+    }
+
+    assert!(Reachability::CarrierGradeNat.is_double_nat());
+    assert!(Reachability::Private.is_double_nat());
+    assert!(!Reachability::Unroutable.is_double_nat());
+}
+
+#[tokio::test]
+async fn asks_the_upstream_gateway_for_pcp() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 52);
+    let upstream_ip = Ipv4Addr::new(127, 0, 0, 53);
+    let external_address = Ipv4Addr::new(100, 64, 12, 34);
+
+    let _requests = gateway_reporting(gateway_ip, external_address, vec![]).await;
+
+    let upstream = UdpSocket::bind((upstream_ip, 5351)).await.unwrap();
+
+    let upstream = tokio::spawn(async move {
+        let mut buffer = [0; 24];
+        let (size, from) = upstream.recv_from(&mut buffer).await.unwrap();
+
+        let mut response = vec![];
+        response.put_u8(2);
+        response.put_u8(128);
+        response.put_u8(0);
+        response.put_u8(0);
+        response.put_u32(0);
+        response.put_u32(4242);
+        response.put_bytes(0, 12);
+
+        upstream.send_to(&response, from).await.unwrap();
+
+        (size, buffer)
+    });
+
+    let report = Client::new(gateway_ip)
+        .probe_reachability(Some(upstream_ip))
+        .await
+        .unwrap();
+
+    assert_eq!(report.external_address().ipv4_address(), external_address);
+    assert_eq!(report.reachability(), Reachability::CarrierGradeNat);
+
+    let probe = report.upstream().unwrap();
+    let response = probe.result().unwrap();
+
+    assert!(probe.speaks_pcp());
+    assert_eq!(response.result(), PcpResultCode::Success);
+    assert_eq!(response.gateway_epoch().as_secs(), 4242);
+
+    let (size, request) = upstream.await.unwrap();
+
+    // version 2, ANNOUNCE, with our gateway's external address as the client address
+    assert_eq!(size, 24);
+    assert_eq!(&request[..2], &[2, 0]);
+    assert_eq!(
+        Ipv6Addr::from(<[u8; 16]>::try_from(&request[8..]).unwrap()),
+        external_address.to_ipv6_mapped()
+    );
+}
+
+#[tokio::test]
+async fn natpmp_only_upstream_does_not_speak_pcp() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 54);
+    let upstream_ip = Ipv4Addr::new(127, 0, 0, 55);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::new(192, 168, 0, 20), vec![]).await;
+
+    let upstream = UdpSocket::bind((upstream_ip, 5351)).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 24];
+        let (_size, from) = upstream.recv_from(&mut buffer).await.unwrap();
+
+        // what a NAT-PMP gateway answers to a version it doesn't know
+        let mut response = vec![];
+        response.put_u8(0);
+        response.put_u8(128);
+        response.put_u16(1);
+        response.put_u32(1);
+
+        upstream.send_to(&response, from).await.unwrap();
+    });
+
+    let report = Client::new(gateway_ip)
+        .with_retry_policy(Rfc6886::with_attempts(2))
+        .probe_reachability(Some(upstream_ip))
+        .await
+        .unwrap();
+
+    assert_eq!(report.reachability(), Reachability::Private);
+
+    let probe = report.upstream().unwrap();
+
+    assert!(!probe.speaks_pcp());
+    assert!(matches!(
+        probe.result(),
+        Err(&NATPMPError::Unsupported { .. })
+    ));
+}
@@ -0,0 +1,98 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+mod common;
+
+use bytes::BufMut as _;
+use natpmp_rs::protocol::MappingProtocol;
+use natpmp_rs::{get_public_address, map_udp_port, unmap_all_ports};
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+use crate::common::{PUBLIC_ADDRESS, Received, gateway, next};
+
+#[tokio::test]
+async fn mapping_response_exposes_the_mapping() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 41);
+    let mut requests = gateway(gateway_ip, vec![44000]).await;
+
+    let response = map_udp_port(
+        NonZeroU16::new(9000),
+        NonZeroU16::new(9000).unwrap(),
+        Some(120),
+        Some(gateway_ip),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(next(&mut requests).await.lifetime, 120);
+
+    assert_eq!(response.gateway(), gateway_ip);
+    assert_eq!(response.internal_port().get(), 9000);
+    assert_eq!(response.external_port(), NonZeroU16::new(44000));
+    assert_eq!(response.requested_external_port(), NonZeroU16::new(9000));
+    assert_eq!(response.lifetime(), Duration::from_secs(120));
+    assert_eq!(
+        response.expires_at() - response.received_at(),
+        Duration::from_secs(120)
+    );
+    assert_eq!(response.gateway_epoch(), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn unmap_all_accepts_the_zero_internal_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 95);
+    let mut requests = gateway(gateway_ip, vec![44000]).await;
+
+    let response = unmap_all_ports(MappingProtocol::TCP, Some(gateway_ip), Some(2))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 2,
+            internal_port: 0,
+            external_port: 0,
+            lifetime: 0,
+        }
+    );
+
+    assert_eq!(response.gateway(), gateway_ip);
+    assert_eq!(response.protocol(), MappingProtocol::TCP);
+    assert_eq!(response.gateway_epoch(), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn a_response_from_another_address_leaves_room_for_the_next() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 98);
+    let impostor_ip = Ipv4Addr::new(127, 0, 0, 99);
+
+    let gateway = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+    let impostor = UdpSocket::bind((impostor_ip, 5351)).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 12];
+        let (_size, from) = gateway.recv_from(&mut buffer).await.unwrap();
+
+        let mut response = vec![];
+        response.put_u8(0);
+        response.put_u8(128);
+        response.put_u16(0);
+        response.put_u32(1);
+        response.put_u32(PUBLIC_ADDRESS.to_bits());
+
+        // discarded, after which the buffer must still hold a whole response
+        impostor.send_to(&response, from).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        gateway.send_to(&response, from).await.unwrap();
+    });
+
+    assert_eq!(
+        get_public_address(Some(gateway_ip), Some(2)).await.unwrap(),
+        PUBLIC_ADDRESS
+    );
+}
@@ -0,0 +1,86 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::NATPMPError;
+use natpmp_rs::retry::{ExponentialBackoff, FixedInterval, RetryPolicy as _, Rfc6886};
+use pretty_assertions::assert_eq;
+use tokio::net::UdpSocket;
+
+#[test]
+fn rfc6886_starts_at_250_ms_and_doubles() {
+    let policy = Rfc6886::default();
+
+    let timeouts = (1..=10)
+        .map_while(|attempt| policy.timeout(attempt))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        timeouts,
+        [250, 500, 1000, 2000, 4000, 8000, 16000, 32000, 64000].map(Duration::from_millis)
+    );
+}
+
+#[test]
+fn exponential_backoff_is_capped_and_jittered() {
+    let policy = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(1), 6)
+        .with_jitter(0.5);
+
+    for attempt in 1..=6 {
+        let ceiling =
+            (Duration::from_millis(100) * 2_u32.pow(attempt - 1)).min(Duration::from_secs(1));
+        let timeout = policy.timeout(attempt).unwrap();
+
+        assert!(timeout <= ceiling && timeout >= ceiling / 2, "{timeout:?}");
+    }
+
+    assert_eq!(policy.timeout(7), None);
+}
+
+#[test]
+fn there_is_no_attempt_zero() {
+    assert_eq!(Rfc6886::default().timeout(0), None);
+    assert_eq!(
+        ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(1), 6).timeout(0),
+        None
+    );
+    assert_eq!(
+        FixedInterval::new(Duration::from_millis(100), 3).timeout(0),
+        None
+    );
+}
+
+#[test]
+fn jitter_that_isnt_a_number_is_no_jitter() {
+    for jitter in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let policy = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(1), 6)
+            .with_jitter(jitter);
+
+        assert_eq!(policy.timeout(1), Some(Duration::from_millis(100)));
+    }
+}
+
+#[tokio::test]
+async fn deadline_cuts_the_schedule_short() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 44);
+    // bound, so the requests don't bounce, but never answered
+    let _socket = UdpSocket::bind((gateway_ip, 5351)).await.unwrap();
+
+    let client = Client::new(gateway_ip).with_retry_policy(
+        FixedInterval::new(Duration::from_millis(100), 100)
+            .with_deadline(Duration::from_millis(350)),
+    );
+
+    let started_at = Instant::now();
+
+    let error = client.get_public_address().await.unwrap_err();
+
+    assert!(started_at.elapsed() < Duration::from_secs(1));
+
+    let NATPMPError::TimedOut { context } = error else {
+        panic!("Unexpected error {error:?}");
+    };
+
+    assert_eq!(context.attempt(), 4);
+}
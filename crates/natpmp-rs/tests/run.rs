@@ -0,0 +1,182 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::os::unix::process::ExitStatusExt as _;
+use std::process::Stdio;
+use std::time::Duration;
+
+mod common;
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+use pretty_assertions::assert_eq;
+use tokio::io::AsyncReadExt as _;
+use tokio::process::Command;
+
+use crate::common::{Received, gateway, next};
+
+fn run(gateway_ip: Ipv4Addr, script: &str) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"));
+
+    command
+        .args(["run", "--gateway", &gateway_ip.to_string(), "--retry", "2"])
+        .args(["--tcp", "8080", "--", "sh", "-c", script])
+        // our logs go to stdout too
+        .env("RUST_LOG", "off")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    command
+}
+
+#[tokio::test]
+async fn runs_the_command_with_the_mapping_and_passes_its_exit_code_on() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 82);
+    let mut requests = gateway(gateway_ip, vec![40080]).await;
+
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        run(
+            gateway_ip,
+            "echo $NATPMP_PUBLIC_IP $NATPMP_TCP_8080; exit 3",
+        )
+        .output(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "198.51.100.7 40080\n"
+    );
+    assert_eq!(output.status.code(), Some(3));
+
+    assert_eq!(
+        next(&mut requests).await,
+        Received {
+            opcode: 2,
+            internal_port: 8080,
+            external_port: 8080,
+            lifetime: 7200,
+        }
+    );
+
+    // the public address, and then the mapping is deleted
+    assert_eq!(next(&mut requests).await.opcode, 0);
+
+    let unmapped = next(&mut requests).await;
+
+    assert_eq!(unmapped.internal_port, 8080);
+    assert_eq!(unmapped.lifetime, 0);
+}
+
+#[tokio::test]
+async fn dies_of_the_signal_that_killed_the_command() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 83);
+    let mut requests = gateway(gateway_ip, vec![40080]).await;
+
+    let status = tokio::time::timeout(
+        Duration::from_secs(10),
+        run(gateway_ip, "kill -TERM $$").status(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(status.code(), None);
+    assert_eq!(status.signal(), Some(15));
+
+    // the mapping is still deleted first
+    assert_eq!(next(&mut requests).await.lifetime, 7200);
+    assert_eq!(next(&mut requests).await.opcode, 0);
+    assert_eq!(next(&mut requests).await.lifetime, 0);
+}
+
+#[tokio::test]
+async fn forwards_signals_to_the_command() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 84);
+    let mut requests = gateway(gateway_ip, vec![40080]).await;
+
+    let mut child = run(
+        gateway_ip,
+        "trap 'echo interrupted; exit 7' INT; echo ready; while :; do sleep 0.05; done",
+    )
+    .spawn()
+    .unwrap();
+
+    let mut stdout = child.stdout.take().unwrap();
+
+    // wait for the trap to be in place
+    let mut ready = [0; 6];
+    stdout.read_exact(&mut ready).await.unwrap();
+
+    assert_eq!(&ready, b"ready\n");
+
+    let pid = Pid::from_raw(i32::try_from(child.id().unwrap()).unwrap());
+
+    kill(pid, Signal::SIGINT).unwrap();
+
+    let status = tokio::time::timeout(Duration::from_secs(10), child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).await.unwrap();
+
+    assert_eq!(rest, "interrupted\n");
+    assert_eq!(status.code(), Some(7));
+
+    assert_eq!(next(&mut requests).await.lifetime, 7200);
+}
+
+#[tokio::test]
+async fn falls_back_to_another_external_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 94);
+    let mut requests = gateway(gateway_ip, vec![43000, 8081]).await;
+
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+            .args(["run", "--gateway", &gateway_ip.to_string(), "--retry", "2"])
+            .args(["--tcp", "8080", "--port-policy", "fallback"])
+            .args(["--fallback-port", "8081", "--", "sh", "-c"])
+            .arg("echo $NATPMP_TCP_8080")
+            .env("RUST_LOG", "off")
+            .stderr(Stdio::null())
+            .output(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert!(output.status.success(), "run failed with {}", output.status);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "8081\n");
+
+    let mut received = vec![];
+
+    for _ in 0..3 {
+        let request = next(&mut requests).await;
+
+        received.push((request.external_port, request.lifetime));
+    }
+
+    // the port granted instead is deleted before asking for the fallback
+    assert_eq!(received, [(8080, 7200), (0, 0), (8081, 7200)]);
+}
+
+#[tokio::test]
+async fn requires_the_fallback_ports() {
+    let output = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+        .args(["run", "--gateway", "127.0.0.1", "--tcp", "8080"])
+        .args(["--port-policy", "fallback-range", "--", "true"])
+        .output()
+        .await
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("--fallback-range"),
+        "the error names the missing argument"
+    );
+}
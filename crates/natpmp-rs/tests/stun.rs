@@ -0,0 +1,142 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+#[expect(
+    dead_code,
+    reason = "Only the gateway reporting another public address is used"
+)]
+mod common;
+
+use bytes::{Buf as _, BufMut as _};
+use natpmp_rs::binding::Binding;
+use natpmp_rs::client::Client;
+use natpmp_rs::errors::StunError;
+use natpmp_rs::retry::Rfc6886;
+use natpmp_rs::stun::{StunVerdict, binding_request};
+use pretty_assertions::{assert_eq, assert_ne};
+use tokio::net::UdpSocket;
+
+use crate::common::gateway_reporting;
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+/// Answers one binding request with `reported`, or with the address the request came from.
+async fn spawn_stun_server(server: SocketAddrV4, reported: Option<SocketAddrV4>) {
+    let socket = UdpSocket::bind(server).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 20];
+        let (_size, from) = socket.recv_from(&mut buffer).await.unwrap();
+
+        let SocketAddr::V4(from) = from else {
+            panic!("Request from an IPv6 address");
+        };
+
+        let mut request = &buffer[..];
+        assert_eq!(request.get_u16(), 0x0001);
+        assert_eq!(request.get_u16(), 0);
+        assert_eq!(request.get_u32(), MAGIC_COOKIE);
+
+        let reported = reported.unwrap_or(from);
+
+        let mut response = vec![];
+        response.put_u16(0x0101);
+        response.put_u16(12);
+        response.put_u32(MAGIC_COOKIE);
+        response.put_slice(request);
+        // XOR-MAPPED-ADDRESS
+        response.put_u16(0x0020);
+        response.put_u16(8);
+        response.put_u8(0);
+        response.put_u8(0x01);
+        response.put_u16(reported.port() ^ 0x2112);
+        response.put_u32(u32::from(*reported.ip()) ^ MAGIC_COOKIE);
+
+        socket.send_to(&response, from).await.unwrap();
+    });
+}
+
+#[tokio::test]
+async fn binding_request_returns_the_reflexive_address() {
+    let server = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 56), 3478);
+    let source = Ipv4Addr::new(127, 0, 0, 57);
+
+    spawn_stun_server(server, None).await;
+
+    let reflexive_address = binding_request(
+        server,
+        &Rfc6886::with_attempts(2),
+        &Binding::new().with_source(source),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(*reflexive_address.ip(), source);
+    assert_ne!(reflexive_address.port(), 0);
+}
+
+#[tokio::test]
+async fn silent_stun_server_times_out() {
+    let server = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 58), 3478);
+    let _socket = UdpSocket::bind(server).await.unwrap();
+
+    let error = binding_request(server, &Rfc6886::with_attempts(2), &Binding::new())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, StunError::TimedOut { attempts: 2, .. }));
+}
+
+#[tokio::test]
+async fn cross_check_reports_a_wrong_external_address() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 59);
+    let server = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 60), 3478);
+    let seen = SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 50), 40000);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::new(203, 0, 113, 9), vec![]).await;
+    spawn_stun_server(server, Some(seen)).await;
+
+    let check = Client::new(gateway_ip)
+        .cross_check_stun(server)
+        .await
+        .unwrap();
+
+    assert_eq!(check.reflexive_address(), seen);
+    assert_eq!(check.verdict(), StunVerdict::Mismatch);
+    assert!(check.is_mismatch());
+}
+
+#[tokio::test]
+async fn cross_check_reports_double_nat() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 61);
+    let server = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 62), 3478);
+    let seen = SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 50), 40000);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::new(192, 168, 1, 20), vec![]).await;
+    spawn_stun_server(server, Some(seen)).await;
+
+    let check = Client::new(gateway_ip)
+        .cross_check_stun(server)
+        .await
+        .unwrap();
+
+    assert_eq!(check.verdict(), StunVerdict::DoubleNat);
+}
+
+#[tokio::test]
+async fn cross_check_matches() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 63);
+    let server = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 64), 3478);
+    let seen = SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 9), 40000);
+
+    let _requests = gateway_reporting(gateway_ip, *seen.ip(), vec![]).await;
+    spawn_stun_server(server, Some(seen)).await;
+
+    let check = Client::new(gateway_ip)
+        .cross_check_stun(server)
+        .await
+        .unwrap();
+
+    assert_eq!(check.verdict(), StunVerdict::Match);
+    assert!(!check.is_mismatch());
+}
@@ -0,0 +1,252 @@
+#![cfg(feature = "torrent-clients")]
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::Stdio;
+use std::time::Duration;
+
+mod common;
+
+use natpmp_rs::errors::IntegrationError;
+use natpmp_rs::integrations::qbittorrent::QBittorrent;
+use natpmp_rs::integrations::transmission::Transmission;
+use pretty_assertions::assert_eq;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+struct Received {
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Received {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+struct Reply {
+    status: &'static str,
+    headers: Vec<(&'static str, &'static str)>,
+    body: &'static str,
+}
+
+/// A stand-in HTTP server that answers one request per connection with the given replies, in order,
+/// and hands back what it received.
+async fn serve(replies: Vec<Reply>) -> (SocketAddr, JoinHandle<Vec<Received>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let handle = tokio::task::spawn(async move {
+        let mut received = vec![];
+
+        for reply in replies {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut buffer = vec![];
+
+            let header_end = loop {
+                let mut chunk = [0; 1024];
+                let size = stream.read(&mut chunk).await.unwrap();
+                buffer.extend_from_slice(&chunk[..size]);
+
+                if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                    break position + 4;
+                }
+            };
+
+            let head = String::from_utf8(buffer[..header_end].to_vec()).unwrap();
+            let mut lines = head.lines();
+            let path = lines.next().unwrap().split(' ').nth(1).unwrap().to_owned();
+            let headers = lines
+                .filter_map(|line| line.split_once(": "))
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect::<Vec<_>>();
+
+            let content_length = headers
+                .iter()
+                .find(|&&(ref key, _)| key.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |&(_, ref value)| value.parse::<usize>().unwrap());
+
+            while buffer.len() < header_end + content_length {
+                let mut chunk = [0; 1024];
+                let size = stream.read(&mut chunk).await.unwrap();
+                buffer.extend_from_slice(&chunk[..size]);
+            }
+
+            received.push(Received {
+                path,
+                headers,
+                body: String::from_utf8(buffer[header_end..].to_vec()).unwrap(),
+            });
+
+            let mut response = format!(
+                "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+                reply.status,
+                reply.body.len()
+            );
+
+            for (key, value) in reply.headers {
+                write!(response, "{}: {}\r\n", key, value).unwrap();
+            }
+
+            write!(response, "\r\n{}", reply.body).unwrap();
+
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+
+        received
+    });
+
+    (address, handle)
+}
+
+#[tokio::test]
+async fn qbittorrent_logs_in_and_sets_listen_port() {
+    let (address, handle) = serve(vec![
+        Reply {
+            status: "200 OK",
+            headers: vec![("Set-Cookie", "SID=session; HttpOnly; path=/")],
+            body: "Ok.",
+        },
+        Reply {
+            status: "200 OK",
+            headers: vec![],
+            body: "",
+        },
+    ])
+    .await;
+
+    let base_url = format!("http://{}", address);
+
+    let qbittorrent = QBittorrent::new(
+        &base_url,
+        Some((String::from("admin"), String::from("secret"))),
+    )
+    .unwrap();
+
+    qbittorrent.set_listen_port(51_413).await.unwrap();
+
+    let received = handle.await.unwrap();
+
+    assert_eq!(received[0].path, "/api/v2/auth/login");
+    assert_eq!(received[0].body, "username=admin&password=secret");
+    assert_eq!(received[0].header("referer"), Some(base_url.as_str()));
+
+    assert_eq!(received[1].path, "/api/v2/app/setPreferences");
+    assert_eq!(received[1].body, "json=%7B%22listen_port%22%3A51413%7D",);
+    assert_eq!(received[1].header("cookie"), Some("SID=session"));
+}
+
+#[tokio::test]
+async fn qbittorrent_rejected_login() {
+    let (address, handle) = serve(vec![Reply {
+        status: "200 OK",
+        headers: vec![],
+        body: "Fails.",
+    }])
+    .await;
+
+    let qbittorrent = QBittorrent::new(
+        &format!("http://{}", address),
+        Some((String::from("admin"), String::from("wrong"))),
+    )
+    .unwrap();
+
+    let result = qbittorrent.set_listen_port(51_413).await;
+
+    assert!(
+        matches!(result, Err(IntegrationError::Authentication)),
+        "Login should have been rejected"
+    );
+
+    assert_eq!(handle.await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn transmission_fetches_session_id_and_sets_peer_port() {
+    let (address, handle) = serve(vec![
+        Reply {
+            status: "409 Conflict",
+            headers: vec![("X-Transmission-Session-Id", "session")],
+            body: "",
+        },
+        Reply {
+            status: "200 OK",
+            headers: vec![("Content-Type", "application/json")],
+            body: r#"{"arguments":{},"result":"success"}"#,
+        },
+    ])
+    .await;
+
+    let mut transmission = Transmission::new(
+        &format!("http://{}/transmission/rpc", address),
+        Some((String::from("admin"), String::from("secret"))),
+    )
+    .unwrap();
+
+    transmission.set_peer_port(51_413).await.unwrap();
+
+    let received = handle.await.unwrap();
+
+    assert_eq!(received[0].header("x-transmission-session-id"), None);
+
+    assert_eq!(received[1].path, "/transmission/rpc");
+    assert_eq!(
+        received[1].header("x-transmission-session-id"),
+        Some("session")
+    );
+    assert_eq!(
+        received[1].header("authorization"),
+        Some("Basic YWRtaW46c2VjcmV0")
+    );
+    assert_eq!(
+        received[1].body,
+        r#"{"arguments":{"peer-port":51413},"method":"session-set"}"#
+    );
+}
+
+#[tokio::test]
+async fn only_the_followed_mapping_sets_the_port() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 90);
+    let mut requests = common::gateway(gateway_ip, vec![40080, 40090]).await;
+
+    let (address, handle) = serve(vec![Reply {
+        status: "200 OK",
+        headers: vec![],
+        body: "",
+    }])
+    .await;
+
+    let status = tokio::time::timeout(
+        Duration::from_secs(10),
+        Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+            .args(["run", "--gateway", &gateway_ip.to_string(), "--retry", "2"])
+            // in order, so that 9090 gets the second port
+            .args(["--concurrency", "1", "--tcp", "8080", "--tcp", "9090"])
+            .args(["--qbittorrent-url", &format!("http://{}", address)])
+            .args(["--torrent-port", "9090", "--", "true"])
+            .stdout(Stdio::null())
+            .status(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert!(status.success(), "run failed with {}", status);
+
+    assert_eq!(common::next(&mut requests).await.internal_port, 8080);
+    assert_eq!(common::next(&mut requests).await.internal_port, 9090);
+
+    let received = handle.await.unwrap();
+
+    assert_eq!(received[0].path, "/api/v2/app/setPreferences");
+    assert_eq!(received[0].body, "json=%7B%22listen_port%22%3A40090%7D");
+}
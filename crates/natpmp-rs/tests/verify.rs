@@ -0,0 +1,112 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+#[expect(
+    dead_code,
+    reason = "Only the gateway reporting another public address is used"
+)]
+mod common;
+
+use natpmp_rs::client::Client;
+use natpmp_rs::verify::{Verifier, VerifyOutcome, serve_reflector, verify_tcp, verify_udp};
+use pretty_assertions::assert_eq;
+use tokio::net::TcpListener;
+
+use crate::common::gateway_reporting;
+
+/// A port nothing listens on
+const CLOSED_PORT: u16 = 9;
+
+const TIMEOUT: Duration = Duration::from_millis(800);
+
+async fn spawn_reflector(address: Ipv4Addr) -> SocketAddr {
+    let listener = TcpListener::bind((address, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(serve_reflector(listener));
+
+    address
+}
+
+#[tokio::test]
+async fn hairpin_reaches_the_mapped_sockets() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 65);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::LOCALHOST, vec![]).await;
+
+    let client = Client::new(gateway_ip);
+
+    let tcp = client.bind_public_tcp(None, Some(60)).await.unwrap();
+    let udp = client.bind_public_udp(None, Some(60)).await.unwrap();
+
+    assert_eq!(
+        verify_tcp(&tcp, Verifier::Hairpin, TIMEOUT).await.unwrap(),
+        VerifyOutcome::Reachable
+    );
+    assert_eq!(
+        verify_udp(&udp, Verifier::Hairpin, TIMEOUT).await.unwrap(),
+        VerifyOutcome::Reachable
+    );
+}
+
+#[tokio::test]
+async fn hairpin_to_the_wrong_port_is_inconclusive() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 66);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::LOCALHOST, vec![CLOSED_PORT]).await;
+
+    let client = Client::new(gateway_ip);
+
+    let tcp = client.bind_public_tcp(None, Some(60)).await.unwrap();
+
+    assert_eq!(
+        verify_tcp(&tcp, Verifier::Hairpin, TIMEOUT).await.unwrap(),
+        VerifyOutcome::HairpinUnsupported
+    );
+}
+
+#[tokio::test]
+async fn reflector_reaches_the_mapped_sockets() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 67);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::LOCALHOST, vec![]).await;
+
+    let reflector = spawn_reflector(Ipv4Addr::new(127, 0, 0, 68)).await;
+    let client = Client::new(gateway_ip);
+
+    let tcp = client.bind_public_tcp(None, Some(60)).await.unwrap();
+    let udp = client.bind_public_udp(None, Some(60)).await.unwrap();
+
+    assert_eq!(
+        verify_tcp(&tcp, Verifier::Reflector(reflector), TIMEOUT)
+            .await
+            .unwrap(),
+        VerifyOutcome::Reachable
+    );
+    assert_eq!(
+        verify_udp(&udp, Verifier::Reflector(reflector), TIMEOUT)
+            .await
+            .unwrap(),
+        VerifyOutcome::Reachable
+    );
+}
+
+#[tokio::test]
+async fn reflector_finds_mappings_that_do_not_forward() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 69);
+
+    let _requests = gateway_reporting(gateway_ip, Ipv4Addr::LOCALHOST, vec![CLOSED_PORT]).await;
+
+    let reflector = spawn_reflector(Ipv4Addr::new(127, 0, 0, 70)).await;
+    let client = Client::new(gateway_ip);
+
+    let udp = client.bind_public_udp(None, Some(60)).await.unwrap();
+
+    assert_eq!(
+        verify_udp(&udp, Verifier::Reflector(reflector), TIMEOUT)
+            .await
+            .unwrap(),
+        VerifyOutcome::Unreachable
+    );
+}
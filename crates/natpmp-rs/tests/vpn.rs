@@ -0,0 +1,161 @@
+#![expect(clippy::tests_outside_test_module, reason = "Integration tests")]
+use std::net::Ipv4Addr;
+use std::process::Stdio;
+use std::time::Duration;
+
+mod common;
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+use pretty_assertions::assert_eq;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+use crate::common::{Received, gateway, next};
+
+#[tokio::test]
+async fn writes_the_forwarded_port_and_removes_it_when_stopped() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 89);
+    let mut requests = gateway(gateway_ip, vec![40089]).await;
+
+    let port_file = std::env::temp_dir().join(format!("natpmp-rs-vpn-port-{}", std::process::id()));
+
+    let mut vpn = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+        .args(["vpn", "--gateway", &gateway_ip.to_string(), "--retry", "2"])
+        .arg("--port-file")
+        .arg(&port_file)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    for opcode in [1, 2] {
+        assert_eq!(
+            next(&mut requests).await,
+            Received {
+                opcode,
+                internal_port: 1,
+                external_port: 1,
+                lifetime: 60,
+            }
+        );
+    }
+
+    // written right after the requests
+    let written = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(written) = tokio::fs::read_to_string(&port_file).await {
+                break written;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(written, "40089\n");
+
+    let pid = Pid::from_raw(i32::try_from(vpn.id().unwrap()).unwrap());
+
+    kill(pid, Signal::SIGTERM).unwrap();
+
+    let status = tokio::time::timeout(Duration::from_secs(10), vpn.wait())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(status.success(), "vpn failed with {}", status);
+    assert!(!port_file.exists(), "the port file outlived the mapping");
+
+    for opcode in [1, 2] {
+        let unmapped = next(&mut requests).await;
+
+        assert_eq!((unmapped.opcode, unmapped.lifetime), (opcode, 0));
+    }
+}
+
+#[tokio::test]
+async fn rejects_a_zero_renew_interval() {
+    let output = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+        .args(["vpn", "--gateway", "127.0.0.1", "--renew-interval", "0"])
+        .output()
+        .await
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("--renew-interval"),
+        "the error names the argument"
+    );
+}
+
+#[tokio::test]
+async fn rejects_a_renew_interval_no_shorter_than_the_lifetime() {
+    let output = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+        .args(["vpn", "--gateway", "127.0.0.1"])
+        .args(["--lifetime", "60", "--renew-interval", "60"])
+        .output()
+        .await
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("--renew-interval"),
+        "the error names the argument"
+    );
+}
+
+#[tokio::test]
+async fn exports_mapping_metrics() {
+    let gateway_ip = Ipv4Addr::new(127, 0, 0, 91);
+    let mut requests = gateway(gateway_ip, vec![40091]).await;
+
+    // a free port, for the metrics
+    let metrics_listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut vpn = Command::new(env!("CARGO_BIN_EXE_natpmp-rs"))
+        .args(["vpn", "--gateway", &gateway_ip.to_string(), "--retry", "2"])
+        .args(["--metrics-listen", &metrics_listen.to_string()])
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    next(&mut requests).await;
+    next(&mut requests).await;
+
+    let metrics = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(mut stream) = TcpStream::connect(metrics_listen).await {
+                stream
+                    .write_all(
+                        b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+
+                if response.contains("natpmp_active_mappings 2") {
+                    break response;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the metrics show both mappings");
+
+    assert!(
+        metrics.contains("natpmp_next_expiry_seconds"),
+        "the expiry is exported too"
+    );
+
+    vpn.kill().await.unwrap();
+}